@@ -0,0 +1,399 @@
+// src/config.rs
+use actix_cors::Cors;
+use actix_web::http::Method;
+use chrono::FixedOffset;
+use std::env;
+
+use crate::models::order::GeoPoint;
+
+// Read a comma-separated list from the environment, falling back to a default
+fn env_list(key: &str, default: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn env_bool(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(value) => matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        Err(_) => default,
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// CORS settings, read from `CORS_*` environment variables.
+///
+/// Origins may be exact (`https://yettapastries.co.ke`), a wildcard subdomain
+/// (`https://*.yettapastries.co.ke`) or `*` to allow any origin. `*` can't be
+/// combined with credentials, since any site could then make signed-in requests.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age: usize,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Self {
+        let allowed_methods = env_list("CORS_ALLOWED_METHODS", "GET,POST,PUT,PATCH,DELETE")
+            .iter()
+            .filter_map(|m| match Method::from_bytes(m.to_ascii_uppercase().as_bytes()) {
+                Ok(method) => Some(method),
+                Err(_) => {
+                    tracing::warn!(method = %m, "Ignoring invalid CORS method");
+                    None
+                }
+            })
+            .collect();
+
+        let config = CorsConfig {
+            allowed_origins: env_list("CORS_ALLOWED_ORIGINS", "http://localhost:5173"),
+            allowed_methods,
+            allowed_headers: env_list("CORS_ALLOWED_HEADERS", "Content-Type,Authorization,Idempotency-Key"),
+            allow_credentials: env_bool("CORS_ALLOW_CREDENTIALS", false),
+            max_age: env_parse("CORS_MAX_AGE", 3600),
+        };
+        assert!(
+            !(config.allow_credentials && config.allowed_origins.iter().any(|o| o == "*")),
+            "CORS_ALLOWED_ORIGINS=* can't be used with CORS_ALLOW_CREDENTIALS=true; list the allowed origins instead"
+        );
+        config
+    }
+
+    /// Build the actix CORS middleware for this configuration
+    pub fn to_cors(&self) -> Cors {
+        let origins = self.allowed_origins.clone();
+
+        let mut cors = Cors::default()
+            .allowed_origin_fn(move |origin, _req_head| {
+                origin
+                    .to_str()
+                    .map(|origin| origins.iter().any(|pattern| origin_matches(pattern, origin)))
+                    .unwrap_or(false)
+            })
+            .allowed_methods(self.allowed_methods.clone())
+            .allowed_headers(self.allowed_headers.iter().map(String::as_str))
+            .max_age(self.max_age);
+
+        if self.allow_credentials {
+            cors = cors.supports_credentials();
+        }
+
+        cors
+    }
+}
+
+// Check an Origin header against a configured pattern, ignoring case.
+// "https://*.example.com" matches "https://shop.example.com" but not "https://example.com".
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    let pattern = pattern.to_ascii_lowercase();
+    let origin = origin.to_ascii_lowercase();
+    match pattern.split_once("*.") {
+        Some((scheme, domain)) => origin
+            .strip_prefix(scheme)
+            .and_then(|host| host.strip_suffix(domain))
+            .map(|sub| sub.len() > 1 && sub.ends_with('.') && !sub[..sub.len() - 1].contains('/'))
+            .unwrap_or(false),
+        None => pattern == origin,
+    }
+}
+
+/// Base URL of the React app, used to build links sent by email
+pub fn frontend_url() -> String {
+    env::var("FRONTEND_URL")
+        .unwrap_or_else(|_| "http://localhost:5173".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Existing accounts promoted to admin at startup (`ADMIN_EMAILS`),
+/// so the first admin can be bootstrapped
+pub fn admin_emails() -> Vec<String> {
+    env_list("ADMIN_EMAILS", "")
+}
+
+/// Bearer token Prometheus must send to scrape /metrics (`METRICS_TOKEN`).
+/// None leaves /metrics switched off.
+pub fn metrics_token() -> Option<String> {
+    env::var("METRICS_TOKEN").ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// A fixed-window limit: at most `max` hits per `window_secs`
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitRule {
+    pub max: u64,
+    pub window_secs: i64,
+}
+
+impl RateLimitRule {
+    fn from_env(prefix: &str, max: u64, window_secs: i64) -> Self {
+        RateLimitRule {
+            max: env_parse(&format!("{}_MAX", prefix), max),
+            window_secs: env_parse(&format!("{}_WINDOW_SECS", prefix), window_secs).max(1),
+        }
+    }
+}
+
+/// Brute-force and abuse protection for auth and payment endpoints, read from
+/// `RATE_LIMIT_*` and `LOCKOUT_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Requests per client IP across /api/auth
+    pub per_ip: RateLimitRule,
+    /// Attempts per email address on login and forgot-password
+    pub per_account: RateLimitRule,
+    /// STK push prompts per phone number
+    pub stk_push_per_phone: RateLimitRule,
+    /// STK push prompts per signed-in user
+    pub stk_push_per_user: RateLimitRule,
+    /// Use X-Forwarded-For / Forwarded for the client IP (only behind a trusted proxy)
+    pub trust_proxy_headers: bool,
+    /// Consecutive failed logins before the account is locked
+    pub lockout_threshold: u32,
+    /// First lockout duration; doubles with every further failure
+    pub lockout_base_secs: i64,
+    pub lockout_max_secs: i64,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        RateLimitConfig {
+            per_ip: RateLimitRule::from_env("RATE_LIMIT_IP", 30, 60),
+            per_account: RateLimitRule::from_env("RATE_LIMIT_ACCOUNT", 10, 900),
+            stk_push_per_phone: RateLimitRule::from_env("RATE_LIMIT_STK_PHONE", 3, 600),
+            stk_push_per_user: RateLimitRule::from_env("RATE_LIMIT_STK_USER", 5, 600),
+            trust_proxy_headers: env_bool("TRUST_PROXY_HEADERS", false),
+            lockout_threshold: env_parse("LOCKOUT_THRESHOLD", 5).max(1),
+            lockout_base_secs: env_parse("LOCKOUT_BASE_SECS", 60),
+            lockout_max_secs: env_parse("LOCKOUT_MAX_SECS", 86_400),
+        }
+    }
+
+    /// Lockout duration after `failures` consecutive failed logins (progressive)
+    pub fn lockout_secs(&self, failures: u32) -> i64 {
+        let doublings = failures.saturating_sub(self.lockout_threshold).min(20);
+        self.lockout_base_secs
+            .saturating_mul(1_i64 << doublings)
+            .min(self.lockout_max_secs)
+    }
+}
+
+/// How long a checkout Idempotency-Key and its stored response are kept
+/// (`IDEMPOTENCY_WINDOW_SECS`, default 24 hours)
+#[derive(Debug, Clone, Copy)]
+pub struct IdempotencyConfig {
+    pub window_secs: i64,
+    /// How long a request that hasn't finished keeps its key, so a crash mid-request
+    /// doesn't block retries for the whole window (`IDEMPOTENCY_LEASE_SECS`, default 60)
+    pub lease_secs: i64,
+}
+
+impl IdempotencyConfig {
+    pub fn from_env() -> Self {
+        IdempotencyConfig {
+            window_secs: env_parse("IDEMPOTENCY_WINDOW_SECS", 86_400).max(1),
+            lease_secs: env_parse("IDEMPOTENCY_LEASE_SECS", 60).max(1),
+        }
+    }
+}
+
+/// Pickup branches and delivery fee rules, read from `PICKUP_*` and `DELIVERY_*`
+/// environment variables.
+///
+/// A delivery to a configured zone costs that zone's flat fee. Otherwise the fee is
+/// `DELIVERY_BASE_FEE + DELIVERY_FEE_PER_KM` times the straight-line distance from
+/// `DELIVERY_ORIGIN`, up to `DELIVERY_MAX_KM`.
+#[derive(Debug, Clone)]
+pub struct FulfilmentConfig {
+    pub pickup_branches: Vec<String>,
+    /// (zone name, flat fee), from `DELIVERY_ZONES=Westlands:200,Kilimani:250`
+    pub delivery_zones: Vec<(String, f64)>,
+    /// Bakery location, from `DELIVERY_ORIGIN=-1.2921,36.8219` (lat,lng)
+    pub delivery_origin: Option<GeoPoint>,
+    pub delivery_base_fee: f64,
+    pub delivery_fee_per_km: f64,
+    pub delivery_max_km: f64,
+}
+
+impl FulfilmentConfig {
+    pub fn from_env() -> Self {
+        let delivery_zones = env_list("DELIVERY_ZONES", "")
+            .iter()
+            .filter_map(|zone| {
+                let parsed = zone.rsplit_once(':')
+                    .and_then(|(name, fee)| Some((name.trim().to_string(), fee.trim().parse().ok()?)));
+                if parsed.is_none() {
+                    tracing::warn!(zone = %zone, "Ignoring invalid delivery zone; expected name:fee");
+                }
+                parsed
+            })
+            .collect();
+
+        let delivery_origin = env::var("DELIVERY_ORIGIN").ok().and_then(|origin| {
+            let parsed = origin.split_once(',')
+                .and_then(|(lat, lng)| Some(GeoPoint { lat: lat.trim().parse().ok()?, lng: lng.trim().parse().ok()? }));
+            if parsed.is_none() {
+                tracing::warn!(origin = %origin, "Ignoring invalid DELIVERY_ORIGIN; expected lat,lng");
+            }
+            parsed
+        });
+
+        FulfilmentConfig {
+            pickup_branches: env_list("PICKUP_BRANCHES", "Main"),
+            delivery_zones,
+            delivery_origin,
+            delivery_base_fee: env_parse("DELIVERY_BASE_FEE", 150.0),
+            delivery_fee_per_km: env_parse("DELIVERY_FEE_PER_KM", 40.0),
+            delivery_max_km: env_parse("DELIVERY_MAX_KM", 20.0),
+        }
+    }
+
+    /// The configured branch matching `name`, ignoring case
+    pub fn find_branch(&self, name: &str) -> Option<&str> {
+        self.pickup_branches.iter().find(|b| b.eq_ignore_ascii_case(name.trim())).map(String::as_str)
+    }
+
+    /// The configured zone matching `name` and its fee, ignoring case
+    pub fn find_zone(&self, name: &str) -> Option<(&str, f64)> {
+        self.delivery_zones.iter()
+            .find(|(zone, _)| zone.eq_ignore_ascii_case(name.trim()))
+            .map(|(zone, fee)| (zone.as_str(), *fee))
+    }
+
+    /// Distance-based fee in whole shillings for a drop-off `distance_km` away,
+    /// or None if it's beyond the delivery radius
+    pub fn distance_fee(&self, distance_km: f64) -> Option<f64> {
+        if distance_km > self.delivery_max_km {
+            return None;
+        }
+        Some((self.delivery_base_fee + self.delivery_fee_per_km * distance_km).ceil())
+    }
+}
+
+/// Time slot settings, read from `BAKERY_UTC_OFFSET` and `SLOT_*` environment variables
+#[derive(Debug, Clone, Copy)]
+pub struct SlotConfig {
+    /// Slot windows are in bakery local time (`BAKERY_UTC_OFFSET=+03:00`)
+    pub utc_offset: FixedOffset,
+    /// How far ahead customers can list slots
+    pub max_days: u32,
+}
+
+impl SlotConfig {
+    pub fn from_env() -> Self {
+        let utc_offset = env::var("BAKERY_UTC_OFFSET").ok()
+            .and_then(|offset| match offset.trim().parse() {
+                Ok(offset) => Some(offset),
+                Err(_) => {
+                    tracing::warn!(offset = %offset, "Ignoring invalid BAKERY_UTC_OFFSET; expected e.g. +03:00");
+                    None
+                }
+            })
+            .unwrap_or_else(|| FixedOffset::east_opt(3 * 3600).unwrap());
+
+        SlotConfig {
+            utc_offset,
+            max_days: env_parse("SLOT_MAX_DAYS", 14).max(1),
+        }
+    }
+}
+
+/// Uploaded photos (cake references and product images), read from `UPLOAD_*` environment variables
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    /// Local directory used by the disk storage backend
+    pub dir: String,
+    pub max_bytes: usize,
+    /// Limit for admin product photos, which are resized on upload
+    pub image_max_bytes: usize,
+}
+
+impl UploadConfig {
+    pub fn from_env() -> Self {
+        UploadConfig {
+            dir: env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string()),
+            max_bytes: env_parse("UPLOAD_MAX_BYTES", 5 * 1024 * 1024),
+            image_max_bytes: env_parse("UPLOAD_IMAGE_MAX_BYTES", 10 * 1024 * 1024),
+        }
+    }
+}
+
+/// Loyalty points, read from `LOYALTY_*` environment variables
+#[derive(Debug, Clone, Copy)]
+pub struct LoyaltyConfig {
+    /// Amount spent per point earned, e.g. 100 means 1 point per KES 100
+    pub spend_per_point: f64,
+    /// What one point takes off an order
+    pub point_value: f64,
+    /// Points expire this long after they were earned
+    pub expiry_days: i64,
+}
+
+impl LoyaltyConfig {
+    pub fn from_env() -> Self {
+        LoyaltyConfig {
+            spend_per_point: env_parse("LOYALTY_SPEND_PER_POINT", 100.0_f64).max(1.0),
+            point_value: env_parse("LOYALTY_POINT_VALUE", 1.0_f64).max(0.0),
+            expiry_days: env_parse("LOYALTY_EXPIRY_DAYS", 365_i64).max(1),
+        }
+    }
+
+    /// Points earned by paying `total`
+    pub fn points_for(&self, total: f64) -> i64 {
+        (total / self.spend_per_point).floor().max(0.0) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_matches_exact_and_wildcard_patterns() {
+        assert!(origin_matches("*", "https://anything.test"));
+        assert!(origin_matches("https://Shop.Example.com", "https://shop.example.COM"));
+        assert!(!origin_matches("https://shop.example.com", "http://shop.example.com"));
+
+        assert!(origin_matches("https://*.example.com", "https://shop.example.com"));
+        assert!(origin_matches("https://*.example.com", "https://a.b.example.com"));
+        assert!(!origin_matches("https://*.example.com", "https://example.com"));
+        assert!(!origin_matches("https://*.example.com", "https://.example.com"));
+        assert!(!origin_matches("https://*.example.com", "https://evil.com/.example.com"));
+        assert!(!origin_matches("https://*.example.com", "https://shop.example.com.evil.com"));
+    }
+
+    #[test]
+    fn lockout_doubles_from_the_threshold_up_to_the_cap() {
+        let rule = RateLimitRule { max: 10, window_secs: 60 };
+        let config = RateLimitConfig {
+            per_ip: rule,
+            per_account: rule,
+            stk_push_per_phone: rule,
+            stk_push_per_user: rule,
+            trust_proxy_headers: false,
+            lockout_threshold: 5,
+            lockout_base_secs: 60,
+            lockout_max_secs: 600,
+        };
+
+        assert_eq!(config.lockout_secs(5), 60);
+        assert_eq!(config.lockout_secs(6), 120);
+        assert_eq!(config.lockout_secs(8), 480);
+        assert_eq!(config.lockout_secs(9), 600);
+        assert_eq!(config.lockout_secs(u32::MAX), 600);
+    }
+}
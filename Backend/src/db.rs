@@ -1,7 +1,10 @@
 // src/db.rs
-use mongodb::{Client, options::ClientOptions};
+use mongodb::{Client, bson::doc, options::ClientOptions};
 use std::env;
 
+use crate::migrations;
+use crate::repository::Repositories;
+
 pub async fn init_db() -> Result<Client, mongodb::error::Error> {
     let mongo_uri = env::var("MONGODB_URI").expect("MONGODB_URI must be set in .env file");
     
@@ -12,4 +15,32 @@ pub async fn init_db() -> Result<Client, mongodb::error::Error> {
     let client = Client::with_options(client_options)?;
     
     Ok(client)
-}
\ No newline at end of file
+}
+
+// Name of the application database (MONGODB_DB, defaults to "yetta_db")
+pub fn database_name() -> String {
+    env::var("MONGODB_DB").unwrap_or_else(|_| "yetta_db".to_string())
+}
+
+// Build the repositories selected by STORAGE_BACKEND ("mongodb" or "memory")
+pub async fn init_repositories() -> Result<Repositories, mongodb::error::Error> {
+    let backend = env::var("STORAGE_BACKEND").unwrap_or_else(|_| "mongodb".to_string());
+
+    if backend.eq_ignore_ascii_case("memory") {
+        tracing::warn!("Using in-memory storage; data will be lost on restart");
+        return Ok(Repositories::in_memory());
+    }
+
+    let client = init_db().await?;
+
+    // Client::with_options connects lazily, so ping to fail fast on a bad URI or a down server
+    let db_name = database_name();
+    let db = client.database(&db_name);
+    db.run_command(doc! { "ping": 1 }, None).await?;
+    tracing::info!(database = %db_name, "Successfully connected to MongoDB");
+
+    // Bring indexes and data up to date before serving any requests
+    migrations::run(&db).await?;
+
+    Ok(Repositories::mongo(&client, &db_name))
+}
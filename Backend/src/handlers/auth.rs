@@ -1,12 +1,20 @@
 use actix_web::{web, HttpResponse, Responder};
-use mongodb::{Client, Collection, bson::{doc, oid::ObjectId}};
+use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
-use log;
 use reqwest; // Added for Google token verification
+use chrono::{Duration, Utc};
+use uuid::Uuid;
 
-use crate::models::user::{User, PublicUser};
+use crate::config::{self, RateLimitConfig};
+use crate::metrics;
+use crate::middleware::rate_limit::{self, too_many_requests};
+use crate::models::user::{normalise_email, User, PublicUser};
+use crate::repository::{RateLimitRepo, RepoError, UserRepo};
 use crate::utils::{password, jwt};
 
+// How long the link in the lockout email works, like password reset links
+const UNLOCK_TOKEN_HOURS: i64 = 1;
+
 // --- Helper Structs for Requests/Responses ---
 
 #[derive(Deserialize)]
@@ -22,6 +30,11 @@ pub struct LoginRequest {
     password: String,
 }
 
+#[derive(Deserialize)]
+pub struct UnlockRequest {
+    token: String,
+}
+
 #[derive(Deserialize)]
 pub struct GoogleRequest {
     credential: String,
@@ -38,69 +51,54 @@ struct ErrorResponse {
     message: String,
 }
 
-// Helper to get the user collection from the DB
-fn get_user_collection(client: &web::Data<Client>) -> Collection<User> {
-    client.database("yetta_db").collection("users")
-}
-
 // --- Route Handlers ---
 
 /// POST /api/auth/signup
-pub async fn signup(client: web::Data<Client>, req: web::Json<SignupRequest>) -> impl Responder {
-    let users_collection = get_user_collection(&client);
-    
-    // 1. Check if user already exists
-    match users_collection.find_one(doc! { "email": &req.email }, None).await {
-        Ok(Some(_)) => {
-            return HttpResponse::Conflict().json(ErrorResponse { 
-                message: "Email already exists".to_string() 
-            });
-        },
-        Ok(None) => (), // Email is available
-        Err(e) => {
-            log::error!("Database error during signup lookup: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
-        }
-    }
-    
-    // 2. Hash the password
+pub async fn signup(users: web::Data<dyn UserRepo>, req: web::Json<SignupRequest>) -> impl Responder {
+    // 1. Hash the password
     let password_hash = match password::hash_password(&req.password) {
         Ok(hash) => hash,
         Err(e) => {
-            log::error!("Password hashing error: {}", e);
+            tracing::error!(error = %e, "Password hashing error");
             return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to create account".to_string() });
         }
     };
     
-    // 3. Create new user
+    // 2. Create new user
     let new_user = User {
         id: Some(ObjectId::new()),
         name: req.name.clone(),
-        email: req.email.clone(),
+        email: normalise_email(&req.email),
         password_hash: Some(password_hash),
         google_id: None,
-        reset_token: None,
-        reset_token_expiry: None,
+        ..Default::default()
     };
     
-    // 4. Insert into database
-    // We clone new_user before insert because insert_one consumes the value,
-    // and we need the original for token generation.
-    if let Err(e) = users_collection.insert_one(&new_user, None).await {
-        log::error!("Database insert error: {}", e);
-        return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to save user".to_string() });
+    // 3. Insert into database
+    // The unique index on email rejects existing accounts, including concurrent double-submits.
+    match users.insert(&new_user).await {
+        Ok(_) => (),
+        Err(RepoError::Duplicate) => {
+            return HttpResponse::Conflict().json(ErrorResponse { 
+                message: "Email already exists".to_string() 
+            });
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Database insert error");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to save user".to_string() });
+        }
     }
     
-    // 5. Create JWT using the new utility function
-    let token = match jwt::create_token(&new_user.email, &new_user.name) {
+    // 4. Create JWT using the new utility function
+    let token = match jwt::create_token(&new_user.email, &new_user.name, new_user.token_version) {
         Ok(token) => token,
         Err(e) => {
-            log::error!("JWT creation error: {}", e);
+            tracing::error!(error = %e, "JWT creation error");
             return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to create session".to_string() });
         }
     };
     
-    // 6. Send response
+    // 5. Send response
     HttpResponse::Ok().json(AuthResponse {
         token,
         user: new_user.into(),
@@ -109,157 +107,259 @@ pub async fn signup(client: web::Data<Client>, req: web::Json<SignupRequest>) ->
 
 // Helper Struct for Google Token Info
 #[derive(Deserialize)]
-struct GoogleTokenInfo {
-    email: String,
-    name: Option<String>,
-    sub: String, // This is the unique Google ID
+pub(crate) struct GoogleTokenInfo {
+    pub(crate) email: String,
+    pub(crate) name: Option<String>,
+    pub(crate) sub: String, // This is the unique Google ID
+    iat: Option<String>, // Seconds since the epoch, as a string
+}
+
+impl GoogleTokenInfo {
+    /// True if the customer signed in with Google no more than `max_age` ago
+    pub(crate) fn issued_within(&self, max_age: Duration) -> bool {
+        self.iat.as_deref()
+            .and_then(|iat| iat.parse::<i64>().ok())
+            .is_some_and(|iat| Utc::now().timestamp() - iat <= max_age.num_seconds())
+    }
+}
+
+/// Have Google check an ID token from Google Sign-In
+pub(crate) async fn google_token_info(credential: &str) -> Result<GoogleTokenInfo, HttpResponse> {
+    let client_http = reqwest::Client::new();
+    let response = match client_http.get("https://oauth2.googleapis.com/tokeninfo")
+        .query(&[("id_token", credential)])
+        .send()
+        .await {
+            Ok(res) => res,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to contact Google API");
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to verify with Google".to_string() }));
+            }
+        };
+
+    if !response.status().is_success() {
+        tracing::warn!(status = %response.status(), "Google API returned error status");
+        return Err(HttpResponse::Unauthorized().json(ErrorResponse { message: "Invalid Google token".to_string() }));
+    }
+
+    response.json().await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to parse Google response");
+        HttpResponse::InternalServerError().json(ErrorResponse { message: "Invalid response from Google".to_string() })
+    })
 }
 
 /// POST /api/auth/login
-pub async fn login(client: web::Data<Client>, req: web::Json<LoginRequest>) -> impl Responder {
-    let users_collection = get_user_collection(&client);
+pub async fn login(
+    users: web::Data<dyn UserRepo>,
+    limits: web::Data<dyn RateLimitRepo>,
+    limit_config: web::Data<RateLimitConfig>,
+    req: web::Json<LoginRequest>,
+) -> impl Responder {
+    let email = normalise_email(&req.email);
+    tracing::info!(email = %email, "Attempting login");
 
-    log::info!("Attempting login for email: {}", req.email);
+    // 1. Per-account rate limit (the per-IP limit is applied to the whole /api/auth scope)
+    let limit_key = format!("login:{}", email);
+    if let Err(retry_after) = rate_limit::check(limits.get_ref(), &limit_key, limit_config.per_account).await {
+        tracing::warn!(email = %email, reason = "rate_limited", "Login failed: too many attempts");
+        metrics::record_login_failure("rate_limited");
+        return too_many_requests(retry_after, "Too many login attempts. Please try again later.");
+    }
 
-    // 1. Find user by email
-    let user = match users_collection.find_one(doc! { "email": &req.email }, None).await {
+    // 2. Find user by email
+    let user = match users.find_by_email(&email).await {
         Ok(Some(user)) => {
-            log::info!("User found: {}", user.email);
+            tracing::info!(email = %user.email, "User found");
             user
         },
         Ok(None) => {
-            log::warn!("Login failed: User not found for email: {}", req.email);
+            tracing::warn!(email = %email, reason = "unknown_email", "Login failed: user not found");
+            metrics::record_login_failure("unknown_email");
             // Do not reveal if email exists or not for security
             return HttpResponse::Unauthorized().json(ErrorResponse { message: "Invalid email or password".to_string() });
         },
         Err(e) => {
-            log::error!("Database error during login lookup: {}", e);
+            tracing::error!(error = %e, "Database error during login lookup");
             return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
         }
     };
 
-    // 2. Check if user has a password (they might be a Google user)
+    // 3. Refuse logins while the account is locked
+    let now = Utc::now().timestamp_millis();
+    if let Some(locked_until) = user.locked_until.filter(|until| *until > now) {
+        tracing::warn!(email = %email, reason = "locked", "Login failed: account locked");
+        metrics::record_login_failure("locked");
+        let retry_after = (locked_until - now + 999) / 1000;
+        return too_many_requests(retry_after, "Account temporarily locked after too many failed attempts. Check your email for an unlock link.");
+    }
+
+    // 4. Check if user has a password (they might be a Google user)
     let hash = match user.password_hash.as_ref() {
         Some(hash) => hash,
         None => {
-            log::warn!("Login failed: User {} has no password (likely Google auth)", req.email);
+            tracing::warn!(email = %email, reason = "no_password", "Login failed: user has no password (likely Google auth)");
+            metrics::record_login_failure("no_password");
             // Logged in with email but has no password hash set
             return HttpResponse::Unauthorized().json(ErrorResponse { message: "Please log in with Google, or reset your password.".to_string() });
         }
     };
     
-    // 3. Verify password
+    // 5. Verify password
     match password::verify_password(hash, &req.password) {
         Ok(true) => {
-            log::info!("Password verified for user: {}", req.email);
-            // 4. Create JWT
-            let token = match jwt::create_token(&user.email, &user.name) {
+            tracing::info!(email = %email, "Password verified");
+            if user.failed_login_attempts > 0 || user.locked_until.is_some() {
+                if let Err(e) = users.clear_failed_logins(&user.email).await {
+                    tracing::error!(error = %e, "Failed to reset failed login counter");
+                }
+            }
+
+            // 6. Create JWT
+            let token = match jwt::create_token(&user.email, &user.name, user.token_version) {
                 Ok(token) => token,
                 Err(e) => {
-                    log::error!("JWT creation error: {}", e);
+                    tracing::error!(error = %e, "JWT creation error");
                     return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to create session".to_string() });
                 }
             };
             
-            // 5. Send response
+            // 7. Send response
             HttpResponse::Ok().json(AuthResponse {
                 token,
                 user: user.into(),
             })
         },
         _ => {
-            log::warn!("Login failed: Invalid password for user: {}", req.email);
+            tracing::warn!(email = %email, reason = "wrong_password", "Login failed: invalid password");
+            metrics::record_login_failure("wrong_password");
+            register_failed_login(users.get_ref(), &limit_config, &user.email).await;
             // Wrong password
             HttpResponse::Unauthorized().json(ErrorResponse { message: "Invalid email or password".to_string() })
         }
     }
 }
 
-/// POST /api/auth/google
-pub async fn verify_google_token(client: web::Data<Client>, req: web::Json<GoogleRequest>) -> impl Responder {
-    log::info!("Verifying Google token...");
-
-    // 1. Verify token with Google API
-    let client_http = reqwest::Client::new();
-    let response = match client_http.get("https://oauth2.googleapis.com/tokeninfo")
-        .query(&[("id_token", &req.credential)])
-        .send()
-        .await {
-            Ok(res) => res,
-            Err(e) => {
-                log::error!("Failed to contact Google API: {}", e);
-                return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to verify with Google".to_string() });
-            }
-        };
+// Count a wrong password and lock the account once the threshold is reached.
+// Each further failure doubles the lockout, up to LOCKOUT_MAX_SECS.
+async fn register_failed_login(users: &dyn UserRepo, limit_config: &RateLimitConfig, email: &str) {
+    let failures = match users.increment_failed_logins(email).await {
+        Ok(failures) => failures,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to record failed login");
+            return;
+        }
+    };
+    if failures < limit_config.lockout_threshold {
+        return;
+    }
 
-    if !response.status().is_success() {
-        log::warn!("Google API returned error status: {}", response.status());
-        return HttpResponse::Unauthorized().json(ErrorResponse { message: "Invalid Google token".to_string() });
+    let lock_secs = limit_config.lockout_secs(failures);
+    let locked_until = Utc::now().timestamp_millis() + lock_secs * 1000;
+    let unlock_token = Uuid::new_v4().to_string();
+    let token_expiry = (Utc::now() + Duration::hours(UNLOCK_TOKEN_HOURS)).timestamp_millis();
+    if let Err(e) = users.lock(email, locked_until, &unlock_token, token_expiry).await {
+        tracing::error!(error = %e, "Failed to lock account");
+        return;
     }
 
-    let google_info: GoogleTokenInfo = match response.json().await {
-        Ok(info) => info,
+    // "Send" the unlock email (logged, like password reset links)
+    let unlock_link = format!("{}/unlock-account?token={}", config::frontend_url(), unlock_token);
+    tracing::warn!(email = %email, failures, lock_secs, unlock_link = %unlock_link, "Account locked after repeated failed logins");
+}
+
+/// POST /api/auth/unlock
+/// Lifts a lockout using the token from the unlock email
+pub async fn unlock_account(users: web::Data<dyn UserRepo>, req: web::Json<UnlockRequest>) -> impl Responder {
+    let now = Utc::now().timestamp_millis();
+    let user = match users.find_by_unlock_token(&req.token).await {
+        Ok(Some(user)) if user.unlock_token_expiry.is_some_and(|expiry| expiry > now) => user,
+        Ok(_) => {
+            return HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid or expired unlock link".to_string() });
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Database error during unlock lookup");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+
+    match users.clear_failed_logins(&user.email).await {
+        Ok(_) => {
+            tracing::info!(email = %user.email, "Account unlocked");
+            HttpResponse::Ok().json(serde_json::json!({ "message": "Account unlocked. You can now log in." }))
+        },
         Err(e) => {
-            log::error!("Failed to parse Google response: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Invalid response from Google".to_string() });
+            tracing::error!(error = %e, "Failed to unlock account");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to unlock account".to_string() })
         }
+    }
+}
+
+/// POST /api/auth/google
+pub async fn verify_google_token(users: web::Data<dyn UserRepo>, req: web::Json<GoogleRequest>) -> impl Responder {
+    tracing::info!("Verifying Google token");
+
+    // 1. Verify token with Google API
+    let google_info = match google_token_info(&req.credential).await {
+        Ok(info) => info,
+        Err(res) => return res,
     };
 
-    log::info!("Google token verified for: {}", google_info.email);
+    let google_email = normalise_email(&google_info.email);
+    tracing::info!(email = %google_email, "Google token verified");
 
-    let users_collection = get_user_collection(&client);
-    
     // 2. Find or create user in database using Google ID
-    let user = match users_collection.find_one(doc! { "google_id": &google_info.sub }, None).await {
+    let user = match users.find_by_google_id(&google_info.sub).await {
         Ok(Some(user)) => {
-            log::info!("Existing Google user found: {}", user.email);
+            tracing::info!(email = %user.email, "Existing Google user found");
             user
         }, 
         Ok(None) => {
             // Check if email exists (account linking)
             // Ideally, you'd want to be careful here. If someone registers with email, then logs in with Google, 
             // should we link them? For simplicity, let's check email.
-            if let Ok(Some(mut existing_user)) = users_collection.find_one(doc! { "email": &google_info.email }, None).await {
-                 log::info!("Linking Google account to existing email: {}", google_info.email);
+            if let Ok(Some(mut existing_user)) = users.find_by_email(&google_email).await {
+                 tracing::info!(email = %google_email, "Linking Google account to existing email");
                  // Update user with Google ID
                  existing_user.google_id = Some(google_info.sub.clone());
-                 let _ = users_collection.update_one(
-                     doc! { "email": &google_info.email },
-                     doc! { "$set": { "google_id": &google_info.sub } },
-                     None
-                 ).await;
+                 let _ = users.set_google_id(&google_email, &google_info.sub).await;
                  existing_user
             } else {
                 // User does not exist, create them
-                log::info!("Creating new Google user: {}", google_info.email);
+                tracing::info!(email = %google_email, "Creating new Google user");
                 let new_user = User {
                     id: Some(ObjectId::new()),
                     name: google_info.name.unwrap_or_else(|| "Google User".to_string()),
-                    email: google_info.email.clone(),
+                    email: google_email.clone(),
                     password_hash: None, // No password for Google users
                     google_id: Some(google_info.sub),
-                    reset_token: None,
-                    reset_token_expiry: None,
+                    ..Default::default()
                 };
                 
-                if let Err(e) = users_collection.insert_one(&new_user, None).await {
-                    log::error!("Database insert error for Google user: {}", e);
-                    return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to save Google user".to_string() });
+                match users.insert(&new_user).await {
+                    Ok(_) => new_user,
+                    // A concurrent sign-in created this user first; use that record
+                    Err(RepoError::Duplicate) => match users.find_by_email(&new_user.email).await {
+                        Ok(Some(user)) => user,
+                        _ => return HttpResponse::Conflict().json(ErrorResponse { message: "Account is being created, please retry".to_string() }),
+                    },
+                    Err(e) => {
+                        tracing::error!(error = %e, "Database insert error for Google user");
+                        return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to save Google user".to_string() });
+                    }
                 }
-                new_user
             }
         },
         Err(e) => {
-            log::error!("Database error during Google login: {}", e);
+            tracing::error!(error = %e, "Database error during Google login");
             return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
         }
     };
     
     // 3. Create your app's JWT
-    let token = match jwt::create_token(&user.email, &user.name) {
+    let token = match jwt::create_token(&user.email, &user.name, user.token_version) {
         Ok(token) => token,
         Err(e) => {
-            log::error!("JWT creation error: {}", e);
+            tracing::error!(error = %e, "JWT creation error");
             return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to create session".to_string() });
         }
     };
@@ -269,4 +369,64 @@ pub async fn verify_google_token(client: web::Data<Client>, req: web::Json<Googl
         token,
         user: user.into(),
     })
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    use crate::repository::Repositories;
+    use crate::test_util::call;
+
+    fn login_as(password: &str) -> test::TestRequest {
+        test::TestRequest::post().uri("/api/auth/login").set_json(serde_json::json!({ "email": "Jane@Example.com", "password": password }))
+    }
+
+    #[actix_web::test]
+    async fn repeated_wrong_passwords_lock_the_account_until_unlocked() {
+        let repos = Repositories::in_memory();
+        let limit_config = RateLimitConfig::from_env();
+        let app = test::init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .app_data(web::Data::new(limit_config.clone()))
+                .route("/api/auth/signup", web::post().to(signup))
+                .route("/api/auth/login", web::post().to(login))
+                .route("/api/auth/unlock", web::post().to(unlock_account))
+        ).await;
+        let signup_req = test::TestRequest::post().uri("/api/auth/signup")
+            .set_json(serde_json::json!({ "name": "Jane", "email": "jane@example.com", "password": "correct horse" }));
+        assert_eq!(call(&app, signup_req).await.0, StatusCode::OK);
+
+        for _ in 0..limit_config.lockout_threshold {
+            assert_eq!(call(&app, login_as("wrong")).await.0, StatusCode::UNAUTHORIZED);
+        }
+        let (status, body) = call(&app, login_as("correct horse")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+
+        let user = repos.users.find_by_email("jane@example.com").await.unwrap().unwrap();
+        let unlock = test::TestRequest::post().uri("/api/auth/unlock").set_json(serde_json::json!({ "token": user.unlock_token.unwrap() }));
+        assert_eq!(call(&app, unlock).await.0, StatusCode::OK);
+
+        let (status, body) = call(&app, login_as("correct horse")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["token"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn expired_unlock_links_are_refused() {
+        let repos = Repositories::in_memory();
+        let app = test::init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .route("/api/auth/unlock", web::post().to(unlock_account))
+        ).await;
+        let user = User { id: Some(ObjectId::new()), email: "jane@example.com".to_string(), ..Default::default() };
+        repos.users.insert(&user).await.unwrap();
+        let now = Utc::now().timestamp_millis();
+        repos.users.lock(&user.email, now + 60_000, "old-link", now - 1).await.unwrap();
+
+        let unlock = test::TestRequest::post().uri("/api/auth/unlock").set_json(serde_json::json!({ "token": "old-link" }));
+        assert_eq!(call(&app, unlock).await.0, StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,14 +1,24 @@
 use actix_web::{web, HttpResponse, Responder, HttpRequest};
-use mongodb::{Client, Collection, bson::doc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use chrono::Utc;
-use futures::stream::StreamExt;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
 
-use crate::models::user::User;
-use crate::models::order::{Order, OrderItem};
-use crate::utils::jwt::decode_token;
-use crate::handlers::mpesa::send_stk_push;
+use crate::config::{FulfilmentConfig, LoyaltyConfig, RateLimitConfig, SlotConfig};
+use crate::metrics;
+use crate::models::order::{Fulfilment, GeoPoint, Order, OrderItem, STATUS_PAID, STATUS_PAYMENT_INITIATED, STATUS_PENDING};
+use crate::utils::geo;
+use crate::models::product::{Product, DEFAULT_CATEGORY};
+use crate::models::slot::SlotReservation;
+use crate::repository::{LoyaltyRepo, OrderRepo, ProductRepo, PromotionRepo, RateLimitRepo, SlotRepo, StockRepo, UploadRepo, UserRepo};
+use crate::utils::jwt::get_user_email_from_req;
+use crate::handlers::mpesa::{check_stk_push_limits, normalize_phone, send_stk_push};
+use crate::handlers::slots::{release_slots, reserve_slots};
+use crate::handlers::stock::{release_stock, reserve_stock, stock_date};
+use crate::handlers::promotions::{cart_lines, evaluate_promotion, redeem_promotion, release_promotion};
+use crate::handlers::loyalty::{current_balance, refund_points, spend_points};
+use crate::handlers::orders::release_order_holds;
+use std::collections::BTreeMap;
 
 // Struct for the item coming from React
 #[derive(Deserialize, Debug)]
@@ -24,7 +34,95 @@ pub struct CheckoutRequest {
     phone_number: Option<String>,
     bank_account: Option<String>,
     items: Vec<OrderItem>, // Frontend sends the items to be ordered
-    total: f64,
+    total: f64, // What the cart showed; the charged total is worked out on the server
+    fulfilment: Option<FulfilmentRequest>,
+    /// Set once the customer has seen the allergy warnings and wants to order anyway
+    #[serde(default)]
+    accept_allergens: bool,
+    promo_code: Option<String>,
+    /// Loyalty points to put towards the order; any more than it needs are left on the balance
+    redeem_points: Option<u32>,
+}
+
+// A promo code to try against the cart before checking out
+#[derive(Deserialize, Debug)]
+pub struct ApplyPromoRequest {
+    code: String,
+    items: Vec<OrderItem>,
+    fulfilment: Option<FulfilmentRequest>,
+}
+
+// A cart item containing something the customer said they're allergic to
+#[derive(Serialize, Debug)]
+struct AllergyWarning {
+    item_id: String,
+    title: String,
+    allergens: Vec<String>,
+}
+
+// How the customer wants to receive the order
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FulfilmentRequest {
+    Pickup {
+        branch: String,
+        pickup_at: DateTime<Utc>,
+    },
+    Delivery {
+        address: String,
+        location: Option<GeoPoint>,
+        notes: Option<String>,
+        deliver_at: Option<DateTime<Utc>>,
+        zone: Option<String>,
+    },
+}
+
+const MAX_DELIVERY_NOTES_LEN: usize = 500;
+const MAX_INSCRIPTION_CHARS: usize = 60;
+const MAX_INSCRIPTION_LINES: usize = 3;
+
+// Characters the kitchen can pipe: letters (any script), digits, spaces and common punctuation
+fn is_pipeable(c: char) -> bool {
+    c.is_alphanumeric() || c == ' ' || c == '\n' || ".,!?'\"&-:()#@+/♥❤".contains(c)
+}
+
+// Check personalisation on an order line; returns the cleaned-up inscription
+fn validate_personalisation(item: &OrderItem, product: &Product) -> Result<Option<String>, String> {
+    let inscription = item.inscription.as_deref().map(str::trim).filter(|i| !i.is_empty());
+    if inscription.is_none() && item.reference_image.is_none() {
+        return Ok(None);
+    }
+    if !product.personalisable {
+        return Err(format!("{} can't be personalised", item.title));
+    }
+
+    if let Some(text) = inscription {
+        if text.chars().count() > MAX_INSCRIPTION_CHARS {
+            return Err(format!("Inscription must be at most {} characters", MAX_INSCRIPTION_CHARS));
+        }
+        if text.lines().count() > MAX_INSCRIPTION_LINES {
+            return Err(format!("Inscription must be at most {} lines", MAX_INSCRIPTION_LINES));
+        }
+        if let Some(c) = text.chars().find(|c| !is_pipeable(*c)) {
+            return Err(format!("Inscription can't contain '{}'", c));
+        }
+    }
+    Ok(inscription.map(str::to_string))
+}
+
+// Reference images must be the customer's own uploads
+async fn check_reference_images(uploads: &dyn UploadRepo, user_email: &str, items: &[OrderItem]) -> Result<(), HttpResponse> {
+    for id in items.iter().filter_map(|i| i.reference_image.as_deref()) {
+        match uploads.find_by_id(id).await {
+            Ok(Some(upload)) if upload.owner_email == user_email => (),
+            Ok(_) => return Err(HttpResponse::BadRequest().json(ErrorResponse { message: "Reference image not found. Please upload it again.".to_string() })),
+            Err(e) => {
+                tracing::error!(error = %e, "Database error checking reference image");
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() }));
+            }
+        }
+    }
+    Ok(())
 }
 
 #[derive(Serialize)]
@@ -32,47 +130,227 @@ struct ErrorResponse {
     message: String,
 }
 
-// Helper to get the user collection
-#[allow(dead_code)]
-fn get_user_collection(client: &web::Data<Client>) -> Collection<User> {
-    client.database("yetta_db").collection("users")
+// Validate the requested fulfilment and work out the delivery fee
+fn resolve_fulfilment(req: &FulfilmentRequest, config: &FulfilmentConfig) -> Result<(Fulfilment, f64), String> {
+    match req {
+        FulfilmentRequest::Pickup { branch, pickup_at } => {
+            let branch = config.find_branch(branch)
+                .ok_or_else(|| format!("Unknown pickup branch. Choose one of: {}", config.pickup_branches.join(", ")))?;
+            if *pickup_at <= Utc::now() {
+                return Err("Pickup time must be in the future".to_string());
+            }
+            Ok((Fulfilment::Pickup { branch: branch.to_string(), pickup_at: *pickup_at }, 0.0))
+        }
+        FulfilmentRequest::Delivery { address, location, notes, deliver_at, zone } => {
+            let address = address.trim();
+            if address.is_empty() {
+                return Err("Delivery address required".to_string());
+            }
+            if location.as_ref().is_some_and(|point| !geo::is_valid(point)) {
+                return Err("Invalid delivery location".to_string());
+            }
+            let notes = notes.as_deref().map(str::trim).filter(|n| !n.is_empty());
+            if notes.is_some_and(|n| n.len() > MAX_DELIVERY_NOTES_LEN) {
+                return Err(format!("Delivery notes must be at most {} characters", MAX_DELIVERY_NOTES_LEN));
+            }
+            if deliver_at.is_some_and(|at| at <= Utc::now()) {
+                return Err("Delivery time must be in the future".to_string());
+            }
+
+            // A known zone has a flat fee; otherwise charge by distance from the bakery
+            let zone = zone.as_deref().and_then(|z| config.find_zone(z));
+            let distance_km = match (location, config.delivery_origin) {
+                (Some(point), Some(origin)) => Some(geo::distance_km(&origin, point)),
+                _ => None,
+            };
+            let fee = match (zone, distance_km) {
+                (Some((_, fee)), _) => fee,
+                (None, Some(km)) => config.distance_fee(km).ok_or("Delivery address is outside our delivery area")?,
+                (None, None) => return Err("Delivery location or zone required".to_string()),
+            };
+
+            Ok((Fulfilment::Delivery {
+                address: address.to_string(),
+                location: *location,
+                notes: notes.map(str::to_string),
+                deliver_at: *deliver_at,
+                zone: zone.map(|(name, _)| name.to_string()),
+                distance_km: distance_km.map(|km| (km * 10.0).round() / 10.0),
+            }, fee))
+        }
+    }
 }
 
-// Helper to get the order collection
-fn get_order_collection(client: &web::Data<Client>) -> Collection<Order> {
-    client.database("yetta_db").collection("orders")
+// The catalog product a cart item is for. Older builds of the cake builder sent a
+// unique `custom-cake-<timestamp>` id per cake; those are still the custom cake.
+fn find_product<'a>(catalog: &'a [Product], item_id: &str) -> Option<&'a Product> {
+    catalog.iter().find(|p| p.item_id == item_id).or_else(|| {
+        catalog.iter().find(|p| item_id.strip_prefix(p.item_id.as_str()).is_some_and(|rest| rest.starts_with('-')) && !p.option_groups.is_empty())
+    })
 }
 
-// Helper to extract user email from JWT
-fn get_user_email_from_req(req: &HttpRequest) -> Result<String, String> {
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                match decode_token(token) {
-                    Ok(token_data) => return Ok(token_data.claims.sub),
-                    Err(_) => return Err("Invalid token".to_string()),
-                }
+// Ids to load from the catalog for a cart, including the product behind each old
+// `custom-cake-<timestamp>` id
+fn catalog_ids(items: &[OrderItem]) -> Vec<String> {
+    let mut ids: Vec<String> = items.iter().map(|i| i.item_id.clone()).collect();
+    ids.extend(items.iter().filter_map(|i| {
+        let (stem, suffix) = i.item_id.rsplit_once('-')?;
+        (!suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit())).then(|| stem.to_string())
+    }));
+    ids
+}
+
+// Server-side prices, options and allergens for every item; the cart's own price is never used
+fn price_items(items: &[OrderItem], catalog: &[Product]) -> Result<Vec<OrderItem>, String> {
+    if items.is_empty() {
+        return Err("Your cart is empty".to_string());
+    }
+
+    let now = Utc::now();
+    items.iter().map(|item| {
+        if item.quantity == 0 {
+            return Err(format!("Quantity for {} must be at least 1", item.title));
+        }
+        let product = find_product(catalog, &item.item_id)
+            .ok_or_else(|| format!("{} isn't on our menu", item.title))?;
+        let inscription = validate_personalisation(item, product)?;
+        if !product.is_on_sale(now) {
+            return Err(if product.deleted_at.is_some() {
+                format!("{} is no longer on the menu", product.title)
+            } else if !product.available {
+                format!("{} is sold out", product.title)
+            } else {
+                format!("{} isn't available right now", product.title)
+            });
+        }
+
+        let (price, options) = product.price_with_options(&item.options)?;
+        Ok(OrderItem {
+            allergens: product.allergens_with(&options),
+            item_id: product.item_id.clone(),
+            title: product.title.clone(),
+            quantity: item.quantity,
+            price,
+            image_src: product.image_src.clone(),
+            options,
+            inscription,
+            reference_image: item.reference_image.clone(),
+        })
+    }).collect()
+}
+
+// Items that contain any of the customer's saved allergies
+fn allergy_warnings(items: &[OrderItem], allergies: &[String]) -> Vec<AllergyWarning> {
+    items.iter().filter_map(|item| {
+        let allergens: Vec<String> = item.allergens.iter().filter(|a| allergies.contains(a)).cloned().collect();
+        (!allergens.is_empty()).then(|| AllergyWarning {
+            item_id: item.item_id.clone(),
+            title: item.title.clone(),
+            allergens,
+        })
+    }).collect()
+}
+
+// Units per product category, reserved against that category's time slots
+async fn reserve_order_slots(
+    slots: &dyn SlotRepo,
+    slot_config: &SlotConfig,
+    catalog: &[Product],
+    items: &[OrderItem],
+    fulfilment: Option<&Fulfilment>,
+) -> Result<Vec<SlotReservation>, HttpResponse> {
+    let mut demand = BTreeMap::new();
+    for item in items {
+        let category = catalog.iter()
+            .find(|p| p.item_id == item.item_id)
+            .map_or(DEFAULT_CATEGORY, |p| p.category.as_str());
+        *demand.entry(category.to_string()).or_insert(0) += item.quantity;
+    }
+
+    let at = fulfilment.and_then(Fulfilment::requested_time);
+    reserve_slots(slots, slot_config, at, &demand).await
+}
+
+/// How checkout collects the money
+enum Payment {
+    /// Nothing left to collect; the order is paid as it is placed
+    Settled,
+    /// An M-Pesa prompt to this phone, sent once the order is saved
+    StkPush(String),
+}
+
+// Check the payment details (and STK push limits) before anything is saved
+async fn check_payment(
+    req: &CheckoutRequest,
+    total: f64,
+    user_email: &str,
+    limits: &dyn RateLimitRepo,
+    limit_config: &RateLimitConfig,
+    method_label: &str,
+) -> Result<Payment, HttpResponse> {
+    // Points and promo codes can cover the whole order; there is nothing to charge
+    if total.ceil() <= 0.0 && ["mpesa", "bank"].contains(&req.payment_method.as_str()) {
+        tracing::info!(payment_method = %req.payment_method, "Order total is zero; no payment taken");
+        return Ok(Payment::Settled);
+    }
+    if req.payment_method == "mpesa" {
+        let Some(phone) = &req.phone_number else {
+            metrics::record_checkout(method_label, "rejected");
+            return Err(HttpResponse::BadRequest().json(ErrorResponse { message: "Mpesa Express phone number required".to_string() }));
+        };
+        let phone = match normalize_phone(phone) {
+            Some(phone) => phone,
+            None => {
+                metrics::record_checkout(method_label, "rejected");
+                return Err(HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid Mpesa Express phone number format".to_string() }));
             }
+        };
+        if let Err(res) = check_stk_push_limits(limits, limit_config, user_email, &phone).await {
+            metrics::record_checkout(method_label, "rate_limited");
+            return Err(res);
         }
+        Ok(Payment::StkPush(phone))
+    } else if req.payment_method == "bank" {
+        if let Some(account) = &req.bank_account {
+            tracing::info!(account = %account, "Processing bank transfer");
+            Ok(Payment::Settled)
+        } else {
+            metrics::record_checkout(method_label, "rejected");
+            Err(HttpResponse::BadRequest().json(ErrorResponse { message: "Bank account required".to_string() }))
+        }
+    } else {
+        metrics::record_checkout(method_label, "rejected");
+        Err(HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid payment method specified".to_string() }))
     }
-    Err("No authorization header".to_string())
 }
 
 /// POST /api/cart/add
 /// Adds an item to a user's cart (Mock version)
-pub async fn add_to_cart(_client: web::Data<Client>, item: web::Json<CartItemRequest>) -> impl Responder {
+pub async fn add_to_cart(item: web::Json<CartItemRequest>) -> impl Responder {
     // In a real app, you would get the user's ID from their JWT token.
     // For this example, we'll just log it.
-    log::info!("Adding item {} to cart", item.item_id);
+    tracing::info!(item_id = %item.item_id, "Adding item to cart");
     
     HttpResponse::Ok().json(json!({ "message": "Item added to cart" })) 
 }
 
 /// POST /api/cart/checkout
 /// Processes the checkout and saves the order
+#[allow(clippy::too_many_arguments)] // one extractor per dependency
 pub async fn process_checkout(
-    client: web::Data<Client>, 
+    orders: web::Data<dyn OrderRepo>, 
+    limits: web::Data<dyn RateLimitRepo>,
+    limit_config: web::Data<RateLimitConfig>,
+    fulfilment_config: web::Data<FulfilmentConfig>,
+    products: web::Data<dyn ProductRepo>,
+    slots: web::Data<dyn SlotRepo>,
+    slot_config: web::Data<SlotConfig>,
+    uploads: web::Data<dyn UploadRepo>,
+    stock: web::Data<dyn StockRepo>,
+    users: web::Data<dyn UserRepo>,
+    promotions: web::Data<dyn PromotionRepo>,
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    loyalty_config: web::Data<LoyaltyConfig>,
     req: web::Json<CheckoutRequest>,
     http_req: HttpRequest
 ) -> impl Responder {
@@ -83,97 +361,437 @@ pub async fn process_checkout(
         Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
     };
     
-    log::info!("Processing checkout for user {} with method {}", user_email, req.payment_method);
+    tracing::info!(user = %user_email, payment_method = %req.payment_method, "Processing checkout");
+
+    // Keep the metric label bounded whatever the client sends
+    let method_label = match req.payment_method.as_str() {
+        "mpesa" | "bank" => req.payment_method.as_str(),
+        _ => "other",
+    };
+
+    // 2. Price the items
+    let item_ids = catalog_ids(&req.items);
+    let catalog = match products.find_by_ids(&item_ids).await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading products for checkout");
+            metrics::record_checkout(method_label, "error");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+    let items = match price_items(&req.items, &catalog) {
+        Ok(items) => items,
+        Err(message) => {
+            metrics::record_checkout(method_label, "rejected");
+            return HttpResponse::BadRequest().json(ErrorResponse { message });
+        }
+    };
+    if let Err(res) = check_reference_images(uploads.get_ref(), &user_email, &items).await {
+        metrics::record_checkout(method_label, "rejected");
+        return res;
+    }
+
+    // Warn before taking anything if the cart clashes with the customer's allergies
+    let allergies = match users.find_by_email(&user_email).await {
+        Ok(user) => user.map(|u| u.allergies).unwrap_or_default(),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading user for checkout");
+            metrics::record_checkout(method_label, "error");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+    let warnings = allergy_warnings(&items, &allergies);
+    if !warnings.is_empty() && !req.accept_allergens {
+        metrics::record_checkout(method_label, "rejected");
+        return HttpResponse::Conflict().json(json!({
+            "message": "Some items contain allergens you've asked to be warned about",
+            "allergy_warnings": warnings,
+        }));
+    }
+
+    let items_total = items.iter().fold(0.0, |sum, i| sum + i.price * i.quantity as f64);
+    if (items_total - req.total).abs() > 0.01 {
+        tracing::warn!(client_total = req.total, server_total = items_total, "Cart total differs from server prices");
+    }
 
-    // 2. Process Payment
-    let status = if req.payment_method == "mpesa" {
-        if let Some(phone) = &req.phone_number {
-            if phone.len() < 8 { 
-                 return HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid Mpesa Express phone number format".to_string() });
+    // 3. Pickup or delivery, and what delivery costs
+    let (fulfilment, delivery_fee) = match &req.fulfilment {
+        Some(fulfilment) => match resolve_fulfilment(fulfilment, &fulfilment_config) {
+            Ok((fulfilment, fee)) => (Some(fulfilment), fee),
+            Err(message) => {
+                metrics::record_checkout(method_label, "rejected");
+                return HttpResponse::BadRequest().json(ErrorResponse { message });
             }
-            log::info!("Initiating Mpesa Express STK Push to {}", phone);
-            
-            // Call actual Mpesa API
-            match send_stk_push(phone, req.total as u32).await {
-                Ok(res) => {
-                    log::info!("STK Push success: {:?}", res.message);
-                    "Payment Initiated"
-                },
-                Err(e) => {
-                    log::error!("STK Push failed: {}", e);
-                    return HttpResponse::BadRequest().json(ErrorResponse { message: format!("Payment failed: {}", e) });
+        },
+        None => (None, 0.0),
+    };
+
+    // 4. Apply any promo code
+    let promotion = match &req.promo_code {
+        Some(code) if !code.trim().is_empty() => {
+            match evaluate_promotion(promotions.get_ref(), code, &user_email, &cart_lines(&items, &catalog), delivery_fee).await {
+                Ok(promotion) => Some(promotion),
+                Err(res) => {
+                    metrics::record_checkout(method_label, "rejected");
+                    return res;
                 }
             }
-        } else {
-            return HttpResponse::BadRequest().json(ErrorResponse { message: "Mpesa Express phone number required".to_string() });
         }
-    } else if req.payment_method == "bank" {
-        if let Some(account) = &req.bank_account {
-            log::info!("Processing bank transfer for account {}", account);
-            "Paid"
-        } else {
-            return HttpResponse::BadRequest().json(ErrorResponse { message: "Bank account required".to_string() });
+        _ => None,
+    };
+    let discount = promotion.as_ref().map(|(_, discount)| discount.clone());
+    let total = (items_total + delivery_fee - discount.as_ref().map_or(0.0, |d| d.amount)).max(0.0);
+
+    // Then any loyalty points, only as many as the total needs
+    let points_redeemed = match req.redeem_points.filter(|p| *p > 0) {
+        Some(requested) => {
+            if loyalty_config.point_value <= 0.0 {
+                metrics::record_checkout(method_label, "rejected");
+                return HttpResponse::BadRequest().json(ErrorResponse { message: "Points can't be redeemed right now".to_string() });
+            }
+            let balance = match current_balance(loyalty.get_ref(), &user_email).await {
+                Ok(balance) => balance,
+                Err(res) => {
+                    metrics::record_checkout(method_label, "error");
+                    return res;
+                }
+            };
+            if (requested as i64) > balance {
+                metrics::record_checkout(method_label, "rejected");
+                return HttpResponse::BadRequest().json(ErrorResponse { message: format!("You only have {} points", balance.max(0)) });
+            }
+            requested.min((total / loyalty_config.point_value).ceil() as u32)
+        }
+        None => 0,
+    };
+    let points_discount = (points_redeemed as f64 * loyalty_config.point_value).min(total);
+    let total = total - points_discount;
+
+    // 5. Hold kitchen capacity for the chosen time
+    let reservations = match reserve_order_slots(slots.get_ref(), &slot_config, &catalog, &items, fulfilment.as_ref()).await {
+        Ok(reservations) => reservations,
+        Err(res) => {
+            metrics::record_checkout(method_label, "rejected");
+            return res;
+        }
+    };
+
+    // 6. Hold stock for the day the order is collected or delivered
+    let date = stock_date(&slot_config, fulfilment.as_ref().and_then(Fulfilment::requested_time));
+    let stock_reservations = match reserve_stock(stock.get_ref(), &catalog, &items, date).await {
+        Ok(stock_reservations) => stock_reservations,
+        Err(res) => {
+            release_slots(slots.get_ref(), &reservations).await;
+            metrics::record_checkout(method_label, "rejected");
+            return res;
         }
-    } else {
-        return HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid payment method specified".to_string() });
     };
 
-    // 3. Create Order Record
+    // 7. Use up the promo code
+    if let Some((promotion, _)) = &promotion {
+        if let Err(res) = redeem_promotion(promotions.get_ref(), promotion, &user_email).await {
+            release_slots(slots.get_ref(), &reservations).await;
+            release_stock(stock.get_ref(), &stock_reservations).await;
+            metrics::record_checkout(method_label, "rejected");
+            return res;
+        }
+    }
+
+    // 8. Spend the points
+    let order_id = ObjectId::new();
+    if points_redeemed > 0 {
+        if let Err(res) = spend_points(loyalty.get_ref(), &user_email, order_id, points_redeemed).await {
+            release_slots(slots.get_ref(), &reservations).await;
+            release_stock(stock.get_ref(), &stock_reservations).await;
+            release_promotion(promotions.get_ref(), discount.as_ref(), &user_email).await;
+            metrics::record_checkout(method_label, "rejected");
+            return res;
+        }
+    }
+
+    // 9. Check how the order will be paid
+    let payment = match check_payment(&req, total, &user_email, limits.get_ref(), &limit_config, method_label).await {
+        Ok(payment) => payment,
+        Err(res) => {
+            release_slots(slots.get_ref(), &reservations).await;
+            release_stock(stock.get_ref(), &stock_reservations).await;
+            release_promotion(promotions.get_ref(), discount.as_ref(), &user_email).await;
+            refund_points(loyalty.get_ref(), &user_email, order_id, points_redeemed).await;
+            return res;
+        }
+    };
+
+    // 10. Create Order Record, before any prompt reaches the customer's phone
     let new_order = Order {
-        id: None, // MongoDB will generate this
+        id: Some(order_id),
         user_email: user_email.clone(),
-        items: req.items.iter().map(|i| OrderItem {
-            item_id: i.item_id.clone(),
-            title: i.title.clone(),
-            quantity: i.quantity,
-            price: i.price,
-            image_src: i.image_src.clone(),
-        }).collect(),
-        total: req.total,
-        status: status.to_string(),
+        items,
+        total,
+        status: match payment {
+            Payment::Settled => STATUS_PAID,
+            Payment::StkPush(_) => STATUS_PENDING,
+        }.to_string(),
         payment_method: req.payment_method.clone(),
         created_at: Utc::now(),
+        fulfilment,
+        delivery_fee,
+        discount: discount.clone(),
+        points_redeemed,
+        points_discount,
+        slot_reservations: reservations.clone(),
+        stock_reservations: stock_reservations.clone(),
+        ..Default::default()
     };
 
-    let collection = get_order_collection(&client);
-    match collection.insert_one(new_order, None).await {
-        Ok(_) => HttpResponse::Ok().json(json!({ "message": "Checkout successful!", "status": status })),
-        Err(e) => {
-            log::error!("Failed to save order: {}", e);
-            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to save order".to_string() })
+    if let Err(e) = orders.insert(&new_order).await {
+        tracing::error!(error = %e, "Failed to save order");
+        release_slots(slots.get_ref(), &reservations).await;
+        release_stock(stock.get_ref(), &stock_reservations).await;
+        release_promotion(promotions.get_ref(), discount.as_ref(), &user_email).await;
+        refund_points(loyalty.get_ref(), &user_email, order_id, points_redeemed).await;
+        metrics::record_checkout(method_label, "error");
+        return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to save order".to_string() });
+    }
+
+    // 11. Process Payment (M-Pesa only takes whole shillings)
+    let mut status = new_order.status.clone();
+    if let Payment::StkPush(phone) = &payment {
+        tracing::info!(phone = %phone, "Initiating Mpesa Express STK Push");
+        match send_stk_push(phone, total.ceil() as u32).await {
+            Ok(res) => {
+                tracing::info!(checkout_request_id = ?res.checkout_request_id, "STK Push success");
+                match &res.checkout_request_id {
+                    Some(checkout_request_id) => match orders.set_payment_request(order_id, checkout_request_id).await {
+                        Ok(true) => status = STATUS_PAYMENT_INITIATED.to_string(),
+                        Ok(false) => tracing::error!(order_id = %order_id, "Order changed before its STK push was recorded"),
+                        Err(e) => tracing::error!(order_id = %order_id, error = %e, "Failed to record STK push on order"),
+                    },
+                    None => tracing::warn!(order_id = %order_id, "STK push accepted without a request id; order left pending"),
+                }
+            },
+            Err(e) => {
+                // Nothing was charged: cancel the order and give back what it holds
+                tracing::error!(error = %e, "STK Push failed");
+                match orders.cancel(order_id, STATUS_PENDING, None).await {
+                    Ok(Some(cancelled)) => release_order_holds(orders.get_ref(), stock.get_ref(), slots.get_ref(), promotions.get_ref(), loyalty.get_ref(), &cancelled).await,
+                    Ok(None) => tracing::warn!(order_id = %order_id, "Order changed before it could be cancelled"),
+                    Err(e) => tracing::error!(order_id = %order_id, error = %e, "Failed to cancel order after STK push failure"),
+                }
+                metrics::record_checkout(method_label, "payment_failed");
+                return HttpResponse::BadRequest().json(ErrorResponse { message: format!("Payment failed: {}", e) });
+            }
         }
     }
+
+    metrics::record_checkout(method_label, "success");
+    HttpResponse::Ok().json(json!({
+        "message": "Checkout successful!",
+        "status": status,
+        "order_id": order_id.to_hex(),
+        "items_total": items_total,
+        "delivery_fee": delivery_fee,
+        "discount": discount,
+        "points_redeemed": points_redeemed,
+        "points_discount": points_discount,
+        "total": total,
+        "allergy_warnings": warnings,
+    }))
 }
 
-/// GET /api/orders
-/// Fetches orders for the logged-in user
-pub async fn get_user_orders(
-    client: web::Data<Client>,
+/// POST /api/cart/apply-promo
+/// Checks a promo code against the cart and shows the totals it would give.
+/// Nothing is reserved; checkout checks the code again.
+pub async fn apply_promo(
+    products: web::Data<dyn ProductRepo>,
+    promotions: web::Data<dyn PromotionRepo>,
+    fulfilment_config: web::Data<FulfilmentConfig>,
+    req: web::Json<ApplyPromoRequest>,
     http_req: HttpRequest
 ) -> impl Responder {
-    // 1. Authenticate User
+    // 1. Authenticate User (per-customer limits)
     let user_email = match get_user_email_from_req(&http_req) {
         Ok(email) => email,
         Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
     };
 
-    let collection = get_order_collection(&client);
-    let filter = doc! { "user_email": user_email };
-    
-    // Find orders
-    let mut cursor = match collection.find(filter, None).await {
-        Ok(cursor) => cursor,
-        Err(e) => return HttpResponse::InternalServerError().json(ErrorResponse { message: format!("Database error: {}", e) }),
+    // 2. Price the cart as checkout would
+    let item_ids = catalog_ids(&req.items);
+    let catalog = match products.find_by_ids(&item_ids).await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading products for promo");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+    let items = match price_items(&req.items, &catalog) {
+        Ok(items) => items,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+    let items_total = items.iter().fold(0.0, |sum, i| sum + i.price * i.quantity as f64);
+    let delivery_fee = match &req.fulfilment {
+        Some(fulfilment) => match resolve_fulfilment(fulfilment, &fulfilment_config) {
+            Ok((_, fee)) => fee,
+            Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+        },
+        None => 0.0,
     };
 
-    // Collect into vector
-    let mut orders = Vec::new();
-    while let Some(result) = cursor.next().await {
-        match result {
-            Ok(order) => orders.push(order),
-            Err(e) => log::error!("Error deserializing order: {}", e),
+    // 3. Apply the code
+    let (promotion, discount) = match evaluate_promotion(promotions.get_ref(), &req.code, &user_email, &cart_lines(&items, &catalog), delivery_fee).await {
+        Ok(result) => result,
+        Err(res) => return res,
+    };
+
+    HttpResponse::Ok().json(json!({
+        "code": promotion.code,
+        "description": promotion.description,
+        "items_total": items_total,
+        "delivery_fee": delivery_fee,
+        "discount": discount,
+        "total": (items_total + delivery_fee - discount.amount).max(0.0),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::Request;
+    use actix_web::{
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        http::StatusCode,
+        middleware::from_fn,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        App,
+    };
+
+    use crate::config::IdempotencyConfig;
+    use crate::middleware::idempotency::{idempotent, IDEMPOTENT_REPLAYED_HEADER};
+    use crate::models::order::OrderItemOption;
+    use crate::models::product::{custom_cake, menu_items, menu_specials};
+    use crate::repository::order::OrderFilter;
+    use crate::repository::Repositories;
+    use crate::test_util::{bearer, call};
+
+    fn catalog() -> Vec<Product> {
+        std::iter::once(custom_cake()).chain(menu_specials()).chain(menu_items()).collect()
+    }
+
+    fn cart_item(item_id: &str, price: f64) -> OrderItem {
+        OrderItem {
+            item_id: item_id.to_string(),
+            title: item_id.to_string(),
+            quantity: 2,
+            price,
+            image_src: String::new(),
+            options: Vec::new(),
+            inscription: None,
+            reference_image: None,
+            allergens: Vec::new(),
         }
     }
 
-    HttpResponse::Ok().json(orders)
-}
\ No newline at end of file
+    #[test]
+    fn prices_come_from_the_catalog() {
+        let items = price_items(&[cart_item("butter-cookies", 0.01)], &catalog()).unwrap();
+        assert_eq!(items[0].price, 2.0);
+        assert_eq!(items[0].title, "Butter Cookies");
+        assert_eq!(items[0].allergens, ["gluten", "dairy", "eggs"]);
+    }
+
+    #[test]
+    fn unknown_and_unpriced_items_are_refused() {
+        assert_eq!(price_items(&[cart_item("made-up-cake", 0.01)], &catalog()).unwrap_err(), "made-up-cake isn't on our menu");
+        assert!(price_items(&[cart_item("chocolate-cake", 30.0)], &catalog()).is_err());
+        assert!(price_items(&[], &catalog()).is_err());
+    }
+
+    #[test]
+    fn old_custom_cake_ids_are_the_custom_cake() {
+        let mut cake = cart_item("custom-cake-1718000000000", 1.0);
+        cake.options = ["size:6inch", "base:vanilla", "frosting:vanilla-bc", "filling:none"].iter().map(|choice| {
+            let (group, option) = choice.split_once(':').unwrap();
+            OrderItemOption { group: group.to_string(), option: option.to_string(), label: String::new(), price_delta: 0.0 }
+        }).collect();
+        assert!(catalog_ids(std::slice::from_ref(&cake)).contains(&"custom-cake".to_string()));
+
+        let items = price_items(&[cake], &catalog()).unwrap();
+        assert_eq!(items[0].item_id, "custom-cake");
+        assert_eq!(items[0].price, 30.0);
+    }
+
+    async fn app(repos: &Repositories) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+        init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .app_data(web::Data::new(RateLimitConfig::from_env()))
+                .app_data(web::Data::new(IdempotencyConfig::from_env()))
+                .app_data(web::Data::new(FulfilmentConfig::from_env()))
+                .app_data(web::Data::new(SlotConfig::from_env()))
+                .app_data(web::Data::new(LoyaltyConfig::from_env()))
+                .service(
+                    web::resource("/api/cart/checkout")
+                        .wrap(from_fn(idempotent))
+                        .route(web::post().to(process_checkout))
+                )
+        ).await
+    }
+
+    fn checkout(quantity: u32) -> TestRequest {
+        TestRequest::post().uri("/api/cart/checkout")
+            .insert_header(("Authorization", bearer("jane@example.com")))
+            .set_json(json!({
+                "payment_method": "bank",
+                "bank_account": "0123456789",
+                "items": [{ "item_id": "butter-cookies", "title": "Butter Cookies", "quantity": quantity, "price": 0.01, "image_src": "" }],
+                "total": 0.02,
+            }))
+    }
+
+    async fn placed_orders(repos: &Repositories) -> u64 {
+        let filter = OrderFilter { user_email: "jane@example.com".to_string(), ..Default::default() };
+        repos.orders.find_page(&filter, 0, 10).await.unwrap().total
+    }
+
+    #[actix_web::test]
+    async fn checkout_places_a_paid_order_at_catalog_prices() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+
+        let (status, body) = call(&app, checkout(2)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["status"], STATUS_PAID);
+        assert_eq!(body["total"], 4.0);
+
+        let order_id = ObjectId::parse_str(body["order_id"].as_str().unwrap()).unwrap();
+        let order = repos.orders.find_by_id(order_id).await.unwrap().unwrap();
+        assert_eq!(order.status, STATUS_PAID);
+        assert_eq!(order.items[0].price, 2.0);
+        assert_eq!(order.items[0].allergens, ["gluten", "dairy", "eggs"]);
+    }
+
+    #[actix_web::test]
+    async fn retried_checkouts_replay_the_first_order() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+        let with_key = |quantity| checkout(quantity).insert_header(("Idempotency-Key", "cart-42"));
+
+        let (status, first) = call(&app, with_key(2)).await;
+        assert_eq!(status, StatusCode::OK, "{}", first);
+
+        let res = call_service(&app, with_key(2).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(), "true");
+        let replayed: serde_json::Value = read_body_json(res).await;
+        assert_eq!(replayed["order_id"], first["order_id"]);
+
+        // The same key can't be reused for a different cart
+        assert_eq!(call(&app, with_key(3)).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(placed_orders(&repos).await, 1);
+
+        // A new key is a new order
+        let (status, _) = call(&app, checkout(2).insert_header(("Idempotency-Key", "cart-43"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(placed_orders(&repos).await, 2);
+    }
+}
@@ -1,11 +1,10 @@
 use actix_web::{web, HttpResponse, Responder, HttpRequest};
-use mongodb::{Client, Collection, bson::doc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use futures::stream::StreamExt;
 
 use crate::models::favorite::Favorite;
-use crate::utils::jwt::decode_token;
+use crate::repository::{FavoriteRepo, ProductRepo, RepoError};
+use crate::utils::jwt::get_user_email_from_req;
 
 #[derive(Deserialize)]
 pub struct AddFavoriteRequest {
@@ -20,30 +19,10 @@ struct ErrorResponse {
     message: String,
 }
 
-// Helper to get the favorites collection
-fn get_favorite_collection(client: &web::Data<Client>) -> Collection<Favorite> {
-    client.database("yetta_db").collection("favorites")
-}
-
-// Helper to extract user email from JWT
-fn get_user_email_from_req(req: &HttpRequest) -> Result<String, String> {
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                match decode_token(token) {
-                    Ok(token_data) => return Ok(token_data.claims.sub),
-                    Err(_) => return Err("Invalid token".to_string()),
-                }
-            }
-        }
-    }
-    Err("No authorization header".to_string())
-}
-
 /// POST /api/favorites/add
 pub async fn add_favorite(
-    client: web::Data<Client>, 
+    favorites: web::Data<dyn FavoriteRepo>, 
+    products: web::Data<dyn ProductRepo>,
     req: web::Json<AddFavoriteRequest>,
     http_req: HttpRequest
 ) -> impl Responder {
@@ -52,32 +31,44 @@ pub async fn add_favorite(
         Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
     };
 
-    let collection = get_favorite_collection(&client);
-
-    // Check if already exists
-    let filter = doc! { "user_email": &user_email, "item_id": &req.item_id };
-    if let Ok(Some(_)) = collection.find_one(filter, None).await {
-        return HttpResponse::Conflict().json(json!({ "message": "Item already in favorites" }));
-    }
-
-    let new_favorite = Favorite {
-        id: None,
-        user_email,
-        item_id: req.item_id.clone(),
-        item_title: req.item_title.clone(),
-        item_image: req.item_image.clone(),
-        item_price: req.item_price,
+    // Catalog items use the catalog's title, image and price rather than the client's copy
+    let product = match products.find_by_ids(std::slice::from_ref(&req.item_id)).await {
+        Ok(found) => found.into_iter().next(),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading product for favorite");
+            None
+        }
+    };
+    let new_favorite = match product {
+        Some(product) => Favorite {
+            id: None,
+            user_email,
+            item_id: product.item_id,
+            item_title: product.title,
+            item_image: product.image_src,
+            item_price: product.price,
+        },
+        None => Favorite {
+            id: None,
+            user_email,
+            item_id: req.item_id.clone(),
+            item_title: req.item_title.clone(),
+            item_image: req.item_image.clone(),
+            item_price: req.item_price,
+        },
     };
 
-    match collection.insert_one(new_favorite, None).await {
+    // The unique (user_email, item_id) index rejects duplicates, even from double-submits
+    match favorites.insert(&new_favorite).await {
         Ok(_) => HttpResponse::Ok().json(json!({ "message": "Added to favorites" })),
+        Err(RepoError::Duplicate) => HttpResponse::Conflict().json(json!({ "message": "Item already in favorites" })),
         Err(e) => HttpResponse::InternalServerError().json(ErrorResponse { message: format!("Failed to add favorite: {}", e) }),
     }
 }
 
 /// DELETE /api/favorites/{item_id}
 pub async fn remove_favorite(
-    client: web::Data<Client>,
+    favorites: web::Data<dyn FavoriteRepo>,
     path: web::Path<String>,
     http_req: HttpRequest
 ) -> impl Responder {
@@ -87,12 +78,9 @@ pub async fn remove_favorite(
         Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
     };
 
-    let collection = get_favorite_collection(&client);
-    let filter = doc! { "user_email": user_email, "item_id": item_id };
-
-    match collection.delete_one(filter, None).await {
-        Ok(result) => {
-            if result.deleted_count == 1 {
+    match favorites.delete(&user_email, &item_id).await {
+        Ok(deleted) => {
+            if deleted {
                 HttpResponse::Ok().json(json!({ "message": "Removed from favorites" }))
             } else {
                 HttpResponse::NotFound().json(json!({ "message": "Favorite not found" }))
@@ -104,7 +92,7 @@ pub async fn remove_favorite(
 
 /// GET /api/favorites
 pub async fn get_favorites(
-    client: web::Data<Client>,
+    favorites: web::Data<dyn FavoriteRepo>,
     http_req: HttpRequest
 ) -> impl Responder {
     let user_email = match get_user_email_from_req(&http_req) {
@@ -112,21 +100,51 @@ pub async fn get_favorites(
         Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
     };
 
-    let collection = get_favorite_collection(&client);
-    let filter = doc! { "user_email": user_email };
-
-    let mut cursor = match collection.find(filter, None).await {
-        Ok(cursor) => cursor,
-        Err(e) => return HttpResponse::InternalServerError().json(ErrorResponse { message: format!("Database error: {}", e) }),
-    };
-
-    let mut favorites = Vec::new();
-    while let Some(result) = cursor.next().await {
-        match result {
-            Ok(fav) => favorites.push(fav),
-            Err(e) => log::error!("Error deserializing favorite: {}", e),
-        }
+    match favorites.find_by_user(&user_email).await {
+        Ok(list) => HttpResponse::Ok().json(list),
+        Err(e) => HttpResponse::InternalServerError().json(ErrorResponse { message: format!("Database error: {}", e) }),
     }
+}
 
-    HttpResponse::Ok().json(favorites)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    use crate::repository::Repositories;
+    use crate::test_util::{bearer, call};
+
+    #[actix_web::test]
+    async fn favorites_round_trip_with_catalog_details() {
+        let repos = Repositories::in_memory();
+        let app = test::init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .route("/api/favorites", web::get().to(get_favorites))
+                .route("/api/favorites/add", web::post().to(add_favorite))
+                .route("/api/favorites/{item_id}", web::delete().to(remove_favorite))
+        ).await;
+        let token = bearer("jane@example.com");
+        let add = || test::TestRequest::post().uri("/api/favorites/add").insert_header(("Authorization", token.clone())).set_json(json!({
+            "item_id": "party-platter",
+            "item_title": "Cheap Platter",
+            "item_image": "",
+            "item_price": 1.0,
+        }));
+
+        assert_eq!(call(&app, add()).await.0, StatusCode::OK);
+        assert_eq!(call(&app, add()).await.0, StatusCode::CONFLICT);
+
+        let (status, list) = call(&app, test::TestRequest::get().uri("/api/favorites").insert_header(("Authorization", token.clone()))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list[0]["item_title"], "Party Platter");
+        assert_eq!(list[0]["item_price"], 45.0);
+
+        let remove = || test::TestRequest::delete().uri("/api/favorites/party-platter").insert_header(("Authorization", token.clone()));
+        assert_eq!(call(&app, remove()).await.0, StatusCode::OK);
+        assert_eq!(call(&app, remove()).await.0, StatusCode::NOT_FOUND);
+
+        let anonymous = test::TestRequest::get().uri("/api/favorites");
+        assert_eq!(call(&app, anonymous).await.0, StatusCode::UNAUTHORIZED);
+    }
 }
@@ -0,0 +1,116 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use prometheus::{Encoder, TextEncoder};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config;
+use crate::handlers::mpesa;
+use crate::repository::HealthRepo;
+
+#[derive(Serialize)]
+struct ReadinessResponse {
+    ready: bool,
+    mongodb: String,
+    mpesa_configured: bool,
+    mpesa_missing: Vec<&'static str>,
+}
+
+/// GET /healthz
+/// Liveness: the process is up and serving requests
+pub async fn liveness() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// GET /readyz
+/// Readiness: MongoDB answers a ping. M-Pesa settings are reported but don't take the
+/// instance out of rotation, since bank transfer orders still work without them.
+pub async fn readiness(health: web::Data<dyn HealthRepo>) -> impl Responder {
+    let mongodb = match health.ping().await {
+        Ok(_) => "ok".to_string(),
+        Err(e) => {
+            tracing::error!(error = %e, "Readiness check: MongoDB ping failed");
+            "unavailable".to_string()
+        }
+    };
+    let mpesa_missing = mpesa::missing_config();
+
+    let response = ReadinessResponse {
+        ready: mongodb == "ok",
+        mongodb,
+        mpesa_configured: mpesa_missing.is_empty(),
+        mpesa_missing,
+    };
+
+    if response.ready {
+        HttpResponse::Ok().json(response)
+    } else {
+        HttpResponse::ServiceUnavailable().json(response)
+    }
+}
+
+/// GET /version
+/// Build information, stamped in by build.rs
+pub async fn version() -> impl Responder {
+    let built_at = env!("BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        .map(|dt| dt.to_rfc3339());
+
+    HttpResponse::Ok().json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("GIT_SHA"),
+        "built_at": built_at,
+    }))
+}
+
+/// GET /metrics
+/// Prometheus text exposition of every registered metric, for scrapers sending
+/// `Authorization: Bearer <METRICS_TOKEN>`. Not served while METRICS_TOKEN is unset.
+pub async fn export_metrics(http_req: HttpRequest) -> impl Responder {
+    let Some(expected) = config::metrics_token() else {
+        return HttpResponse::NotFound().finish();
+    };
+    let given = http_req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !given.is_some_and(|token| mpesa::secrets_match(token, &expected)) {
+        return HttpResponse::Unauthorized().insert_header((header::WWW_AUTHENTICATE, "Bearer")).finish();
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!(error = %e, "Failed to encode metrics");
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    use crate::test_util::call;
+
+    #[actix_web::test]
+    async fn metrics_need_the_scrape_token() {
+        std::env::set_var("METRICS_TOKEN", "scrape-token");
+        let app = test::init_service(App::new().route("/metrics", web::get().to(export_metrics))).await;
+        let scrape = |auth: Option<&str>| {
+            let req = test::TestRequest::get().uri("/metrics");
+            match auth {
+                Some(auth) => req.insert_header(("Authorization", auth.to_string())),
+                None => req,
+            }
+        };
+
+        assert_eq!(call(&app, scrape(None)).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(&app, scrape(Some("Bearer wrong-token"))).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(&app, scrape(Some("Bearer scrape-token"))).await.0, StatusCode::OK);
+    }
+}
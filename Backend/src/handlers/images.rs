@@ -0,0 +1,111 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest, http::header};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::storage::FileStorage;
+use crate::utils::imaging::{self, Variant};
+
+// Variants never change once written, so caches can keep them forever
+const CACHE_FOREVER: &str = "public, max-age=31536000, immutable";
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn storage_key(id: &Uuid, variant: Variant) -> String {
+    format!("products/{}/{}.webp", id, variant.name())
+}
+
+/// Public URL of one variant, for use as a product's `image_src`
+pub fn image_url(id: &Uuid, variant: Variant) -> String {
+    format!("/api/images/{}/{}.webp", id, variant.name())
+}
+
+/// POST /api/admin/images
+/// Takes a product photo as the raw request body (JPEG, PNG or WebP) and stores
+/// thumbnail, card and full-size WebP versions of it. Returns their URLs.
+pub async fn upload_product_image(
+    storage: web::Data<dyn FileStorage>,
+    body: web::Bytes,
+) -> impl Responder {
+    // 1. Only real images (the size limit is enforced by the route's PayloadConfig)
+    if imaging::sniff(&body).is_none() {
+        return HttpResponse::UnsupportedMediaType().json(ErrorResponse { message: "Upload a JPEG, PNG or WebP image".to_string() });
+    }
+
+    // 2. Decode and resize off the async workers; this is CPU heavy
+    let rendered = web::block(move || {
+        let image = imaging::decode(&body)?;
+        let variants = Variant::ALL.into_iter()
+            .map(|variant| Ok((variant, imaging::encode_variant(&image, variant)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok::<_, String>((image.width(), image.height(), variants))
+    }).await;
+    let (width, height, variants) = match rendered {
+        Ok(Ok(rendered)) => rendered,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Rejected product image");
+            return HttpResponse::UnprocessableEntity().json(ErrorResponse { message: "The image couldn't be read".to_string() });
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Image processing task failed");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to process image".to_string() });
+        }
+    };
+
+    // 3. Store every variant
+    let id = Uuid::new_v4();
+    let mut urls = BTreeMap::new();
+    for (variant, bytes) in &variants {
+        if let Err(e) = storage.put(&storage_key(&id, *variant), bytes).await {
+            tracing::error!(error = %e, "Failed to store product image");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to store image".to_string() });
+        }
+        urls.insert(variant.name(), image_url(&id, *variant));
+    }
+
+    tracing::info!(image_id = %id, width, height, "Product image uploaded");
+    HttpResponse::Created().json(json!({ "id": id, "width": width, "height": height, "variants": urls }))
+}
+
+/// GET /api/images/{id}/{variant}.webp
+/// Serves a product image variant with long-lived cache headers
+pub async fn get_product_image(
+    storage: web::Data<dyn FileStorage>,
+    path: web::Path<(String, String)>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    let (id, file) = path.into_inner();
+    let not_found = || HttpResponse::NotFound().json(ErrorResponse { message: "Image not found".to_string() });
+    let (Ok(id), Some(variant)) = (Uuid::parse_str(&id), file.strip_suffix(".webp").and_then(Variant::from_name)) else {
+        return not_found();
+    };
+
+    // The id and variant fully identify the bytes
+    let etag = format!("\"{}-{}\"", id.simple(), variant.name());
+    let cached = http_req.headers().get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if cached {
+        return HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, CACHE_FOREVER))
+            .finish();
+    }
+
+    match storage.get(&storage_key(&id, variant)).await {
+        Ok(Some(bytes)) => HttpResponse::Ok()
+            .content_type("image/webp")
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, CACHE_FOREVER))
+            .body(bytes),
+        Ok(None) => not_found(),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read product image");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to read image".to_string() })
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use chrono::{Duration, Utc};
+use mongodb::bson::oid::ObjectId;
+use uuid::Uuid;
+
+use crate::config::LoyaltyConfig;
+use crate::models::loyalty::{LoyaltyEntry, LoyaltyKind};
+use crate::models::order::Order;
+use crate::models::user::normalise_email;
+use crate::repository::{LoyaltyRepo, UserRepo};
+use crate::utils::jwt::get_user_email_from_req;
+
+const RECENT_ENTRIES: i64 = 50;
+const MAX_NOTE_LEN: usize = 200;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AdjustPointsRequest {
+    email: String,
+    points: i64,
+    note: String,
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    tracing::error!(error = %e, "Database error in loyalty");
+    HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+}
+
+fn entry(id: String, user_email: &str, kind: LoyaltyKind, points: i64, order_id: Option<ObjectId>) -> LoyaltyEntry {
+    LoyaltyEntry {
+        id,
+        user_email: user_email.to_string(),
+        kind,
+        points,
+        order_id,
+        note: None,
+        created_at: Utc::now(),
+        expires_at: None,
+    }
+}
+
+// Lapse earned points past their expiry that haven't been spent, one expiry entry per
+// earning ("expire|<earn id>"). Points are redeemed oldest first, so redemptions (less
+// those refunded) count against the earliest earnings before any of them expire, as do
+// the per-customer daily expiries recorded before each earning got its own. Whatever a
+// cancellation took back is left out of its earning.
+async fn expire_points(loyalty: &dyn LoyaltyRepo, user_email: &str) -> Result<(), HttpResponse> {
+    let now = Utc::now();
+    let entries = loyalty.entries(user_email, None).await.map_err(database_error)?;
+    let mut earned: Vec<&LoyaltyEntry> = entries.iter().filter(|e| e.kind == LoyaltyKind::Earn).collect();
+    earned.sort_by_key(|e| (e.expires_at, e.created_at));
+    let expired_earning = |earn: &LoyaltyEntry| entries.iter().find(|e| e.id == format!("expire|{}", earn.id));
+    let legacy_expired: i64 = entries.iter()
+        .filter(|e| e.kind == LoyaltyKind::Expire && !earned.iter().any(|earn| e.id == format!("expire|{}", earn.id)))
+        .map(|e| -e.points)
+        .sum();
+    let redeemed: i64 = entries.iter()
+        .filter(|e| e.kind == LoyaltyKind::Redeem)
+        .map(|e| -e.points)
+        .sum();
+    let refunded: i64 = entries.iter()
+        .filter(|e| e.id.starts_with("refund-redeem|"))
+        .map(|e| e.points)
+        .sum();
+
+    let mut used = (redeemed - refunded).max(0) + legacy_expired;
+    let mut balance = entries.iter().map(|e| e.points).sum::<i64>();
+    for earn in earned {
+        let reversed: i64 = entries.iter()
+            .filter(|e| e.id.starts_with("reverse-earn|") && e.order_id.is_some() && e.order_id == earn.order_id)
+            .map(|e| -e.points)
+            .sum();
+        let already_expired = expired_earning(earn);
+        let mut remaining = (earn.points - reversed).max(0) + already_expired.map_or(0, |e| e.points);
+        let covered = used.min(remaining);
+        used -= covered;
+        remaining -= covered;
+
+        let lapsed = remaining.min(balance);
+        if already_expired.is_some() || earn.expires_at.is_none_or(|at| at > now) || lapsed <= 0 {
+            continue;
+        }
+        let mut expiry = entry(format!("expire|{}", earn.id), user_email, LoyaltyKind::Expire, -lapsed, earn.order_id);
+        expiry.note = Some("Points expired".to_string());
+        match loyalty.record(&expiry).await {
+            Ok(true) => {
+                tracing::info!(user = %user_email, earned = %earn.id, points = lapsed, "Loyalty points expired");
+                balance -= lapsed;
+            }
+            Ok(false) => (),
+            Err(e) => return Err(database_error(e)),
+        }
+    }
+    Ok(())
+}
+
+/// The customer's points after expiring any that have lapsed
+pub async fn current_balance(loyalty: &dyn LoyaltyRepo, user_email: &str) -> Result<i64, HttpResponse> {
+    expire_points(loyalty, user_email).await?;
+    loyalty.balance(user_email).await.map_err(database_error)
+}
+
+/// Take points off the customer for an order; fails if they've been spent elsewhere since the balance was checked.
+/// An order can spend and be refunded more than once (a failed payment retried), so each entry gets its own id.
+pub async fn spend_points(loyalty: &dyn LoyaltyRepo, user_email: &str, order_id: ObjectId, points: u32) -> Result<(), HttpResponse> {
+    let id = format!("redeem|{}|{}", order_id.to_hex(), Uuid::new_v4());
+    let spend = entry(id, user_email, LoyaltyKind::Redeem, -(points as i64), Some(order_id));
+    match loyalty.spend(&spend).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(HttpResponse::Conflict().json(ErrorResponse { message: "Your points balance changed; please try again".to_string() })),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// Give back points spent on an order that didn't go through
+pub async fn refund_points(loyalty: &dyn LoyaltyRepo, user_email: &str, order_id: ObjectId, points: u32) {
+    if points == 0 {
+        return;
+    }
+    let id = format!("refund-redeem|{}|{}", order_id.to_hex(), Uuid::new_v4());
+    let refund = entry(id, user_email, LoyaltyKind::Adjust, points as i64, Some(order_id));
+    if let Err(e) = loyalty.record(&refund).await {
+        tracing::error!(order_id = %order_id, error = %e, "Failed to refund loyalty points");
+    }
+}
+
+/// Credit the points a paid order earns. Recorded once per order, however often M-Pesa repeats the callback.
+pub async fn credit_order_points(loyalty: &dyn LoyaltyRepo, config: &LoyaltyConfig, order: &Order) {
+    let Some(order_id) = order.id else { return };
+    let points = config.points_for(order.total);
+    if points <= 0 {
+        return;
+    }
+
+    let mut earn = entry(format!("earn|{}", order_id.to_hex()), &order.user_email, LoyaltyKind::Earn, points, Some(order_id));
+    earn.expires_at = Some(earn.created_at + Duration::days(config.expiry_days));
+    match loyalty.record(&earn).await {
+        Ok(true) => tracing::info!(order_id = %order_id, points, "Loyalty points earned"),
+        Ok(false) => (),
+        Err(e) => tracing::error!(order_id = %order_id, error = %e, "Failed to credit loyalty points"),
+    }
+}
+
+/// Take back the points a cancelled order earned, as many as the customer still has.
+/// Spent points are refunded with the order's other holds.
+pub async fn reverse_earned_points(loyalty: &dyn LoyaltyRepo, order: &Order) {
+    let Some(order_id) = order.id else { return };
+    let earned = match loyalty.find(&format!("earn|{}", order_id.to_hex())).await {
+        Ok(Some(earned)) => earned,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!(order_id = %order_id, error = %e, "Failed to look up earned loyalty points");
+            return;
+        }
+    };
+    // Points already spent stay spent; `spend` refuses if another spend got there first, so read the balance again
+    for _ in 0..3 {
+        let balance = match loyalty.balance(&order.user_email).await {
+            Ok(balance) => balance,
+            Err(e) => {
+                tracing::error!(order_id = %order_id, error = %e, "Failed to read loyalty balance");
+                return;
+            }
+        };
+        let points = earned.points.min(balance);
+        if points <= 0 {
+            tracing::info!(order_id = %order_id, earned = earned.points, "Earned loyalty points were already spent");
+            return;
+        }
+        let mut reversal = entry(format!("reverse-earn|{}", order_id.to_hex()), &order.user_email, LoyaltyKind::Adjust, -points, Some(order_id));
+        reversal.note = Some("Order refunded".to_string());
+        match loyalty.spend(&reversal).await {
+            Ok(true) => {
+                if points < earned.points {
+                    tracing::info!(order_id = %order_id, earned = earned.points, reversed = points, "Some earned loyalty points were already spent");
+                }
+                return;
+            }
+            Ok(false) if loyalty.find(&reversal.id).await.ok().flatten().is_some() => return,
+            Ok(false) => (),
+            Err(e) => {
+                tracing::error!(order_id = %order_id, error = %e, "Failed to reverse loyalty points");
+                return;
+            }
+        }
+    }
+    tracing::error!(order_id = %order_id, "Loyalty balance kept changing; earned points not reversed");
+}
+
+/// GET /api/loyalty
+/// The caller's points, what they're worth at checkout, and recent activity
+pub async fn get_loyalty(
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    config: web::Data<LoyaltyConfig>,
+    http_req: HttpRequest
+) -> impl Responder {
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+
+    let points = match current_balance(loyalty.get_ref(), &user_email).await {
+        Ok(points) => points,
+        Err(res) => return res,
+    };
+    match loyalty.entries(&user_email, Some(RECENT_ENTRIES)).await {
+        Ok(entries) => HttpResponse::Ok().json(json!({
+            "points": points,
+            "value": points.max(0) as f64 * config.point_value,
+            "spend_per_point": config.spend_per_point,
+            "point_value": config.point_value,
+            "entries": entries,
+        })),
+        Err(e) => database_error(e),
+    }
+}
+
+/// POST /api/admin/loyalty/adjust
+/// Adds or removes points by hand, e.g. goodwill or correcting a mistake
+pub async fn adjust_points(
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    users: web::Data<dyn UserRepo>,
+    req: web::Json<AdjustPointsRequest>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Validate
+    let note = req.note.trim();
+    if req.points == 0 {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "Points must not be zero".to_string() });
+    }
+    if note.is_empty() || note.len() > MAX_NOTE_LEN {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: format!("A note of at most {} characters is required", MAX_NOTE_LEN) });
+    }
+    let email = &normalise_email(&req.email);
+    match users.find_by_email(email).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return HttpResponse::NotFound().json(ErrorResponse { message: "User not found".to_string() }),
+        Err(e) => return database_error(e),
+    }
+
+    // 2. Record it, never taking the balance below zero
+    let balance = match current_balance(loyalty.get_ref(), email).await {
+        Ok(balance) => balance,
+        Err(res) => return res,
+    };
+    let admin = get_user_email_from_req(&http_req).unwrap_or_default();
+    let mut adjustment = entry(format!("adjust|{}", Uuid::new_v4()), email, LoyaltyKind::Adjust, req.points, None);
+    adjustment.note = Some(format!("{} (by {})", note, admin));
+
+    let recorded = if req.points < 0 {
+        loyalty.spend(&adjustment).await
+    } else {
+        loyalty.record(&adjustment).await
+    };
+    match recorded {
+        Ok(true) => {
+            tracing::info!(user = %email, points = req.points, admin = %admin, "Loyalty points adjusted");
+            HttpResponse::Ok().json(json!({ "points": balance + req.points, "entry": adjustment }))
+        }
+        Ok(false) => HttpResponse::BadRequest().json(ErrorResponse { message: format!("{} only has {} points", email, balance) }),
+        Err(e) => database_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::loyalty::MemoryLoyaltyRepo;
+
+    const EMAIL: &str = "jane@example.com";
+
+    async fn add(loyalty: &MemoryLoyaltyRepo, id: &str, kind: LoyaltyKind, points: i64, order_id: Option<ObjectId>, expires_in_days: Option<i64>) {
+        let mut e = entry(id.to_string(), EMAIL, kind, points, order_id);
+        e.expires_at = expires_in_days.map(|days| Utc::now() + Duration::days(days));
+        assert!(loyalty.record(&e).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn lapsed_points_expire_after_redemptions() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        let order = ObjectId::new();
+        add(&loyalty, "earn|a", LoyaltyKind::Earn, 100, Some(order), Some(-1)).await;
+        add(&loyalty, "earn|b", LoyaltyKind::Earn, 20, Some(ObjectId::new()), Some(30)).await;
+        spend_points(&loyalty, EMAIL, order, 30).await.unwrap();
+
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 20);
+        // Running again expires nothing more
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 20);
+    }
+
+    #[actix_web::test]
+    async fn refunded_redemptions_do_not_shield_points() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        let order = ObjectId::new();
+        add(&loyalty, "earn|a", LoyaltyKind::Earn, 100, Some(ObjectId::new()), Some(-1)).await;
+        spend_points(&loyalty, EMAIL, order, 30).await.unwrap();
+        refund_points(&loyalty, EMAIL, order, 30).await;
+
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn earlier_expiries_are_not_counted_twice() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        add(&loyalty, "earn|a", LoyaltyKind::Earn, 50, Some(ObjectId::new()), Some(-40)).await;
+        add(&loyalty, "expire|earlier", LoyaltyKind::Expire, -50, None, None).await;
+        add(&loyalty, "earn|b", LoyaltyKind::Earn, 40, Some(ObjectId::new()), Some(-1)).await;
+        add(&loyalty, "earn|c", LoyaltyKind::Earn, 15, Some(ObjectId::new()), Some(30)).await;
+
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 15);
+    }
+
+    #[actix_web::test]
+    async fn reversed_earnings_do_not_expire() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        let order = ObjectId::new();
+        add(&loyalty, "earn|a", LoyaltyKind::Earn, 60, Some(order), Some(-1)).await;
+        add(&loyalty, "reverse-earn|a", LoyaltyKind::Adjust, -60, Some(order), None).await;
+        add(&loyalty, "earn|b", LoyaltyKind::Earn, 10, Some(ObjectId::new()), Some(30)).await;
+
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 10);
+        assert!(loyalty.entries(EMAIL, None).await.unwrap().iter().all(|e| e.kind != LoyaltyKind::Expire));
+    }
+
+    #[actix_web::test]
+    async fn spending_more_than_the_balance_fails() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        add(&loyalty, "earn|a", LoyaltyKind::Earn, 10, Some(ObjectId::new()), Some(30)).await;
+        assert!(spend_points(&loyalty, EMAIL, ObjectId::new(), 11).await.is_err());
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 10);
+    }
+
+    #[actix_web::test]
+    async fn each_earning_expires_on_its_own() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        add(&loyalty, "earn|a", LoyaltyKind::Earn, 30, Some(ObjectId::new()), Some(-2)).await;
+        add(&loyalty, "earn|c", LoyaltyKind::Earn, 5, Some(ObjectId::new()), Some(30)).await;
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 5);
+
+        // A second earning lapsing the same day still expires
+        add(&loyalty, "earn|b", LoyaltyKind::Earn, 20, Some(ObjectId::new()), Some(-1)).await;
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 5);
+        assert!(loyalty.find("expire|earn|a").await.unwrap().is_some());
+        assert_eq!(loyalty.find("expire|earn|b").await.unwrap().unwrap().points, -20);
+    }
+
+    #[actix_web::test]
+    async fn reversing_spent_earnings_stops_at_zero() {
+        let loyalty = MemoryLoyaltyRepo::default();
+        let order = Order { id: Some(ObjectId::new()), user_email: EMAIL.to_string(), ..Default::default() };
+        add(&loyalty, &format!("earn|{}", order.id.unwrap().to_hex()), LoyaltyKind::Earn, 50, order.id, Some(30)).await;
+        spend_points(&loyalty, EMAIL, ObjectId::new(), 40).await.unwrap();
+
+        reverse_earned_points(&loyalty, &order).await;
+        assert_eq!(current_balance(&loyalty, EMAIL).await.unwrap(), 0);
+        // Cancelling again takes nothing more
+        reverse_earned_points(&loyalty, &order).await;
+        assert_eq!(loyalty.balance(EMAIL).await.unwrap(), 0);
+    }
+}
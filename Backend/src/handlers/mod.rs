@@ -3,4 +3,15 @@ pub mod auth;
 pub mod cart;
 pub mod mpesa;
 pub mod favorites;
-pub mod password_reset;
\ No newline at end of file
+pub mod health;
+pub mod images;
+pub mod loyalty;
+pub mod orders;
+pub mod password_reset;
+pub mod products;
+pub mod promotions;
+pub mod slots;
+pub mod stock;
+pub mod uploads;
+pub mod users;
+
@@ -1,14 +1,25 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use reqwest::Client;
 use chrono::Utc;
 use base64::{Engine as _, engine::general_purpose};
 use std::env;
+use std::time::Instant;
+
+use crate::config::{LoyaltyConfig, RateLimitConfig};
+use crate::metrics;
+use crate::middleware::rate_limit::{self, too_many_requests};
+use crate::models::order::{STATUS_PAID, STATUS_PAYMENT_FAILED, STATUS_PAYMENT_INITIATED};
+use crate::handlers::orders::{release_order_holds, retake_order_holds};
+use crate::handlers::loyalty::credit_order_points;
+use crate::repository::{LoyaltyRepo, OrderRepo, PromotionRepo, RateLimitRepo, SlotRepo, StockRepo};
+use crate::utils::jwt::get_user_email_from_req;
 
 #[derive(Deserialize)]
 pub struct StkPushRequest {
+    pub order_id: String,
     pub phone_number: String,
-    pub amount: u32,
 }
 
 #[derive(Serialize)]
@@ -28,8 +39,33 @@ struct AccessTokenResponse {
     _expires_in: String,
 }
 
+// Environment variables the Daraja integration needs
+const REQUIRED_CONFIG: [&str; 6] = [
+    "MPESA_CONSUMER_KEY",
+    "MPESA_CONSUMER_SECRET",
+    "MPESA_PASSKEY",
+    "MPESA_SHORTCODE",
+    "MPESA_CALLBACK_URL",
+    "MPESA_CALLBACK_SECRET",
+];
+
+/// Names of required M-Pesa settings that are unset or empty
+pub fn missing_config() -> Vec<&'static str> {
+    REQUIRED_CONFIG
+        .into_iter()
+        .filter(|key| env::var(key).map(|v| v.trim().is_empty()).unwrap_or(true))
+        .collect()
+}
+
 // Helper to get Access Token
 async fn get_access_token(client: &Client) -> Result<String, String> {
+    let start = Instant::now();
+    let result = fetch_access_token(client).await;
+    metrics::MPESA_TOKEN_FETCH_DURATION.observe(start.elapsed().as_secs_f64());
+    result
+}
+
+async fn fetch_access_token(client: &Client) -> Result<String, String> {
     let consumer_key = env::var("MPESA_CONSUMER_KEY").map_err(|_| "MPESA_CONSUMER_KEY not set")?;
     let consumer_secret = env::var("MPESA_CONSUMER_SECRET").map_err(|_| "MPESA_CONSUMER_SECRET not set")?;
     let auth_url = "https://sandbox.safaricom.co.ke/oauth/v1/generate?grant_type=client_credentials";
@@ -50,6 +86,16 @@ async fn get_access_token(client: &Client) -> Result<String, String> {
 }
 
 pub async fn send_stk_push(phone_number: &str, amount: u32) -> Result<StkPushResponse, String> {
+    let result = request_stk_push(phone_number, amount).await;
+    if let Err(e) = &result {
+        tracing::warn!(error = %e, amount, "M-Pesa STK push was not accepted");
+    }
+    let outcome = if result.is_ok() { "success" } else { "failure" };
+    metrics::STK_PUSHES.with_label_values(&[outcome]).inc();
+    result
+}
+
+async fn request_stk_push(phone_number: &str, amount: u32) -> Result<StkPushResponse, String> {
     let client = Client::new();
 
     // 1. Get Access Token
@@ -101,21 +147,344 @@ pub async fn send_stk_push(phone_number: &str, amount: u32) -> Result<StkPushRes
         })
     } else {
         let error_text = res.text().await.unwrap_or_default();
-        log::error!("STK Push Failed: {}", error_text);
+        tracing::error!(response = %error_text, "STK Push failed");
         Err(format!("STK Push failed: {}", error_text))
     }
 }
 
-pub async fn initiate_stk_push(req: web::Json<StkPushRequest>) -> impl Responder {
-    match send_stk_push(&req.phone_number, req.amount).await {
-        Ok(response) => HttpResponse::Ok().json(response),
-        Err(e) => HttpResponse::BadRequest().json(StkPushResponse {
-            message: e,
+impl StkPushResponse {
+    fn error(message: impl Into<String>) -> Self {
+        StkPushResponse {
+            message: message.into(),
             merchant_request_id: None,
             checkout_request_id: None,
             response_code: None,
             response_description: None,
             customer_message: None,
-        })
+        }
+    }
+}
+
+/// Normalise a Kenyan mobile number to the 2547XXXXXXXX / 2541XXXXXXXX form Daraja expects.
+/// Accepts 07.., 01.., 7.., 1.., 254.. and +254.. with spaces or dashes.
+pub fn normalize_phone(phone: &str) -> Option<String> {
+    let digits: String = phone.chars().filter(|c| !matches!(c, ' ' | '-' | '+')).collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let local = digits
+        .strip_prefix("254")
+        .or_else(|| digits.strip_prefix('0'))
+        .unwrap_or(&digits);
+
+    let valid = local.len() == 9 && (local.starts_with('7') || local.starts_with('1'));
+    valid.then(|| format!("254{}", local))
+}
+
+/// Apply the per-user and per-phone STK push limits
+pub async fn check_stk_push_limits(
+    limits: &dyn RateLimitRepo,
+    limit_config: &RateLimitConfig,
+    user_email: &str,
+    phone: &str,
+) -> Result<(), HttpResponse> {
+    let user_key = format!("stk:user:{}", user_email.to_lowercase());
+    let phone_key = format!("stk:phone:{}", phone);
+
+    for (key, rule) in [(user_key, limit_config.stk_push_per_user), (phone_key, limit_config.stk_push_per_phone)] {
+        if let Err(retry_after) = rate_limit::check(limits, &key, rule).await {
+            tracing::warn!(key = %key, "STK push rate limit exceeded");
+            return Err(too_many_requests(retry_after, "Too many payment requests. Please wait before trying again."));
+        }
+    }
+    Ok(())
+}
+
+/// POST /api/payment/mpesa/stkpush
+/// Re-sends the M-Pesa payment prompt for one of the caller's unpaid orders.
+/// The amount always comes from the stored order, never from the client.
+#[allow(clippy::too_many_arguments)] // one extractor per dependency
+pub async fn initiate_stk_push(
+    orders: web::Data<dyn OrderRepo>,
+    limits: web::Data<dyn RateLimitRepo>,
+    limit_config: web::Data<RateLimitConfig>,
+    stock: web::Data<dyn StockRepo>,
+    slots: web::Data<dyn SlotRepo>,
+    promotions: web::Data<dyn PromotionRepo>,
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    req: web::Json<StkPushRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    // 1. Authenticate User
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(StkPushResponse::error(e)),
+    };
+
+    // 2. Validate input
+    let phone = match normalize_phone(&req.phone_number) {
+        Some(phone) => phone,
+        None => return HttpResponse::BadRequest().json(StkPushResponse::error("Invalid M-Pesa phone number")),
+    };
+    let order_id = match ObjectId::parse_str(&req.order_id) {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::NotFound().json(StkPushResponse::error("Order not found")),
+    };
+
+    // 3. The order must exist, belong to the caller and still be unpaid
+    let order = match orders.find_by_id(order_id).await {
+        Ok(Some(order)) if order.user_email == user_email => order,
+        Ok(_) => return HttpResponse::NotFound().json(StkPushResponse::error("Order not found")),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading order for STK push");
+            return HttpResponse::InternalServerError().json(StkPushResponse::error("Database error"));
+        }
+    };
+    if !order.is_unpaid() {
+        return HttpResponse::Conflict().json(StkPushResponse::error(format!("Order is already {}", order.status)));
+    }
+    // Only one prompt at a time: a second push would replace the request id the first callback is matched on
+    if order.status == STATUS_PAYMENT_INITIATED {
+        return HttpResponse::Conflict().json(StkPushResponse::error("A payment request is already waiting on your phone"));
+    }
+
+    // 4. Rate limits per user and per phone number
+    if let Err(res) = check_stk_push_limits(limits.get_ref(), &limit_config, &user_email, &phone).await {
+        return res;
+    }
+
+    // 5. Charge the order total (M-Pesa only takes whole shillings)
+    let amount = order.total.ceil() as u32;
+    if amount == 0 {
+        return HttpResponse::BadRequest().json(StkPushResponse::error("Order has nothing to pay"));
+    }
+
+    // 6. A failed payment gave its stock, slots, promo use and points back; take them again before retrying
+    let order = match retake_order_holds(orders.get_ref(), stock.get_ref(), slots.get_ref(), promotions.get_ref(), loyalty.get_ref(), order).await {
+        Ok(order) => order,
+        Err(res) => return res,
+    };
+
+    tracing::info!(order_id = %order_id, phone = %phone, amount, "Re-sending STK push for order");
+    match send_stk_push(&phone, amount).await {
+        Ok(response) => {
+            if let Some(checkout_request_id) = &response.checkout_request_id {
+                match orders.set_payment_request(order_id, checkout_request_id).await {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        tracing::error!(order_id = %order_id, checkout_request_id = %checkout_request_id, "Another STK push or payment got to the order first; this request won't be matched");
+                        return HttpResponse::Conflict().json(StkPushResponse::error("A payment request is already in progress for this order"));
+                    }
+                    Err(e) => tracing::error!(error = %e, "Failed to record STK push on order"),
+                }
+            }
+            HttpResponse::Ok().json(response)
+        },
+        Err(e) => {
+            if order.status == STATUS_PAYMENT_FAILED {
+                release_order_holds(orders.get_ref(), stock.get_ref(), slots.get_ref(), promotions.get_ref(), loyalty.get_ref(), &order).await;
+            }
+            HttpResponse::BadRequest().json(StkPushResponse::error(e))
+        },
+    }
+}
+
+// --- STK push callback (called by Safaricom) ---
+
+#[derive(Deserialize)]
+pub struct StkCallbackEnvelope {
+    #[serde(rename = "Body")]
+    body: StkCallbackBody,
+}
+
+#[derive(Deserialize)]
+struct StkCallbackBody {
+    #[serde(rename = "stkCallback")]
+    stk_callback: StkCallback,
+}
+
+#[derive(Deserialize)]
+struct StkCallback {
+    #[serde(rename = "CheckoutRequestID")]
+    checkout_request_id: String,
+    #[serde(rename = "ResultCode")]
+    result_code: i64,
+    #[serde(rename = "ResultDesc")]
+    result_desc: String,
+    #[serde(rename = "CallbackMetadata")]
+    metadata: Option<CallbackMetadata>,
+}
+
+#[derive(Deserialize)]
+struct CallbackMetadata {
+    #[serde(rename = "Item")]
+    items: Vec<CallbackItem>,
+}
+
+#[derive(Deserialize)]
+struct CallbackItem {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Value")]
+    value: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    secret: Option<String>,
+}
+
+// Compare secrets without stopping at the first differing byte, so response
+// timing doesn't reveal how much of a guess was right
+pub(crate) fn secrets_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// A value from the callback metadata, e.g. "Amount" or "MpesaReceiptNumber"
+fn metadata_value<'a>(callback: &'a StkCallback, name: &str) -> Option<&'a serde_json::Value> {
+    callback.metadata.as_ref()?
+        .items.iter()
+        .find(|item| item.name == name)?
+        .value.as_ref()
+}
+
+/// POST /api/payment/mpesa/callback
+/// Marks the order Paid or Payment Failed. The callback URL registered with Daraja
+/// must carry MPESA_CALLBACK_SECRET as `?secret=...`; without it every callback is refused.
+#[allow(clippy::too_many_arguments)] // one extractor per dependency
+pub async fn mpesa_callback(
+    orders: web::Data<dyn OrderRepo>,
+    stock: web::Data<dyn StockRepo>,
+    slots: web::Data<dyn SlotRepo>,
+    promotions: web::Data<dyn PromotionRepo>,
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    loyalty_config: web::Data<LoyaltyConfig>,
+    query: web::Query<CallbackQuery>,
+    payload: web::Json<StkCallbackEnvelope>,
+) -> impl Responder {
+    let expected = env::var("MPESA_CALLBACK_SECRET").unwrap_or_default();
+    if expected.is_empty() {
+        tracing::error!("Rejected M-Pesa callback: MPESA_CALLBACK_SECRET is not set");
+        return HttpResponse::Unauthorized().finish();
+    }
+    if !query.secret.as_deref().is_some_and(|given| secrets_match(given, &expected)) {
+        tracing::warn!("Rejected M-Pesa callback with a missing or wrong secret");
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let callback = &payload.body.stk_callback;
+    let receipt = metadata_value(callback, "MpesaReceiptNumber").and_then(|value| value.as_str());
+    let amount = metadata_value(callback, "Amount").and_then(|value| value.as_f64());
+    let mut paid = callback.result_code == 0;
+
+    // A success is only believed if it covers the order total we asked for
+    if paid {
+        match orders.find_by_checkout_request_id(&callback.checkout_request_id).await {
+            Ok(Some(order)) if amount.is_some_and(|amount| amount >= order.total.ceil()) => (),
+            Ok(Some(order)) => {
+                tracing::error!(order_id = ?order.id, amount = ?amount, total = order.total, "M-Pesa callback amount doesn't cover the order; treating as failed");
+                paid = false;
+            }
+            Ok(None) => (),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to load order for M-Pesa callback");
+                return HttpResponse::InternalServerError().json(serde_json::json!({ "ResultCode": 1, "ResultDesc": "Temporary failure" }));
+            }
+        }
+    }
+    let status = if paid { STATUS_PAID } else { STATUS_PAYMENT_FAILED };
+
+    tracing::info!(
+        checkout_request_id = %callback.checkout_request_id,
+        result_code = callback.result_code,
+        result_desc = %callback.result_desc,
+        "M-Pesa callback received"
+    );
+    metrics::MPESA_PAYMENT_RESULTS.with_label_values(&[if paid { "paid" } else { "failed" }]).inc();
+
+    match orders.record_payment_result(&callback.checkout_request_id, status, receipt).await {
+        Ok(Some(order)) => {
+            tracing::info!(order_id = ?order.id, status, "Order payment status updated");
+            if paid {
+                // Points are only earned once M-Pesa confirms the money arrived
+                credit_order_points(loyalty.get_ref(), &loyalty_config, &order).await;
+            } else {
+                release_order_holds(orders.get_ref(), stock.get_ref(), slots.get_ref(), promotions.get_ref(), loyalty.get_ref(), &order).await;
+            }
+        },
+        Ok(None) => {
+            tracing::warn!(checkout_request_id = %callback.checkout_request_id, "No order awaiting this M-Pesa callback");
+        },
+        Err(e) => {
+            // Non-zero ResultCode asks Daraja to retry the callback later
+            tracing::error!(error = %e, "Failed to record M-Pesa payment result");
+            return HttpResponse::InternalServerError().json(serde_json::json!({ "ResultCode": 1, "ResultDesc": "Temporary failure" }));
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({ "ResultCode": 0, "ResultDesc": "Accepted" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::{init_service, TestRequest}, App};
+
+    use crate::models::order::Order;
+    use crate::repository::Repositories;
+    use crate::test_util::{bearer, call};
+
+    #[test]
+    fn normalize_phone_accepts_kenyan_mobile_formats() {
+        for phone in ["0712345678", "712345678", "254712345678", "+254 712 345 678", "0712-345-678"] {
+            assert_eq!(normalize_phone(phone).as_deref(), Some("254712345678"), "{}", phone);
+        }
+        assert_eq!(normalize_phone("0112345678").as_deref(), Some("254112345678"));
+    }
+
+    #[test]
+    fn normalize_phone_rejects_other_numbers() {
+        for phone in ["", "0812345678", "071234567", "07123456789", "0712abc678", "255712345678"] {
+            assert_eq!(normalize_phone(phone), None, "{}", phone);
+        }
+    }
+
+    #[test]
+    fn secrets_match_needs_the_exact_secret() {
+        assert!(secrets_match("s3cret", "s3cret"));
+        assert!(!secrets_match("s3cres", "s3cret"));
+        assert!(!secrets_match("s3cre", "s3cret"));
+        assert!(!secrets_match("", "s3cret"));
+    }
+
+    #[actix_web::test]
+    async fn a_second_push_waits_for_the_first() {
+        let repos = Repositories::in_memory();
+        let order_id = ObjectId::new();
+        repos.orders.insert(&Order {
+            id: Some(order_id),
+            user_email: "jane@example.com".to_string(),
+            total: 30.0,
+            status: STATUS_PAYMENT_INITIATED.to_string(),
+            checkout_request_id: Some("ws_CO_first".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        let app = init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .app_data(web::Data::new(RateLimitConfig::from_env()))
+                .route("/api/payment/mpesa/stkpush", web::post().to(initiate_stk_push))
+        ).await;
+
+        let push = TestRequest::post().uri("/api/payment/mpesa/stkpush")
+            .insert_header(("Authorization", bearer("jane@example.com")))
+            .set_json(serde_json::json!({ "order_id": order_id.to_hex(), "phone_number": "0712345678" }));
+        assert_eq!(call(&app, push).await.0, StatusCode::CONFLICT);
+
+        assert!(!repos.orders.set_payment_request(order_id, "ws_CO_second").await.unwrap());
+        let order = repos.orders.find_by_id(order_id).await.unwrap().unwrap();
+        assert_eq!(order.checkout_request_id.as_deref(), Some("ws_CO_first"));
     }
 }
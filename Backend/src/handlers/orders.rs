@@ -0,0 +1,448 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::order::{Order, OrderItem, Refund, REFUND_PENDING, STATUS_PAID, STATUS_PAYMENT_INITIATED};
+use crate::handlers::slots::{release_slots, retake_slots};
+use crate::handlers::promotions::{redeem_promotion, release_promotion};
+use crate::handlers::loyalty::{refund_points, reverse_earned_points, spend_points};
+use crate::handlers::stock::{release_stock, retake_stock};
+use crate::repository::{LoyaltyRepo, OrderRepo, ProductRepo, PromotionRepo, RepoError, SlotRepo, StockRepo};
+use crate::repository::order::OrderFilter;
+use crate::utils::jwt::get_user_email_from_req;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+// Query string for GET /api/orders, e.g. ?page=2&per_page=20&status=Paid,Cancelled&from=2025-01-01&to=2025-01-31
+#[derive(Deserialize, Debug)]
+pub struct OrderListQuery {
+    page: Option<u64>,
+    per_page: Option<u64>,
+    status: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+// Body for POST /api/admin/orders/{id}/refund
+#[derive(Deserialize, Debug)]
+pub struct SettleRefundRequest {
+    /// M-Pesa reversal transaction id or bank transfer reference
+    reference: String,
+}
+
+#[derive(Serialize)]
+struct UnavailableItem {
+    item_id: String,
+    title: String,
+}
+
+// Load an order for the signed-in user. Other users' orders look the same as missing ones.
+async fn load_own_order(orders: &dyn OrderRepo, order_id: &str, http_req: &HttpRequest) -> Result<Order, HttpResponse> {
+    let user_email = get_user_email_from_req(http_req)
+        .map_err(|e| HttpResponse::Unauthorized().json(ErrorResponse { message: e }))?;
+
+    let not_found = || HttpResponse::NotFound().json(ErrorResponse { message: "Order not found".to_string() });
+    let order_id = ObjectId::parse_str(order_id).map_err(|_| not_found())?;
+
+    match orders.find_by_id(order_id).await {
+        Ok(Some(order)) if order.user_email == user_email => Ok(order),
+        Ok(_) => Err(not_found()),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading order");
+            Err(HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() }))
+        }
+    }
+}
+
+// Accepts an RFC 3339 timestamp or a plain date. A plain `to` date includes the whole day.
+fn parse_date_bound(value: &str, end_of_day: bool) -> Option<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let date = if end_of_day { date.checked_add_days(Days::new(1))? } else { date };
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// Give back everything an unpaid order holds (stock, slot capacity, the promo use and spent points)
+/// exactly once, however many times it fails or is cancelled
+pub async fn release_order_holds(
+    orders: &dyn OrderRepo,
+    stock: &dyn StockRepo,
+    slots: &dyn SlotRepo,
+    promotions: &dyn PromotionRepo,
+    loyalty: &dyn LoyaltyRepo,
+    order: &Order,
+) {
+    let Some(order_id) = order.id else { return };
+    if order.holds_released {
+        return;
+    }
+    match orders.set_holds_released(order_id, true).await {
+        Ok(true) => {
+            release_stock(stock, &order.stock_reservations).await;
+            release_slots(slots, &order.slot_reservations).await;
+            release_promotion(promotions, order.discount.as_ref(), &order.user_email).await;
+            refund_points(loyalty, &order.user_email, order_id, order.points_redeemed).await;
+        }
+        Ok(false) => (),
+        Err(e) => tracing::error!(order_id = %order_id, error = %e, "Failed to mark order holds released"),
+    }
+}
+
+/// Take back what `release_order_holds` gave up before a failed payment is retried.
+/// All or nothing: fails if anything has run out since, leaving the order released.
+pub async fn retake_order_holds(
+    orders: &dyn OrderRepo,
+    stock: &dyn StockRepo,
+    slots: &dyn SlotRepo,
+    promotions: &dyn PromotionRepo,
+    loyalty: &dyn LoyaltyRepo,
+    order: Order,
+) -> Result<Order, HttpResponse> {
+    let Some(order_id) = order.id else { return Ok(order) };
+    if !order.holds_released {
+        return Ok(order);
+    }
+    let database_error = |e: RepoError| {
+        tracing::error!(order_id = %order_id, error = %e, "Database error retaking order holds");
+        HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+    };
+    // Claim the flag first so two retries can't both take everything again
+    if !orders.set_holds_released(order_id, false).await.map_err(database_error)? {
+        return Ok(Order { holds_released: false, ..order });
+    }
+
+    let taken = async {
+        retake_stock(stock, &order.stock_reservations).await?;
+        if let Err(res) = retake_slots(slots, &order.slot_reservations).await {
+            release_stock(stock, &order.stock_reservations).await;
+            return Err(res);
+        }
+        let promotion = match &order.discount {
+            None => Ok(()),
+            Some(discount) => match promotions.find(&discount.code).await {
+                Ok(Some(promotion)) => redeem_promotion(promotions, &promotion, &order.user_email).await,
+                Ok(None) => Err(HttpResponse::Conflict().json(ErrorResponse { message: format!("{} is no longer available", discount.code) })),
+                Err(e) => Err(database_error(e)),
+            },
+        };
+        if let Err(res) = promotion {
+            release_stock(stock, &order.stock_reservations).await;
+            release_slots(slots, &order.slot_reservations).await;
+            return Err(res);
+        }
+        if order.points_redeemed > 0 {
+            if let Err(res) = spend_points(loyalty, &order.user_email, order_id, order.points_redeemed).await {
+                release_stock(stock, &order.stock_reservations).await;
+                release_slots(slots, &order.slot_reservations).await;
+                release_promotion(promotions, order.discount.as_ref(), &order.user_email).await;
+                return Err(res);
+            }
+        }
+        Ok(())
+    }.await;
+
+    match taken {
+        Ok(()) => Ok(Order { holds_released: false, ..order }),
+        Err(res) => {
+            if let Err(e) = orders.set_holds_released(order_id, true).await {
+                tracing::error!(order_id = %order_id, error = %e, "Failed to mark order holds released");
+            }
+            Err(res)
+        }
+    }
+}
+
+/// GET /api/orders
+/// Fetches a page of the logged-in user's orders, newest first
+pub async fn get_user_orders(
+    orders: web::Data<dyn OrderRepo>,
+    query: web::Query<OrderListQuery>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Authenticate User
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+
+    // 2. Build the filter
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let mut filter = OrderFilter { user_email, ..Default::default() };
+    if let Some(status) = &query.status {
+        filter.statuses = status.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    }
+    for (value, end_of_day, bound) in [
+        (&query.from, false, &mut filter.created_from),
+        (&query.to, true, &mut filter.created_to),
+    ] {
+        if let Some(value) = value {
+            match parse_date_bound(value.trim(), end_of_day) {
+                Some(at) => *bound = Some(at),
+                None => return HttpResponse::BadRequest().json(ErrorResponse { message: format!("Invalid date '{}'. Use YYYY-MM-DD or an RFC 3339 timestamp", value) }),
+            }
+        }
+    }
+
+    // 3. Find orders
+    let skip = (page - 1).saturating_mul(per_page);
+    match orders.find_page(&filter, skip, per_page).await {
+        Ok(result) => HttpResponse::Ok().json(json!({
+            "orders": result.orders,
+            "page": page,
+            "per_page": per_page,
+            "total": result.total,
+            "total_pages": result.total.div_ceil(per_page),
+            "unreadable": result.unreadable,
+        })),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error listing orders");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+        }
+    }
+}
+
+/// GET /api/orders/{id}
+/// A single order belonging to the logged-in user
+pub async fn get_order(
+    orders: web::Data<dyn OrderRepo>,
+    path: web::Path<String>,
+    http_req: HttpRequest
+) -> impl Responder {
+    match load_own_order(orders.get_ref(), &path, &http_req).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(res) => res,
+    }
+}
+
+/// POST /api/orders/{id}/cancel
+/// Cancels an order that hasn't started preparation; paid orders get a pending refund
+pub async fn cancel_order(
+    orders: web::Data<dyn OrderRepo>,
+    slots: web::Data<dyn SlotRepo>,
+    stock: web::Data<dyn StockRepo>,
+    promotions: web::Data<dyn PromotionRepo>,
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    path: web::Path<String>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Load the caller's order
+    let order = match load_own_order(orders.get_ref(), &path, &http_req).await {
+        Ok(order) => order,
+        Err(res) => return res,
+    };
+
+    // 2. Only before the kitchen starts on it
+    if !order.is_cancellable() {
+        let message = if order.status == STATUS_PAYMENT_INITIATED {
+            "Payment is still in progress. Try again once it completes.".to_string()
+        } else {
+            format!("Order can no longer be cancelled (status: {})", order.status)
+        };
+        return HttpResponse::Conflict().json(ErrorResponse { message });
+    }
+
+    // 3. Paid orders are owed their money back
+    let refund = (order.status == STATUS_PAID).then(|| Refund {
+        amount: order.total,
+        method: order.payment_method.clone(),
+        status: REFUND_PENDING.to_string(),
+        requested_at: Utc::now(),
+        reference: None,
+        settled_by: None,
+        settled_at: None,
+    });
+
+    // 4. Cancel only if the status hasn't moved on since we read it
+    let Some(order_id) = order.id else {
+        return HttpResponse::NotFound().json(ErrorResponse { message: "Order not found".to_string() });
+    };
+    match orders.cancel(order_id, &order.status, refund).await {
+        Ok(Some(cancelled)) => {
+            // Free the kitchen capacity for other customers (a failed payment may already have)
+            release_order_holds(orders.get_ref(), stock.get_ref(), slots.get_ref(), promotions.get_ref(), loyalty.get_ref(), &cancelled).await;
+            reverse_earned_points(loyalty.get_ref(), &cancelled).await;
+            match &cancelled.refund {
+                Some(refund) => tracing::info!(order_id = %order_id, amount = refund.amount, method = %refund.method, "Order cancelled; refund pending"),
+                None => tracing::info!(order_id = %order_id, "Order cancelled"),
+            }
+            HttpResponse::Ok().json(cancelled)
+        },
+        Ok(None) => HttpResponse::Conflict().json(ErrorResponse { message: "Order status changed; please refresh and try again".to_string() }),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to cancel order");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to cancel order".to_string() })
+        }
+    }
+}
+
+/// POST /api/orders/{id}/reorder
+/// Returns the order's items at today's prices for the client to put in the cart.
+/// Items no longer on the menu are listed under `unavailable`.
+pub async fn reorder(
+    orders: web::Data<dyn OrderRepo>,
+    products: web::Data<dyn ProductRepo>,
+    path: web::Path<String>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Load the caller's order
+    let order = match load_own_order(orders.get_ref(), &path, &http_req).await {
+        Ok(order) => order,
+        Err(res) => return res,
+    };
+
+    // 2. Look up current prices
+    let item_ids: Vec<String> = order.items.iter().map(|i| i.item_id.clone()).collect();
+    let catalog = match products.find_by_ids(&item_ids).await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading products for reorder");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+
+    // 3. Build the cart lines
+    let mut items = Vec::new();
+    let mut unavailable = Vec::new();
+    for item in &order.items {
+        let product = catalog.iter().find(|p| p.item_id == item.item_id && p.is_on_sale(Utc::now()));
+        // Custom cakes are repriced with today's option prices; options since removed make them unavailable
+        let priced = product.and_then(|p| p.price_with_options(&item.options).ok().map(|priced| (p, priced)));
+        match priced {
+            Some((product, (price, options))) => items.push(OrderItem {
+                allergens: product.allergens_with(&options),
+                item_id: product.item_id.clone(),
+                title: product.title.clone(),
+                quantity: item.quantity,
+                price,
+                image_src: product.image_src.clone(),
+                options,
+                inscription: item.inscription.clone(),
+                reference_image: item.reference_image.clone(),
+            }),
+            None => unavailable.push(UnavailableItem { item_id: item.item_id.clone(), title: item.title.clone() }),
+        }
+    }
+    let total = items.iter().fold(0.0, |sum, i| sum + i.price * i.quantity as f64);
+
+    HttpResponse::Ok().json(json!({ "items": items, "unavailable": unavailable, "total": total }))
+}
+
+/// GET /api/admin/refunds
+/// Refunds owed on cancelled orders that haven't been paid out yet, oldest first
+pub async fn list_pending_refunds(orders: web::Data<dyn OrderRepo>) -> impl Responder {
+    match orders.find_pending_refunds().await {
+        Ok(pending) => HttpResponse::Ok().json(pending),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error listing pending refunds");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+        }
+    }
+}
+
+/// POST /api/admin/orders/{id}/refund
+/// Records that staff paid a pending refund out (M-Pesa reversal or bank transfer)
+pub async fn settle_refund(
+    orders: web::Data<dyn OrderRepo>,
+    path: web::Path<String>,
+    req: web::Json<SettleRefundRequest>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. The admin settling it, for the record
+    let admin_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+
+    // 2. Validate input
+    let Ok(order_id) = ObjectId::parse_str(path.as_str()) else {
+        return HttpResponse::NotFound().json(ErrorResponse { message: "Order not found".to_string() });
+    };
+    let reference = req.reference.trim();
+    if reference.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "A payment reference is required".to_string() });
+    }
+
+    // 3. Settle only a refund that is still pending, so it is never paid twice
+    match orders.settle_refund(order_id, reference, &admin_email).await {
+        Ok(Some(order)) => {
+            tracing::info!(order_id = %order_id, admin = %admin_email, reference = %reference, "Refund settled");
+            HttpResponse::Ok().json(order)
+        },
+        Ok(None) => match orders.find_by_id(order_id).await {
+            Ok(Some(_)) => HttpResponse::Conflict().json(ErrorResponse { message: "Order has no pending refund".to_string() }),
+            Ok(None) => HttpResponse::NotFound().json(ErrorResponse { message: "Order not found".to_string() }),
+            Err(e) => {
+                tracing::error!(error = %e, "Database error loading order");
+                HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+            }
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to settle refund");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to settle refund".to_string() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    use crate::models::order::REFUND_REFUNDED;
+    use crate::repository::Repositories;
+    use crate::test_util::{bearer, call};
+
+    #[actix_web::test]
+    async fn cancelled_paid_orders_are_refunded_once() {
+        let repos = Repositories::in_memory();
+        let order_id = ObjectId::new();
+        repos.orders.insert(&Order {
+            id: Some(order_id),
+            user_email: "jane@example.com".to_string(),
+            total: 30.0,
+            status: STATUS_PAID.to_string(),
+            payment_method: "mpesa".to_string(),
+            created_at: Utc::now(),
+            ..Default::default()
+        }).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .route("/api/orders/{id}/cancel", web::post().to(cancel_order))
+                .route("/api/admin/refunds", web::get().to(list_pending_refunds))
+                .route("/api/admin/orders/{id}/refund", web::post().to(settle_refund))
+        ).await;
+
+        let cancel = test::TestRequest::post().uri(&format!("/api/orders/{}/cancel", order_id.to_hex()))
+            .insert_header(("Authorization", bearer("jane@example.com")));
+        assert_eq!(call(&app, cancel).await.0, StatusCode::OK);
+
+        let list = || test::TestRequest::get().uri("/api/admin/refunds").insert_header(("Authorization", bearer("admin@example.com")));
+        let (status, body) = call(&app, list()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["refund"]["amount"], 30.0);
+
+        let settle = || test::TestRequest::post().uri(&format!("/api/admin/orders/{}/refund", order_id.to_hex()))
+            .insert_header(("Authorization", bearer("admin@example.com")))
+            .set_json(json!({ "reference": "QK12ABC345" }));
+        let (status, body) = call(&app, settle()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["refund"]["status"], REFUND_REFUNDED);
+        assert_eq!(body["refund"]["settled_by"], "admin@example.com");
+
+        assert_eq!(call(&app, settle()).await.0, StatusCode::CONFLICT);
+        assert_eq!(call(&app, list()).await.1, json!([]));
+    }
+}
@@ -1,11 +1,12 @@
 use actix_web::{web, HttpResponse, Responder};
-use mongodb::{Client, Collection, bson::doc};
 use serde::{Deserialize, Serialize};
-use log;
 use uuid::Uuid;
 use chrono::{Utc, Duration};
 
-use crate::models::user::User;
+use crate::config::{self, RateLimitConfig};
+use crate::middleware::rate_limit::{self, too_many_requests};
+use crate::models::user::normalise_email;
+use crate::repository::{RateLimitRepo, UserRepo};
 use crate::utils::password;
 
 #[derive(Deserialize)]
@@ -24,27 +25,33 @@ struct MessageResponse {
     message: String,
 }
 
-fn get_user_collection(client: &web::Data<Client>) -> Collection<User> {
-    client.database("yetta_db").collection("users")
-}
-
 /// POST /api/auth/forgot-password
-pub async fn forgot_password(client: web::Data<Client>, req: web::Json<ForgotPasswordRequest>) -> impl Responder {
-    let users_collection = get_user_collection(&client);
-    
+pub async fn forgot_password(
+    users: web::Data<dyn UserRepo>,
+    limits: web::Data<dyn RateLimitRepo>,
+    limit_config: web::Data<RateLimitConfig>,
+    req: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    // Per-account limit so nobody can flood an inbox with reset emails
+    let email = normalise_email(&req.email);
+    let limit_key = format!("forgot:{}", email);
+    if let Err(retry_after) = rate_limit::check(limits.get_ref(), &limit_key, limit_config.per_account).await {
+        return too_many_requests(retry_after, "Too many reset requests. Please try again later.");
+    }
+
     // 1. Check if user exists
-    let _user = match users_collection.find_one(doc! { "email": &req.email }, None).await {
+    let _user = match users.find_by_email(&email).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             // For security, don't reveal that the user doesn't exist.
             // Just pretend we sent an email.
-            log::info!("Forgot password requested for non-existent email: {}", req.email);
+            tracing::info!(email = %email, "Forgot password requested for non-existent email");
             return HttpResponse::Ok().json(MessageResponse { 
                 message: "If an account exists with this email, a reset link has been sent.".to_string() 
             });
         },
         Err(e) => {
-            log::error!("Database error: {}", e);
+            tracing::error!(error = %e, "Database error");
             return HttpResponse::InternalServerError().json(MessageResponse { message: "Database error".to_string() });
         }
     };
@@ -55,26 +62,16 @@ pub async fn forgot_password(client: web::Data<Client>, req: web::Json<ForgotPas
     let expiry_ts = expiry.timestamp_millis();
 
     // 3. Save token to DB
-    let update_result = users_collection.update_one(
-        doc! { "email": &req.email },
-        doc! { "$set": { 
-            "reset_token": &reset_token,
-            "reset_token_expiry": expiry_ts
-        }},
-        None
-    ).await;
+    let update_result = users.set_reset_token(&email, &reset_token, expiry_ts).await;
 
     if let Err(e) = update_result {
-        log::error!("Failed to save reset token: {}", e);
+        tracing::error!(error = %e, "Failed to save reset token");
         return HttpResponse::InternalServerError().json(MessageResponse { message: "Failed to process request".to_string() });
     }
 
     // 4. "Send" Email (Log to console)
-    let reset_link = format!("http://localhost:5173/reset-password?token={}", reset_token);
-    log::info!("---------------------------------------------------");
-    log::info!("PASSWORD RESET LINK FOR {}:", req.email);
-    log::info!("{}", reset_link);
-    log::info!("---------------------------------------------------");
+    let reset_link = format!("{}/reset-password?token={}", config::frontend_url(), reset_token);
+    tracing::info!(email = %email, reset_link = %reset_link, "Password reset link generated");
 
     HttpResponse::Ok().json(MessageResponse { 
         message: "If an account exists with this email, a reset link has been sent.".to_string() 
@@ -82,17 +79,15 @@ pub async fn forgot_password(client: web::Data<Client>, req: web::Json<ForgotPas
 }
 
 /// POST /api/auth/reset-password
-pub async fn reset_password(client: web::Data<Client>, req: web::Json<ResetPasswordRequest>) -> impl Responder {
-    let users_collection = get_user_collection(&client);
-
+pub async fn reset_password(users: web::Data<dyn UserRepo>, req: web::Json<ResetPasswordRequest>) -> impl Responder {
     // 1. Find user by token
-    let user = match users_collection.find_one(doc! { "reset_token": &req.token }, None).await {
+    let user = match users.find_by_reset_token(&req.token).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             return HttpResponse::BadRequest().json(MessageResponse { message: "Invalid or expired token".to_string() });
         },
         Err(e) => {
-            log::error!("Database error: {}", e);
+            tracing::error!(error = %e, "Database error");
             return HttpResponse::InternalServerError().json(MessageResponse { message: "Database error".to_string() });
         }
     };
@@ -111,25 +106,22 @@ pub async fn reset_password(client: web::Data<Client>, req: web::Json<ResetPassw
     let password_hash = match password::hash_password(&req.new_password) {
         Ok(hash) => hash,
         Err(e) => {
-            log::error!("Password hashing error: {}", e);
+            tracing::error!(error = %e, "Password hashing error");
             return HttpResponse::InternalServerError().json(MessageResponse { message: "Failed to reset password".to_string() });
         }
     };
 
     // 4. Update User (Set new password, clear token)
-    let update_result = users_collection.update_one(
-        doc! { "_id": user.id },
-        doc! { 
-            "$set": { "password_hash": password_hash },
-            "$unset": { "reset_token": "", "reset_token_expiry": "" }
-        },
-        None
-    ).await;
+    let user_id = match user.id {
+        Some(id) => id,
+        None => return HttpResponse::InternalServerError().json(MessageResponse { message: "Invalid user record".to_string() }),
+    };
+    let update_result = users.update_password(user_id, &password_hash).await;
 
     match update_result {
         Ok(_) => HttpResponse::Ok().json(MessageResponse { message: "Password reset successful. You can now login.".to_string() }),
         Err(e) => {
-            log::error!("Failed to update password: {}", e);
+            tracing::error!(error = %e, "Failed to update password");
             HttpResponse::InternalServerError().json(MessageResponse { message: "Failed to update password".to_string() })
         }
     }
@@ -0,0 +1,418 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+
+use crate::config::SlotConfig;
+use crate::handlers::stock::stock_date;
+use crate::models::product::{conflicting_tags, normalise_allergens, OptionGroup, PriceChange, Product, DEFAULT_CATEGORY};
+use crate::repository::{PriceChangeRepo, ProductRepo, RepoError, StockRepo};
+use crate::utils::jwt::get_user_email_from_req;
+use crate::utils::search;
+
+const MAX_ITEM_ID_LEN: usize = 64;
+const MAX_TITLE_LEN: usize = 100;
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 50;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProductQuery {
+    /// Day to check daily stock for; defaults to today
+    date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    q: Option<String>,
+    /// Comma-separated; any of them
+    category: Option<String>,
+    min_price: Option<f64>,
+    max_price: Option<f64>,
+    /// Comma-separated dietary tags; all of them
+    tags: Option<String>,
+    /// Comma-separated allergens to avoid
+    free_from: Option<String>,
+    limit: Option<usize>,
+    date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AdminProductQuery {
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+// Everything staff can edit; stock has its own endpoints
+#[derive(Deserialize, Debug)]
+pub struct ProductRequest {
+    title: String,
+    price: f64,
+    description: Option<String>,
+    category: Option<String>,
+    #[serde(default)]
+    dietary_tags: Vec<String>,
+    #[serde(default)]
+    allergens: Vec<String>,
+    image_src: String,
+    #[serde(default)]
+    images: Vec<String>,
+    #[serde(default)]
+    sort_order: i32,
+    #[serde(default = "default_true")]
+    available: bool,
+    available_from: Option<DateTime<Utc>>,
+    available_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    option_groups: Vec<OptionGroup>,
+    #[serde(default)]
+    personalisable: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateProductRequest {
+    item_id: String,
+    #[serde(flatten)]
+    product: ProductRequest,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// A catalog item as customers see it, with its stock for the day
+#[derive(Serialize)]
+struct ProductView {
+    #[serde(flatten)]
+    product: Product,
+    sold_out: bool,
+    /// Only for stock-tracked products
+    stock_remaining: Option<u32>,
+}
+
+/// GET /api/products/{item_id}
+/// A catalog item with its option groups, so the cake builder can render the server's choices and prices
+pub async fn get_product(
+    products: web::Data<dyn ProductRepo>,
+    stock: web::Data<dyn StockRepo>,
+    config: web::Data<SlotConfig>,
+    path: web::Path<String>,
+    query: web::Query<ProductQuery>,
+) -> impl Responder {
+    let product = match products.find_by_ids(&[path.into_inner()]).await {
+        Ok(found) => match found.into_iter().next() {
+            Some(product) => product,
+            None => return HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() }),
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading product");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+
+    let date = query.date.unwrap_or_else(|| stock_date(&config, None));
+    match with_stock(stock.get_ref(), vec![product], date).await {
+        Ok(mut views) => HttpResponse::Ok().json(views.remove(0)),
+        Err(e) => database_error(e),
+    }
+}
+
+/// GET /api/products
+/// The menu in display order, with what's sold out for the day
+pub async fn list_products(
+    products: web::Data<dyn ProductRepo>,
+    stock: web::Data<dyn StockRepo>,
+    config: web::Data<SlotConfig>,
+    query: web::Query<ProductQuery>,
+) -> impl Responder {
+    let now = Utc::now();
+    let catalog = match products.list(false).await {
+        // Sold-out items stay listed (and marked); out-of-season ones don't
+        Ok(catalog) => catalog.into_iter().filter(|p| p.in_season(now)).collect(),
+        Err(e) => return database_error(e),
+    };
+
+    let date = query.date.unwrap_or_else(|| stock_date(&config, None));
+    match with_stock(stock.get_ref(), catalog, date).await {
+        Ok(views) => HttpResponse::Ok().json(views),
+        Err(e) => database_error(e),
+    }
+}
+
+/// GET /api/products/search
+/// Full-text search over titles, descriptions, categories and dietary tags (MongoDB
+/// text index, with typo-tolerant scoring when it finds nothing), best matches first.
+/// Without `q` it just filters the menu.
+pub async fn search_products(
+    products: web::Data<dyn ProductRepo>,
+    stock: web::Data<dyn StockRepo>,
+    config: web::Data<SlotConfig>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let phrase = query.q.as_deref().unwrap_or_default().trim().to_lowercase();
+    let categories: Vec<String> = comma_list(query.category.as_deref()).iter().map(|c| c.to_lowercase()).collect();
+    let tags: Vec<String> = comma_list(query.tags.as_deref()).iter().map(|t| search::normalise_tag(t)).collect();
+    let free_from = match normalise_allergens(&comma_list(query.free_from.as_deref())) {
+        Ok(free_from) => free_from,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+
+    // 1. Ranked matches from the whole menu, specials and seasonal items included;
+    //    without a query, everything in menu order
+    let found = if search::tokenize(&phrase).is_empty() {
+        products.list(false).await.map(|catalog| catalog.into_iter().map(|p| (0.0, p)).collect())
+    } else {
+        products.search(&phrase).await
+    };
+    let found: Vec<(f64, Product)> = match found {
+        Ok(found) => found,
+        Err(e) => return database_error(e),
+    };
+
+    // 2. Keep what's on sale today and passes the filters, in ranked order
+    let now = Utc::now();
+    let matches: Vec<(f64, Product)> = found.into_iter()
+        .filter(|(_, p)| p.in_season(now))
+        .filter(|(_, p)| categories.is_empty() || categories.contains(&p.category))
+        .filter(|(_, p)| query.min_price.is_none_or(|min| p.price >= min))
+        .filter(|(_, p)| query.max_price.is_none_or(|max| p.price <= max))
+        .filter(|(_, p)| tags.iter().all(|tag| p.dietary_tags.contains(tag)))
+        // Only the product's own allergens; options that add more can still be avoided when ordering
+        .filter(|(_, p)| !p.allergens.iter().any(|a| free_from.contains(a)))
+        .collect();
+
+    let total = matches.len();
+    let results: Vec<Product> = matches.into_iter().take(limit).map(|(_, p)| p).collect();
+
+    let date = query.date.unwrap_or_else(|| stock_date(&config, None));
+    match with_stock(stock.get_ref(), results, date).await {
+        Ok(results) => HttpResponse::Ok().json(json!({ "results": results, "total": total })),
+        Err(e) => database_error(e),
+    }
+}
+
+fn comma_list(value: Option<&str>) -> Vec<String> {
+    value.unwrap_or_default().split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect()
+}
+
+fn normalise_tags(tags: &[String]) -> Vec<String> {
+    let mut normalised: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|t| search::normalise_tag(t)) {
+        if !tag.is_empty() && !normalised.contains(&tag) {
+            normalised.push(tag);
+        }
+    }
+    normalised
+}
+
+// Attach each tracked product's stock for `date`; tracked products with no level set have none left
+async fn with_stock(stock: &dyn StockRepo, products: Vec<Product>, date: NaiveDate) -> Result<Vec<ProductView>, RepoError> {
+    let stock_ids: Vec<String> = products.iter().filter_map(|p| p.stock_id(date)).collect();
+    let levels = if stock_ids.is_empty() { Default::default() } else { stock.levels(&stock_ids).await? };
+
+    let now = Utc::now();
+    Ok(products.into_iter().map(|product| {
+        let stock_remaining = product.stock_id(date).map(|id| levels.get(&id).copied().unwrap_or(0));
+        let sold_out = !product.is_on_sale(now) || stock_remaining == Some(0);
+        ProductView { product, sold_out, stock_remaining }
+    }).collect())
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    tracing::error!(error = %e, "Database error in products");
+    HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+}
+
+// Check and normalise an admin's product
+fn validate_product(item_id: &str, req: &ProductRequest) -> Result<Product, String> {
+    // The id ends up in stock ids ("<item_id>|<date>") and URLs
+    if item_id.is_empty() || item_id.len() > MAX_ITEM_ID_LEN
+        || !item_id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+        return Err("Item id must be lowercase letters, digits and dashes".to_string());
+    }
+    let title = req.title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_LEN {
+        return Err(format!("Title must be 1-{} characters", MAX_TITLE_LEN));
+    }
+    if !req.price.is_finite() || req.price < 0.0 {
+        return Err("Price must be zero or more".to_string());
+    }
+    if req.image_src.trim().is_empty() {
+        return Err("Image required".to_string());
+    }
+    if let (Some(from), Some(until)) = (req.available_from, req.available_until) {
+        if from >= until {
+            return Err("Availability must end after it starts".to_string());
+        }
+    }
+
+    let allergens = normalise_allergens(&req.allergens)?;
+    let dietary_tags = normalise_tags(&req.dietary_tags);
+    if let Some(tag) = conflicting_tags(&dietary_tags, &allergens).first() {
+        return Err(format!("A product containing {} can't be tagged {}", allergens.join(", "), tag));
+    }
+
+    let mut option_groups = req.option_groups.clone();
+    for option in option_groups.iter_mut().flat_map(|g| g.options.iter_mut()) {
+        option.allergens = normalise_allergens(&option.allergens)?;
+    }
+
+    let mut group_ids = HashSet::new();
+    for group in &option_groups {
+        if !group_ids.insert(group.id.as_str()) {
+            return Err(format!("Option group '{}' appears twice", group.id));
+        }
+        let mut option_ids = HashSet::new();
+        if group.options.iter().any(|o| !option_ids.insert(o.id.as_str()) || !o.price_delta.is_finite()) {
+            return Err(format!("Option group '{}' has duplicate or badly priced options", group.id));
+        }
+        let max = group.max_select.unwrap_or(group.options.len() as u32);
+        if group.min_select > max || group.min_select as usize > group.options.len() {
+            return Err(format!("Option group '{}' asks for more choices than it offers", group.id));
+        }
+    }
+
+    let category = req.category.as_deref().map(|c| c.trim().to_lowercase()).unwrap_or_default();
+    Ok(Product {
+        item_id: item_id.to_string(),
+        title: title.to_string(),
+        price: req.price,
+        description: req.description.as_deref().map(str::trim).filter(|d| !d.is_empty()).map(str::to_string),
+        image_src: req.image_src.trim().to_string(),
+        images: req.images.iter().map(|i| i.trim().to_string()).filter(|i| !i.is_empty()).collect(),
+        sort_order: req.sort_order,
+        category: if category.is_empty() { DEFAULT_CATEGORY.to_string() } else { category },
+        dietary_tags,
+        allergens,
+        available: req.available,
+        available_from: req.available_from,
+        available_until: req.available_until,
+        option_groups,
+        personalisable: req.personalisable,
+        stock_tracking: None,
+        deleted_at: None,
+    })
+}
+
+/// GET /api/admin/products
+/// The whole catalog, including hidden items; `?include_deleted=true` adds deleted ones
+pub async fn admin_list_products(
+    products: web::Data<dyn ProductRepo>,
+    query: web::Query<AdminProductQuery>,
+) -> impl Responder {
+    match products.list(query.include_deleted).await {
+        Ok(products) => HttpResponse::Ok().json(products),
+        Err(e) => database_error(e),
+    }
+}
+
+/// POST /api/admin/products
+pub async fn create_product(
+    products: web::Data<dyn ProductRepo>,
+    req: web::Json<CreateProductRequest>,
+) -> impl Responder {
+    let product = match validate_product(req.item_id.trim(), &req.product) {
+        Ok(product) => product,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    match products.insert(&product).await {
+        Ok(()) => {
+            tracing::info!(item_id = %product.item_id, price = product.price, "Product created");
+            HttpResponse::Created().json(product)
+        },
+        Err(RepoError::Duplicate) => HttpResponse::Conflict().json(ErrorResponse {
+            message: "A product with this item id already exists (it may have been deleted)".to_string(),
+        }),
+        Err(e) => database_error(e),
+    }
+}
+
+/// PUT /api/admin/products/{item_id}
+/// Replaces a product's details; price changes are recorded in its price history
+pub async fn update_product(
+    products: web::Data<dyn ProductRepo>,
+    price_changes: web::Data<dyn PriceChangeRepo>,
+    path: web::Path<String>,
+    req: web::Json<ProductRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    let item_id = path.into_inner();
+    let admin_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+    let product = match validate_product(&item_id, &req) {
+        Ok(product) => product,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    // 1. Stock tracking is managed separately, so carry it over
+    let existing = match products.find_by_ids(std::slice::from_ref(&item_id)).await {
+        Ok(found) => found.into_iter().find(|p| p.deleted_at.is_none()),
+        Err(e) => return database_error(e),
+    };
+    let Some(existing) = existing else {
+        return HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() });
+    };
+    let product = Product { stock_tracking: existing.stock_tracking, ..product };
+
+    // 2. Save, keeping the version we replaced for the audit
+    let previous = match products.update(&product).await {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() }),
+        Err(e) => return database_error(e),
+    };
+
+    // 3. Record who changed the price
+    if previous.price != product.price {
+        let change = PriceChange {
+            id: None,
+            item_id: item_id.clone(),
+            old_price: previous.price,
+            new_price: product.price,
+            changed_by: admin_email,
+            changed_at: Utc::now(),
+        };
+        tracing::info!(item_id = %item_id, old_price = change.old_price, new_price = change.new_price, changed_by = %change.changed_by, "Product price changed");
+        if let Err(e) = price_changes.insert(&change).await {
+            tracing::error!(item_id = %item_id, error = %e, "Failed to record price change");
+        }
+    }
+
+    tracing::info!(item_id = %item_id, "Product updated");
+    HttpResponse::Ok().json(product)
+}
+
+/// DELETE /api/admin/products/{item_id}
+/// Takes a product off the menu; past orders keep pointing at it
+pub async fn delete_product(
+    products: web::Data<dyn ProductRepo>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match products.soft_delete(&path, Utc::now()).await {
+        Ok(true) => {
+            tracing::info!(item_id = %path, "Product deleted");
+            HttpResponse::Ok().json(json!({ "message": "Product deleted" }))
+        },
+        Ok(false) => HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
+
+/// GET /api/admin/products/{item_id}/price-history
+pub async fn get_price_history(
+    price_changes: web::Data<dyn PriceChangeRepo>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match price_changes.list_for_item(&path).await {
+        Ok(changes) => HttpResponse::Ok().json(changes),
+        Err(e) => database_error(e),
+    }
+}
@@ -0,0 +1,200 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use crate::models::order::OrderItem;
+use crate::models::product::{Product, DEFAULT_CATEGORY};
+use crate::models::promotion::{Discount, Promotion, PromotionKind};
+use crate::repository::{PromotionRepo, RepoError};
+
+const MAX_CODE_LEN: usize = 32;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PromotionRequest {
+    description: Option<String>,
+    kind: PromotionKind,
+    #[serde(default = "default_true")]
+    active: bool,
+    starts_at: Option<DateTime<Utc>>,
+    ends_at: Option<DateTime<Utc>>,
+    max_uses: Option<u32>,
+    max_uses_per_user: Option<u32>,
+    #[serde(default)]
+    min_order_total: f64,
+    #[serde(default)]
+    categories: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreatePromotionRequest {
+    code: String,
+    #[serde(flatten)]
+    promotion: PromotionRequest,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    tracing::error!(error = %e, "Database error in promotions");
+    HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+}
+
+/// Codes are case-insensitive for customers and stored uppercase
+pub fn normalise_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+/// (category, line total) for each cart line, as promotions see them
+pub fn cart_lines(items: &[OrderItem], catalog: &[Product]) -> Vec<(String, f64)> {
+    items.iter().map(|item| {
+        let category = catalog.iter()
+            .find(|p| p.item_id == item.item_id)
+            .map_or(DEFAULT_CATEGORY, |p| p.category.as_str());
+        (category.to_string(), item.price * item.quantity as f64)
+    }).collect()
+}
+
+/// Look up a code and work out what it takes off this cart
+pub async fn evaluate_promotion(
+    promotions: &dyn PromotionRepo,
+    code: &str,
+    user_email: &str,
+    lines: &[(String, f64)],
+    delivery_fee: f64,
+) -> Result<(Promotion, Discount), HttpResponse> {
+    let code = normalise_code(code);
+    let promotion = match promotions.find(&code).await {
+        Ok(Some(promotion)) => promotion,
+        Ok(None) => return Err(HttpResponse::BadRequest().json(ErrorResponse { message: format!("{} isn't a valid promo code", code) })),
+        Err(e) => return Err(database_error(e)),
+    };
+    let user_uses = match promotions.user_uses(&code, user_email).await {
+        Ok(uses) => uses,
+        Err(e) => return Err(database_error(e)),
+    };
+
+    match promotion.evaluate(lines, delivery_fee, user_uses, Utc::now()) {
+        Ok(discount) => Ok((promotion, discount)),
+        Err(message) => Err(HttpResponse::BadRequest().json(ErrorResponse { message })),
+    }
+}
+
+/// Count the use against the code's limits; fails if another order just used up the last one
+pub async fn redeem_promotion(promotions: &dyn PromotionRepo, promotion: &Promotion, user_email: &str) -> Result<(), HttpResponse> {
+    match promotions.redeem(promotion, user_email).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(HttpResponse::Conflict().json(ErrorResponse { message: format!("{} is no longer available", promotion.code) })),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// Give a use back to the code
+pub async fn release_promotion(promotions: &dyn PromotionRepo, discount: Option<&Discount>, user_email: &str) {
+    let Some(discount) = discount else { return };
+    if let Err(e) = promotions.release(&discount.code, user_email).await {
+        tracing::error!(code = %discount.code, error = %e, "Failed to release promo code use");
+    }
+}
+
+// Check and normalise an admin's promotion
+fn validate_promotion(code: &str, req: &PromotionRequest) -> Result<Promotion, String> {
+    let code = normalise_code(code);
+    if code.len() < 3 || code.len() > MAX_CODE_LEN || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Code must be 3-{} letters, digits, dashes or underscores", MAX_CODE_LEN));
+    }
+    match req.kind {
+        PromotionKind::Percentage { percent } if !(percent > 0.0 && percent <= 100.0) => {
+            return Err("Percentage must be more than 0 and at most 100".to_string());
+        }
+        PromotionKind::FixedAmount { amount } if !(amount.is_finite() && amount > 0.0) => {
+            return Err("Amount must be more than 0".to_string());
+        }
+        _ => (),
+    }
+    if !req.min_order_total.is_finite() || req.min_order_total < 0.0 {
+        return Err("Minimum order total must be zero or more".to_string());
+    }
+    if let (Some(start), Some(end)) = (req.starts_at, req.ends_at) {
+        if start >= end {
+            return Err("Promotion must end after it starts".to_string());
+        }
+    }
+
+    let mut categories: Vec<String> = req.categories.iter().map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty()).collect();
+    categories.sort();
+    categories.dedup();
+    Ok(Promotion {
+        code,
+        description: req.description.as_deref().map(str::trim).filter(|d| !d.is_empty()).map(str::to_string),
+        kind: req.kind.clone(),
+        active: req.active,
+        starts_at: req.starts_at,
+        ends_at: req.ends_at,
+        max_uses: req.max_uses,
+        max_uses_per_user: req.max_uses_per_user,
+        min_order_total: req.min_order_total,
+        categories,
+        uses: 0,
+    })
+}
+
+/// GET /api/admin/promotions
+pub async fn list_promotions(promotions: web::Data<dyn PromotionRepo>) -> impl Responder {
+    match promotions.list().await {
+        Ok(promotions) => HttpResponse::Ok().json(promotions),
+        Err(e) => database_error(e),
+    }
+}
+
+/// POST /api/admin/promotions
+pub async fn create_promotion(
+    promotions: web::Data<dyn PromotionRepo>,
+    req: web::Json<CreatePromotionRequest>,
+) -> impl Responder {
+    let promotion = match validate_promotion(&req.code, &req.promotion) {
+        Ok(promotion) => promotion,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    match promotions.insert(&promotion).await {
+        Ok(()) => {
+            tracing::info!(code = %promotion.code, kind = promotion.kind.name(), "Promotion created");
+            HttpResponse::Created().json(promotion)
+        },
+        Err(RepoError::Duplicate) => HttpResponse::Conflict().json(ErrorResponse { message: "A promotion with this code already exists".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
+
+/// PUT /api/admin/promotions/{code}
+/// Replaces a promotion's rules; its use count is kept. Set `active: false` to withdraw a code.
+pub async fn update_promotion(
+    promotions: web::Data<dyn PromotionRepo>,
+    path: web::Path<String>,
+    req: web::Json<PromotionRequest>,
+) -> impl Responder {
+    let promotion = match validate_promotion(&path, &req) {
+        Ok(promotion) => promotion,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    match promotions.update(&promotion).await {
+        Ok(true) => {
+            tracing::info!(code = %promotion.code, active = promotion.active, "Promotion updated");
+            match promotions.find(&promotion.code).await {
+                Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+                Ok(None) => HttpResponse::NotFound().json(ErrorResponse { message: "Promotion not found".to_string() }),
+                Err(e) => database_error(e),
+            }
+        },
+        Ok(false) => HttpResponse::NotFound().json(ErrorResponse { message: "Promotion not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
@@ -0,0 +1,412 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use chrono::{DateTime, Days, Duration, NaiveDate, NaiveTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use std::collections::BTreeMap;
+
+use crate::config::SlotConfig;
+use crate::models::slot::{slot_id, SlotReservation, SlotRule};
+use crate::repository::SlotRepo;
+
+const DEFAULT_DAYS: u32 = 7;
+const TIME_FORMAT: &str = "%H:%M";
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SlotListQuery {
+    days: Option<u32>,
+    category: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SlotRuleRequest {
+    category: String,
+    start: String,
+    end: String,
+    capacity: u32,
+}
+
+#[derive(Serialize)]
+struct AvailableSlot {
+    slot_id: String,
+    date: String,
+    category: String,
+    start: String,
+    end: String,
+    capacity: u32,
+    remaining: u32,
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), TIME_FORMAT).ok()
+}
+
+fn rule_window(rule: &SlotRule) -> Option<(NaiveTime, NaiveTime)> {
+    Some((parse_time(&rule.start)?, parse_time(&rule.end)?))
+}
+
+// Check and normalise an admin's rule
+fn validate_rule(req: &SlotRuleRequest) -> Result<SlotRule, String> {
+    let category = req.category.trim().to_lowercase();
+    if category.is_empty() {
+        return Err("Category required".to_string());
+    }
+    let (start, end) = match (parse_time(&req.start), parse_time(&req.end)) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        (Some(_), Some(_)) => return Err("Window must end after it starts".to_string()),
+        _ => return Err("Times must be HH:MM".to_string()),
+    };
+    Ok(SlotRule {
+        id: None,
+        category,
+        start: start.format(TIME_FORMAT).to_string(),
+        end: end.format(TIME_FORMAT).to_string(),
+        capacity: req.capacity,
+    })
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    tracing::error!(error = %e, "Database error in slots");
+    HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+}
+
+// A window is offered while it hasn't started and its day is within `max_days` of today
+fn is_bookable(config: &SlotConfig, date: NaiveDate, rule: &SlotRule) -> bool {
+    let now = Utc::now();
+    let today = now.with_timezone(&config.utc_offset).date_naive();
+    let in_range = today.checked_add_days(Days::new(config.max_days as u64)).is_some_and(|last| date >= today && date < last);
+    let starts_at = rule_window(rule).and_then(|(start, _)| date.and_time(start).and_local_timezone(config.utc_offset).single());
+    in_range && starts_at.is_some_and(|starts_at| starts_at > now)
+}
+
+/// Reserve capacity for an order wanted at `at`. `demand` is units per product category;
+/// categories without slot rules are unlimited. All or nothing: on failure nothing stays booked.
+pub async fn reserve_slots(
+    slots: &dyn SlotRepo,
+    config: &SlotConfig,
+    at: Option<DateTime<Utc>>,
+    demand: &BTreeMap<String, u32>,
+) -> Result<Vec<SlotReservation>, HttpResponse> {
+    let rules = slots.list_rules().await.map_err(database_error)?;
+
+    let mut reserved: Vec<SlotReservation> = Vec::new();
+    for (category, &quantity) in demand {
+        let category_rules: Vec<&SlotRule> = rules.iter().filter(|r| &r.category == category).collect();
+        if category_rules.is_empty() || quantity == 0 {
+            continue;
+        }
+
+        let failure = match at {
+            None => Some(HttpResponse::BadRequest().json(ErrorResponse { message: format!("Choose a pickup or delivery time for {}", category) })),
+            Some(at) => {
+                let local = at.with_timezone(&config.utc_offset);
+                let rule = category_rules.iter().find(|r| {
+                    rule_window(r).is_some_and(|(start, end)| (start..end).contains(&local.time()))
+                });
+                match rule {
+                    None => Some(HttpResponse::BadRequest().json(ErrorResponse { message: format!("No {} slot at that time", category) })),
+                    // Only the windows `list_available_slots` offers can be booked
+                    Some(rule) if !is_bookable(config, local.date_naive(), rule) => Some(HttpResponse::BadRequest().json(ErrorResponse {
+                        message: format!("{} slots can only be booked for windows that haven't started, up to {} days ahead", category, config.max_days),
+                    })),
+                    Some(rule) => {
+                        let id = slot_id(&local.date_naive().to_string(), category, &rule.start);
+                        // Bookings are only needed until the day is over
+                        match slots.reserve(&id, quantity, rule.capacity, at + Duration::days(2)).await {
+                            Ok(true) => {
+                                reserved.push(SlotReservation { slot_id: id, quantity });
+                                None
+                            }
+                            Ok(false) => Some(HttpResponse::Conflict().json(ErrorResponse {
+                                message: format!("The {}-{} {} slot on {} is full", rule.start, rule.end, category, local.date_naive()),
+                            })),
+                            Err(e) => Some(database_error(e)),
+                        }
+                    }
+                }
+            }
+        };
+
+        if let Some(res) = failure {
+            release_slots(slots, &reserved).await;
+            return Err(res);
+        }
+    }
+    Ok(reserved)
+}
+
+/// Give back capacity taken by `reserve_slots`
+pub async fn release_slots(slots: &dyn SlotRepo, reservations: &[SlotReservation]) {
+    for reservation in reservations {
+        if let Err(e) = slots.release(&reservation.slot_id, reservation.quantity).await {
+            tracing::error!(slot_id = %reservation.slot_id, error = %e, "Failed to release slot capacity");
+        }
+    }
+}
+
+/// Book again the capacity a failed payment gave back; fails if a slot has filled up since.
+/// All or nothing, like `reserve_slots`.
+pub async fn retake_slots(slots: &dyn SlotRepo, reservations: &[SlotReservation]) -> Result<(), HttpResponse> {
+    if reservations.is_empty() {
+        return Ok(());
+    }
+    let rules = slots.list_rules().await.map_err(database_error)?;
+
+    let mut retaken: Vec<SlotReservation> = Vec::new();
+    for reservation in reservations {
+        let mut parts = reservation.slot_id.splitn(3, '|');
+        let (Some(date), Some(category), Some(start)) = (parts.next(), parts.next(), parts.next()) else { continue };
+        // A rule removed since the order was placed leaves that window unlimited
+        let Some(rule) = rules.iter().find(|r| r.category == category && r.start == start) else { continue };
+        let expires_at = date.parse::<NaiveDate>().ok()
+            .and_then(|day| day.and_hms_opt(0, 0, 0))
+            .map(|midnight| midnight.and_utc() + Duration::days(3))
+            .unwrap_or_else(|| Utc::now() + Duration::days(2));
+
+        let failure = match slots.reserve(&reservation.slot_id, reservation.quantity, rule.capacity, expires_at).await {
+            Ok(true) => {
+                retaken.push(reservation.clone());
+                continue;
+            }
+            Ok(false) => HttpResponse::Conflict().json(ErrorResponse {
+                message: format!("The {}-{} {} slot on {} is full", rule.start, rule.end, category, date),
+            }),
+            Err(e) => database_error(e),
+        };
+        release_slots(slots, &retaken).await;
+        return Err(failure);
+    }
+    Ok(())
+}
+
+/// GET /api/slots?days=7&category=cakes
+/// Slots with capacity left over the next N days (bakery local time)
+pub async fn list_available_slots(
+    slots: web::Data<dyn SlotRepo>,
+    config: web::Data<SlotConfig>,
+    query: web::Query<SlotListQuery>,
+) -> impl Responder {
+    let days = query.days.unwrap_or(DEFAULT_DAYS).clamp(1, config.max_days);
+    let category = query.category.as_deref().map(|c| c.trim().to_lowercase());
+
+    let mut rules = match slots.list_rules().await {
+        Ok(rules) => rules,
+        Err(e) => return database_error(e),
+    };
+    rules.retain(|r| category.as_ref().is_none_or(|c| &r.category == c));
+    rules.sort_by(|a, b| (&a.start, &a.category).cmp(&(&b.start, &b.category)));
+
+    // 1. Every future window of every rule
+    let now = Utc::now();
+    let today = now.with_timezone(&config.utc_offset).date_naive();
+    let mut candidates = Vec::new();
+    for day in 0..days {
+        let Some(date) = today.checked_add_days(Days::new(day as u64)) else { break };
+        for rule in &rules {
+            if is_bookable(&config, date, rule) {
+                candidates.push((date.to_string(), rule));
+            }
+        }
+    }
+
+    // 2. Subtract what's booked
+    let ids: Vec<String> = candidates.iter().map(|(date, rule)| slot_id(date, &rule.category, &rule.start)).collect();
+    let booked = match slots.booked(&ids).await {
+        Ok(booked) => booked,
+        Err(e) => return database_error(e),
+    };
+
+    let available: Vec<AvailableSlot> = candidates.into_iter().zip(ids)
+        .filter_map(|((date, rule), id)| {
+            let remaining = rule.capacity.saturating_sub(booked.get(&id).copied().unwrap_or(0));
+            (remaining > 0).then(|| AvailableSlot {
+                slot_id: id,
+                date,
+                category: rule.category.clone(),
+                start: rule.start.clone(),
+                end: rule.end.clone(),
+                capacity: rule.capacity,
+                remaining,
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(available)
+}
+
+/// GET /api/admin/slot-rules
+pub async fn list_slot_rules(slots: web::Data<dyn SlotRepo>) -> impl Responder {
+    match slots.list_rules().await {
+        Ok(rules) => HttpResponse::Ok().json(rules),
+        Err(e) => database_error(e),
+    }
+}
+
+/// POST /api/admin/slot-rules
+/// Adds a daily capacity for a category and time window
+pub async fn create_slot_rule(
+    slots: web::Data<dyn SlotRepo>,
+    req: web::Json<SlotRuleRequest>,
+) -> impl Responder {
+    let rule = match validate_rule(&req) {
+        Ok(rule) => rule,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    match slots.insert_rule(&rule).await {
+        Ok(id) => {
+            tracing::info!(rule_id = %id, category = %rule.category, start = %rule.start, capacity = rule.capacity, "Slot rule created");
+            HttpResponse::Created().json(SlotRule { id: Some(id), ..rule })
+        },
+        Err(e) => database_error(e),
+    }
+}
+
+/// PUT /api/admin/slot-rules/{id}
+pub async fn update_slot_rule(
+    slots: web::Data<dyn SlotRepo>,
+    path: web::Path<String>,
+    req: web::Json<SlotRuleRequest>,
+) -> impl Responder {
+    let Ok(id) = ObjectId::parse_str(path.as_str()) else {
+        return HttpResponse::NotFound().json(ErrorResponse { message: "Slot rule not found".to_string() });
+    };
+    let rule = match validate_rule(&req) {
+        Ok(rule) => rule,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    match slots.update_rule(id, &rule).await {
+        Ok(true) => {
+            tracing::info!(rule_id = %id, capacity = rule.capacity, "Slot rule updated");
+            HttpResponse::Ok().json(SlotRule { id: Some(id), ..rule })
+        },
+        Ok(false) => HttpResponse::NotFound().json(ErrorResponse { message: "Slot rule not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
+
+/// DELETE /api/admin/slot-rules/{id}
+pub async fn delete_slot_rule(
+    slots: web::Data<dyn SlotRepo>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let Ok(id) = ObjectId::parse_str(path.as_str()) else {
+        return HttpResponse::NotFound().json(ErrorResponse { message: "Slot rule not found".to_string() });
+    };
+
+    match slots.delete_rule(id).await {
+        Ok(true) => {
+            tracing::info!(rule_id = %id, "Slot rule deleted");
+            HttpResponse::Ok().json(json!({ "message": "Slot rule deleted" }))
+        },
+        Ok(false) => HttpResponse::NotFound().json(ErrorResponse { message: "Slot rule not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::repository::slot::MemorySlotRepo;
+
+    fn config() -> SlotConfig {
+        SlotConfig { utc_offset: FixedOffset::east_opt(3 * 3600).unwrap(), max_days: 14 }
+    }
+
+    // Noon bakery time, `days` from today
+    fn noon(days: u64) -> DateTime<Utc> {
+        let config = config();
+        let date = Utc::now().with_timezone(&config.utc_offset).date_naive() + Days::new(days);
+        config.utc_offset.from_local_datetime(&date.and_hms_opt(12, 0, 0).unwrap()).unwrap().with_timezone(&Utc)
+    }
+
+    async fn repo_with_rules(rules: &[(&str, u32)]) -> MemorySlotRepo {
+        let slots = MemorySlotRepo::default();
+        for (category, capacity) in rules {
+            let rule = SlotRule { id: None, category: category.to_string(), start: "09:00".to_string(), end: "17:00".to_string(), capacity: *capacity };
+            slots.insert_rule(&rule).await.unwrap();
+        }
+        slots
+    }
+
+    fn demand(items: &[(&str, u32)]) -> BTreeMap<String, u32> {
+        items.iter().map(|(category, quantity)| (category.to_string(), *quantity)).collect()
+    }
+
+    async fn booked(slots: &MemorySlotRepo, reservations: &[SlotReservation]) -> u32 {
+        let ids: Vec<String> = reservations.iter().map(|r| r.slot_id.clone()).collect();
+        slots.booked(&ids).await.unwrap().values().sum()
+    }
+
+    #[actix_web::test]
+    async fn reserves_up_to_capacity_and_releases() {
+        let slots = repo_with_rules(&[("cakes", 3)]).await;
+        let at = Some(noon(1));
+
+        let first = reserve_slots(&slots, &config(), at, &demand(&[("cakes", 2)])).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(booked(&slots, &first).await, 2);
+
+        let full = reserve_slots(&slots, &config(), at, &demand(&[("cakes", 2)])).await.unwrap_err();
+        assert_eq!(full.status(), StatusCode::CONFLICT);
+
+        release_slots(&slots, &first).await;
+        assert_eq!(booked(&slots, &first).await, 0);
+        assert!(reserve_slots(&slots, &config(), at, &demand(&[("cakes", 3)])).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn books_all_categories_or_none() {
+        let slots = repo_with_rules(&[("cakes", 5), ("pies", 1)]).await;
+        let at = Some(noon(1));
+
+        let err = reserve_slots(&slots, &config(), at, &demand(&[("cakes", 2), ("pies", 2)])).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        let cakes = [SlotReservation { slot_id: slot_id(&noon(1).with_timezone(&config().utc_offset).date_naive().to_string(), "cakes", "09:00"), quantity: 0 }];
+        assert_eq!(booked(&slots, &cakes).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn categories_without_rules_are_unlimited() {
+        let slots = repo_with_rules(&[("cakes", 1)]).await;
+        let reserved = reserve_slots(&slots, &config(), None, &demand(&[("cookies", 500)])).await.unwrap();
+        assert!(reserved.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn only_offered_windows_can_be_booked() {
+        let slots = repo_with_rules(&[("cakes", 5)]).await;
+        let cakes = demand(&[("cakes", 1)]);
+
+        for at in [None, Some(noon(1) + Duration::hours(6)), Some(noon(0) - Duration::days(1)), Some(noon(14))] {
+            let err = reserve_slots(&slots, &config(), at, &cakes).await.unwrap_err();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST, "{:?}", at);
+        }
+    }
+
+    #[actix_web::test]
+    async fn retakes_released_slots_while_there_is_room() {
+        let slots = repo_with_rules(&[("cakes", 2)]).await;
+        let at = Some(noon(2));
+
+        let held = reserve_slots(&slots, &config(), at, &demand(&[("cakes", 2)])).await.unwrap();
+        release_slots(&slots, &held).await;
+        retake_slots(&slots, &held).await.unwrap();
+        assert_eq!(booked(&slots, &held).await, 2);
+
+        release_slots(&slots, &held).await;
+        reserve_slots(&slots, &config(), at, &demand(&[("cakes", 1)])).await.unwrap();
+        let err = retake_slots(&slots, &held).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert_eq!(booked(&slots, &held).await, 1);
+    }
+}
@@ -0,0 +1,184 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use std::collections::BTreeMap;
+
+use crate::config::SlotConfig;
+use crate::models::order::{OrderItem, StockReservation};
+use crate::models::product::{Product, StockTracking};
+use crate::repository::{ProductRepo, RepoResult, StockRepo};
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetStockRequest {
+    quantity: u32,
+    /// Only for daily stock; defaults to today
+    date: Option<NaiveDate>,
+    /// Defaults to daily when a date is given, otherwise total
+    tracking: Option<StockTracking>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SoldOutRequest {
+    sold_out: bool,
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    tracing::error!(error = %e, "Database error in stock");
+    HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+}
+
+/// The bakery-local day an order draws daily stock from: its pickup/delivery day, or today
+pub fn stock_date(config: &SlotConfig, at: Option<DateTime<Utc>>) -> NaiveDate {
+    at.unwrap_or_else(Utc::now).with_timezone(&config.utc_offset).date_naive()
+}
+
+/// Take every reservation or none of them. Returns the index of the one that ran out, if any.
+async fn take_all(stock: &dyn StockRepo, reservations: &[StockReservation]) -> RepoResult<Option<usize>> {
+    for (i, reservation) in reservations.iter().enumerate() {
+        match stock.reserve(&reservation.stock_id, reservation.quantity).await {
+            Ok(true) => continue,
+            Ok(false) => {
+                release_stock(stock, &reservations[..i]).await;
+                return Ok(Some(i));
+            },
+            Err(e) => {
+                release_stock(stock, &reservations[..i]).await;
+                return Err(e);
+            },
+        }
+    }
+    Ok(None)
+}
+
+/// Reserve stock for the tracked products in an order. Untracked products are unlimited.
+pub async fn reserve_stock(
+    stock: &dyn StockRepo,
+    catalog: &[Product],
+    items: &[OrderItem],
+    date: NaiveDate,
+) -> Result<Vec<StockReservation>, HttpResponse> {
+    // Several lines can draw on the same stock (e.g. two custom cakes)
+    let mut wanted: BTreeMap<String, (u32, &str)> = BTreeMap::new();
+    for item in items {
+        let Some(product) = catalog.iter().find(|p| p.item_id == item.item_id) else { continue };
+        if let Some(stock_id) = product.stock_id(date) {
+            wanted.entry(stock_id).or_insert((0, product.title.as_str())).0 += item.quantity;
+        }
+    }
+
+    let reservations: Vec<StockReservation> = wanted.iter()
+        .map(|(stock_id, (quantity, _))| StockReservation { stock_id: stock_id.clone(), quantity: *quantity })
+        .collect();
+
+    match take_all(stock, &reservations).await {
+        Ok(None) => Ok(reservations),
+        Ok(Some(i)) => {
+            let (stock_id, (_, title)) = wanted.iter().nth(i).expect("reservations mirror wanted");
+            let left = stock.levels(std::slice::from_ref(stock_id)).await.ok()
+                .and_then(|levels| levels.get(stock_id).copied())
+                .unwrap_or(0);
+            let message = if left == 0 {
+                format!("{} is sold out", title)
+            } else {
+                format!("Only {} {} left", left, title)
+            };
+            Err(HttpResponse::Conflict().json(ErrorResponse { message }))
+        },
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// Take the stock back for an order that released it (e.g. retrying a failed payment)
+pub async fn retake_stock(stock: &dyn StockRepo, reservations: &[StockReservation]) -> Result<(), HttpResponse> {
+    match take_all(stock, reservations).await {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Conflict().json(ErrorResponse {
+            message: "Some items in this order have sold out since it was placed".to_string(),
+        })),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// Put reserved stock back on the shelf
+pub async fn release_stock(stock: &dyn StockRepo, reservations: &[StockReservation]) {
+    for reservation in reservations {
+        if let Err(e) = stock.release(&reservation.stock_id, reservation.quantity).await {
+            tracing::error!(stock_id = %reservation.stock_id, error = %e, "Failed to release stock");
+        }
+    }
+}
+
+/// PUT /api/admin/products/{item_id}/stock
+/// Sets how many are left, in total or for one day
+pub async fn set_stock(
+    products: web::Data<dyn ProductRepo>,
+    stock: web::Data<dyn StockRepo>,
+    config: web::Data<SlotConfig>,
+    path: web::Path<String>,
+    req: web::Json<SetStockRequest>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+    let tracking = req.tracking.unwrap_or(if req.date.is_some() { StockTracking::Daily } else { StockTracking::Total });
+    if tracking == StockTracking::Total && req.date.is_some() {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "A date only applies to daily stock".to_string() });
+    }
+
+    // 1. Switch the product to this kind of tracking
+    match products.set_stock_tracking(&item_id, Some(tracking)).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() }),
+        Err(e) => return database_error(e),
+    }
+
+    // 2. Set the level; daily levels are dropped a couple of days after their date
+    let (stock_id, date, expires_at) = match tracking {
+        StockTracking::Total => (item_id.clone(), None, None),
+        StockTracking::Daily => {
+            let date = req.date.unwrap_or_else(|| stock_date(&config, None));
+            let expires_at = date.checked_add_days(Days::new(2)).and_then(|d| d.and_hms_opt(0, 0, 0)).map(|d| d.and_utc());
+            (format!("{}|{}", item_id, date), Some(date), expires_at)
+        }
+    };
+    if let Err(e) = stock.set_level(&stock_id, &item_id, req.quantity, expires_at).await {
+        return database_error(e);
+    }
+
+    tracing::info!(item_id = %item_id, quantity = req.quantity, date = ?date, "Stock level set");
+    HttpResponse::Ok().json(json!({ "item_id": item_id, "tracking": tracking, "date": date, "quantity": req.quantity }))
+}
+
+/// DELETE /api/admin/products/{item_id}/stock
+/// Stops tracking stock; the product becomes unlimited
+pub async fn stop_tracking_stock(
+    products: web::Data<dyn ProductRepo>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match products.set_stock_tracking(&path, None).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Stock tracking stopped" })),
+        Ok(false) => HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
+
+/// PUT /api/admin/products/{item_id}/sold-out
+/// Marks a product sold out (or back on sale) regardless of stock
+pub async fn set_sold_out(
+    products: web::Data<dyn ProductRepo>,
+    path: web::Path<String>,
+    req: web::Json<SoldOutRequest>,
+) -> impl Responder {
+    match products.set_available(&path, !req.sold_out).await {
+        Ok(true) => {
+            tracing::info!(item_id = %path, sold_out = req.sold_out, "Product availability changed");
+            HttpResponse::Ok().json(json!({ "item_id": path.into_inner(), "sold_out": req.sold_out }))
+        },
+        Ok(false) => HttpResponse::NotFound().json(ErrorResponse { message: "Product not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
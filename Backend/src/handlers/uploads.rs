@@ -0,0 +1,110 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest, http::header};
+use serde::Serialize;
+use serde_json::json;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::middleware::admin::is_admin;
+use crate::models::upload::Upload;
+use crate::repository::{UploadRepo, UserRepo};
+use crate::storage::FileStorage;
+use crate::utils::imaging;
+use crate::utils::jwt::get_user_email_from_req;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// POST /api/uploads/reference-image
+/// Stores a cake reference photo sent as the raw request body (JPEG, PNG or WebP).
+/// Returns an id to send as `reference_image` on the order line.
+pub async fn upload_reference_image(
+    uploads: web::Data<dyn UploadRepo>,
+    storage: web::Data<dyn FileStorage>,
+    body: web::Bytes,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Authenticate User
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+
+    // 2. Only real images (the size limit is enforced by the route's PayloadConfig)
+    let Some((content_type, extension)) = imaging::sniff(&body) else {
+        return HttpResponse::UnsupportedMediaType().json(ErrorResponse { message: "Upload a JPEG, PNG or WebP image".to_string() });
+    };
+
+    // 3. Save the file, then its record
+    let id = Uuid::new_v4().to_string();
+    let upload = Upload {
+        key: format!("reference/{}.{}", id, extension),
+        id,
+        owner_email: user_email,
+        content_type: content_type.to_string(),
+        size: body.len() as u64,
+        created_at: Utc::now(),
+    };
+
+    if let Err(e) = storage.put(&upload.key, &body).await {
+        tracing::error!(error = %e, "Failed to store reference image");
+        return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to store image".to_string() });
+    }
+    match uploads.insert(&upload).await {
+        Ok(_) => {
+            tracing::info!(upload_id = %upload.id, size = upload.size, content_type, "Reference image uploaded");
+            HttpResponse::Created().json(json!({ "id": upload.id, "content_type": upload.content_type, "size": upload.size }))
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to save upload record");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to store image".to_string() })
+        }
+    }
+}
+
+/// GET /api/uploads/{id}
+/// The image itself, for its owner or for admins preparing the order
+pub async fn get_upload(
+    uploads: web::Data<dyn UploadRepo>,
+    users: web::Data<dyn UserRepo>,
+    storage: web::Data<dyn FileStorage>,
+    path: web::Path<String>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Authenticate User
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+    let not_found = || HttpResponse::NotFound().json(ErrorResponse { message: "Image not found".to_string() });
+
+    // 2. Find the upload and check access
+    let upload = match uploads.find_by_id(&path).await {
+        Ok(Some(upload)) => upload,
+        Ok(None) => return not_found(),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error loading upload");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() });
+        }
+    };
+    if upload.owner_email != user_email {
+        let admin = matches!(users.find_by_email(&user_email).await, Ok(Some(user)) if is_admin(&user));
+        if !admin {
+            return not_found();
+        }
+    }
+
+    // 3. Serve the file
+    match storage.get(&upload.key).await {
+        Ok(Some(bytes)) => HttpResponse::Ok()
+            .content_type(upload.content_type)
+            .insert_header((header::CACHE_CONTROL, "private, max-age=86400"))
+            .body(bytes),
+        Ok(None) => not_found(),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read upload");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to read image".to_string() })
+        }
+    }
+}
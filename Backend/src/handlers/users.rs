@@ -0,0 +1,665 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use chrono::{Duration, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::config::{self, FulfilmentConfig, RateLimitConfig};
+use crate::handlers::auth::google_token_info;
+use crate::handlers::mpesa::normalize_phone;
+use crate::middleware::rate_limit::{self, too_many_requests};
+use crate::models::product::{normalise_allergens, ALLERGENS};
+use crate::models::user::{normalise_email, DeliveryAddress, MarketingPreferences, Profile, PublicUser, User};
+use crate::repository::{FavoriteRepo, LoyaltyRepo, OrderRepo, PromotionRepo, RateLimitRepo, RepoError, UploadRepo, UserRepo};
+use crate::utils::geo;
+use crate::utils::jwt::{self, get_user_email_from_req};
+use crate::utils::password;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_ADDRESS_LEN: usize = 300;
+const MAX_DELIVERY_NOTES_LEN: usize = 500;
+const MAX_EMAIL_LEN: usize = 254;
+const MIN_PASSWORD_LEN: usize = 8;
+// How recently a Google-only customer must have signed in to change their email
+const GOOGLE_REAUTH_MINUTES: i64 = 5;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AllergiesRequest {
+    allergies: Vec<String>,
+}
+
+// Fields left out are unchanged; optional fields sent as null are cleared
+#[derive(Deserialize, Debug)]
+pub struct UpdateProfileRequest {
+    name: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    phone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    default_address: Option<Option<DeliveryAddress>>,
+    #[serde(default, deserialize_with = "present")]
+    birthday: Option<Option<NaiveDate>>,
+    marketing: Option<MarketingPreferences>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeEmailRequest {
+    new_email: String,
+    password: Option<String>, // Required unless the account only signs in with Google
+    google_credential: Option<String>, // A fresh Google Sign-In ID token, for accounts without a password
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConfirmEmailRequest {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+// Tells a field sent as null (Some(None)) apart from one left out (None)
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    tracing::error!(error = %e, "Database error in users");
+    HttpResponse::InternalServerError().json(ErrorResponse { message: "Database error".to_string() })
+}
+
+/// GET /api/users/me/allergies
+/// The caller's saved allergies, plus every allergen they can choose from
+pub async fn get_allergies(
+    users: web::Data<dyn UserRepo>,
+    http_req: HttpRequest
+) -> impl Responder {
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+
+    match users.find_by_email(&user_email).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(json!({ "allergies": user.allergies, "available": ALLERGENS })),
+        Ok(None) => HttpResponse::NotFound().json(ErrorResponse { message: "User not found".to_string() }),
+        Err(e) => database_error(e),
+    }
+}
+
+/// PUT /api/users/me/allergies
+/// Replaces the allergens checkout warns the caller about
+pub async fn set_allergies(
+    users: web::Data<dyn UserRepo>,
+    req: web::Json<AllergiesRequest>,
+    http_req: HttpRequest
+) -> impl Responder {
+    let user_email = match get_user_email_from_req(&http_req) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::Unauthorized().json(ErrorResponse { message: e }),
+    };
+    let allergies = match normalise_allergens(&req.allergies) {
+        Ok(allergies) => allergies,
+        Err(message) => return HttpResponse::BadRequest().json(ErrorResponse { message }),
+    };
+
+    match users.set_allergies(&user_email, &allergies).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "allergies": allergies })),
+        Err(e) => database_error(e),
+    }
+}
+
+// The signed-in caller's account
+async fn load_caller(users: &dyn UserRepo, http_req: &HttpRequest) -> Result<User, HttpResponse> {
+    let user_email = get_user_email_from_req(http_req)
+        .map_err(|e| HttpResponse::Unauthorized().json(ErrorResponse { message: e }))?;
+    match users.find_by_email(&user_email).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HttpResponse::NotFound().json(ErrorResponse { message: "User not found".to_string() })),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+// Check the caller's password before a sensitive change, limited per account like logins
+async fn check_password(
+    limits: &dyn RateLimitRepo,
+    limit_config: &RateLimitConfig,
+    user: &User,
+    password: &str,
+) -> Result<(), HttpResponse> {
+    let limit_key = format!("password:{}", user.email.to_lowercase());
+    if let Err(retry_after) = rate_limit::check(limits, &limit_key, limit_config.per_account).await {
+        return Err(too_many_requests(retry_after, "Too many attempts. Please try again later."));
+    }
+
+    let Some(hash) = user.password_hash.as_deref() else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { message: "Your account signs in with Google. Use forgot password to set a password first.".to_string() }));
+    };
+    match password::verify_password(hash, password) {
+        Ok(true) => Ok(()),
+        _ => {
+            tracing::warn!(email = %user.email, "Wrong current password on account change");
+            Err(HttpResponse::Unauthorized().json(ErrorResponse { message: "Current password is incorrect".to_string() }))
+        }
+    }
+}
+
+// Check and tidy a default delivery address the way checkout would
+fn validate_address(address: DeliveryAddress, fulfilment_config: &FulfilmentConfig) -> Result<DeliveryAddress, String> {
+    let street = address.address.trim();
+    if street.is_empty() || street.len() > MAX_ADDRESS_LEN {
+        return Err(format!("Delivery address must be 1-{} characters", MAX_ADDRESS_LEN));
+    }
+    if address.location.as_ref().is_some_and(|point| !geo::is_valid(point)) {
+        return Err("Invalid delivery location".to_string());
+    }
+    let notes = address.notes.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if notes.is_some_and(|n| n.len() > MAX_DELIVERY_NOTES_LEN) {
+        return Err(format!("Delivery notes must be at most {} characters", MAX_DELIVERY_NOTES_LEN));
+    }
+    let zone = match address.zone.as_deref().map(str::trim).filter(|z| !z.is_empty()) {
+        Some(zone) => Some(fulfilment_config.find_zone(zone).ok_or_else(|| format!("Unknown delivery zone {}", zone))?.0.to_string()),
+        None => None,
+    };
+
+    Ok(DeliveryAddress {
+        address: street.to_string(),
+        location: address.location,
+        notes: notes.map(str::to_string),
+        zone,
+    })
+}
+
+// Apply the requested changes to the user, or say what's wrong with them
+fn apply_profile_update(user: &mut User, req: UpdateProfileRequest, fulfilment_config: &FulfilmentConfig) -> Result<(), String> {
+    if let Some(name) = req.name {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("Name must be 1-{} characters", MAX_NAME_LEN));
+        }
+        user.name = name.to_string();
+    }
+    if let Some(phone) = req.phone {
+        user.phone = match phone.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+            Some(phone) => Some(normalize_phone(phone).ok_or("Invalid phone number. Use a Kenyan mobile number such as 0712345678")?),
+            None => None,
+        };
+    }
+    if let Some(address) = req.default_address {
+        user.default_address = address.map(|a| validate_address(a, fulfilment_config)).transpose()?;
+    }
+    if let Some(birthday) = req.birthday {
+        let earliest = NaiveDate::from_ymd_opt(1900, 1, 1).unwrap_or_default();
+        if birthday.is_some_and(|b| b > Utc::now().date_naive() || b < earliest) {
+            return Err("Birthday must be a past date".to_string());
+        }
+        user.birthday = birthday;
+    }
+    if let Some(marketing) = req.marketing {
+        user.marketing = marketing;
+    }
+    Ok(())
+}
+
+/// GET /api/users/me
+/// The caller's profile
+pub async fn get_profile(
+    users: web::Data<dyn UserRepo>,
+    http_req: HttpRequest
+) -> impl Responder {
+    match load_caller(users.get_ref(), &http_req).await {
+        Ok(user) => HttpResponse::Ok().json(Profile::from(user)),
+        Err(res) => res,
+    }
+}
+
+/// PATCH /api/users/me
+/// Updates name, phone, default delivery address, birthday and marketing preferences
+pub async fn update_profile(
+    users: web::Data<dyn UserRepo>,
+    fulfilment_config: web::Data<FulfilmentConfig>,
+    req: web::Json<UpdateProfileRequest>,
+    http_req: HttpRequest
+) -> impl Responder {
+    let mut user = match load_caller(users.get_ref(), &http_req).await {
+        Ok(user) => user,
+        Err(res) => return res,
+    };
+    if let Err(message) = apply_profile_update(&mut user, req.into_inner(), &fulfilment_config) {
+        return HttpResponse::BadRequest().json(ErrorResponse { message });
+    }
+
+    match users.update_profile(&user).await {
+        Ok(()) => HttpResponse::Ok().json(Profile::from(user)),
+        Err(e) => database_error(e),
+    }
+}
+
+/// POST /api/users/me/email
+/// Starts an email change. The new address only takes effect once confirmed from its inbox.
+pub async fn request_email_change(
+    users: web::Data<dyn UserRepo>,
+    limits: web::Data<dyn RateLimitRepo>,
+    limit_config: web::Data<RateLimitConfig>,
+    req: web::Json<ChangeEmailRequest>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Load the caller and check the new address
+    let user = match load_caller(users.get_ref(), &http_req).await {
+        Ok(user) => user,
+        Err(res) => return res,
+    };
+    let new_email = normalise_email(&req.new_email);
+    let well_formed = new_email.len() <= MAX_EMAIL_LEN
+        && new_email.split_once('@').is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.'))
+        && !new_email.contains(char::is_whitespace);
+    if !well_formed {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "Enter a valid email address".to_string() });
+    }
+    if new_email.eq_ignore_ascii_case(&user.email) {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "That is already your email".to_string() });
+    }
+
+    // 2. Confirm it's the owner asking: their password, or signing in with Google again just now
+    if user.password_hash.is_some() {
+        let Some(password) = req.password.as_deref() else {
+            return HttpResponse::BadRequest().json(ErrorResponse { message: "Current password required".to_string() });
+        };
+        if let Err(res) = check_password(limits.get_ref(), &limit_config, &user, password).await {
+            return res;
+        }
+    } else {
+        let Some(credential) = req.google_credential.as_deref() else {
+            return HttpResponse::BadRequest().json(ErrorResponse { message: "Sign in with Google again to change your email".to_string() });
+        };
+        let google_info = match google_token_info(credential).await {
+            Ok(info) => info,
+            Err(res) => return res,
+        };
+        if user.google_id.as_deref() != Some(google_info.sub.as_str()) {
+            return HttpResponse::Forbidden().json(ErrorResponse { message: "That Google account isn't linked to this account".to_string() });
+        }
+        if !google_info.issued_within(Duration::minutes(GOOGLE_REAUTH_MINUTES)) {
+            return HttpResponse::Unauthorized().json(ErrorResponse { message: "Sign in with Google again to change your email".to_string() });
+        }
+    }
+
+    // 3. Don't offer an address that belongs to someone else
+    match users.find_by_email(&new_email).await {
+        Ok(Some(_)) => return HttpResponse::Conflict().json(ErrorResponse { message: "Email already exists".to_string() }),
+        Ok(None) => (),
+        Err(e) => return database_error(e),
+    }
+
+    // 4. Save the pending change and "send" the confirmation link to the new address (logged, like password resets)
+    let token = Uuid::new_v4().to_string();
+    let expiry = (Utc::now() + Duration::hours(1)).timestamp_millis();
+    if let Err(e) = users.set_email_change(&user.email, &new_email, &token, expiry).await {
+        return database_error(e);
+    }
+    let confirm_link = format!("{}/confirm-email?token={}", config::frontend_url(), token);
+    tracing::info!(email = %user.email, new_email = %new_email, confirm_link = %confirm_link, "Email change confirmation link generated");
+
+    HttpResponse::Ok().json(json!({
+        "message": format!("We've sent a confirmation link to {}. Your email changes once you open it.", new_email),
+        "pending_email": new_email,
+    }))
+}
+
+/// POST /api/users/confirm-email
+/// Completes an email change using the token from the confirmation email.
+/// Orders, favorites, uploads, loyalty points and promo code use counts move to the new address.
+/// Delivery slot bookings are held on the orders, so they move with them.
+/// Sessions issued for the old address stop working, and the response carries a new token.
+/// If moving the history fails, opening the link again finishes the job.
+#[allow(clippy::too_many_arguments)] // one extractor per dependency
+pub async fn confirm_email_change(
+    users: web::Data<dyn UserRepo>,
+    orders: web::Data<dyn OrderRepo>,
+    favorites: web::Data<dyn FavoriteRepo>,
+    uploads: web::Data<dyn UploadRepo>,
+    loyalty: web::Data<dyn LoyaltyRepo>,
+    promotions: web::Data<dyn PromotionRepo>,
+    req: web::Json<ConfirmEmailRequest>,
+) -> impl Responder {
+    // 1. Find the pending change
+    let user = match users.find_by_email_change_token(&req.token).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid or expired link".to_string() }),
+        Err(e) => return database_error(e),
+    };
+    let (Some(user_id), Some(new_email)) = (user.id, user.pending_email.clone()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid or expired link".to_string() });
+    };
+
+    if user.email_change_expiry.is_none_or(|expiry| Utc::now().timestamp_millis() > expiry) {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "Link has expired. Please request the change again.".to_string() });
+    }
+
+    // 2. Switch the account over; the unique index catches an address taken in the meantime.
+    // A retry after a failed move finds the switch already done and only finishes the move,
+    // within an hour of the switch.
+    let (old_email, token_version) = if user.email == new_email {
+        let Some(previous_email) = user.previous_email.clone() else {
+            return HttpResponse::BadRequest().json(ErrorResponse { message: "Invalid or expired link".to_string() });
+        };
+        (previous_email, user.token_version)
+    } else {
+        let retry_expiry = (Utc::now() + Duration::hours(1)).timestamp_millis();
+        match users.change_email(user_id, &new_email, retry_expiry).await {
+            Ok(()) => (),
+            Err(RepoError::Duplicate) => return HttpResponse::Conflict().json(ErrorResponse { message: "Email already exists".to_string() }),
+            Err(e) => return database_error(e),
+        }
+        tracing::info!(old_email = %user.email, new_email = %new_email, "Email changed");
+        (user.email.clone(), user.token_version + 1)
+    };
+
+    // 3. Bring the customer's history along
+    let moves = [
+        ("orders", orders.reassign_user(&old_email, &new_email).await),
+        ("favorites", favorites.reassign_user(&old_email, &new_email).await),
+        ("uploads", uploads.reassign_user(&old_email, &new_email).await),
+        ("loyalty", loyalty.reassign_user(&old_email, &new_email).await),
+        ("promotion uses", promotions.reassign_user(&old_email, &new_email).await),
+    ];
+    let mut moved = true;
+    for (records, result) in moves {
+        if let Err(e) = result {
+            tracing::error!(records, old_email = %old_email, new_email = %new_email, error = %e, "Failed to move records to new email");
+            moved = false;
+        }
+    }
+    if !moved {
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            message: "Your email has changed, but moving your history failed. Open the link again to retry.".to_string()
+        });
+    }
+    if let Err(e) = users.finish_email_change(user_id).await {
+        return database_error(e);
+    }
+
+    // 4. New session for the new address
+    let token = match jwt::create_token(&new_email, &user.name, token_version) {
+        Ok(token) => token,
+        Err(e) => {
+            tracing::error!(error = %e, "JWT creation error");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to create session".to_string() });
+        }
+    };
+    let user = User { email: new_email, token_version, ..user };
+    HttpResponse::Ok().json(json!({
+        "message": "Your email has been changed.",
+        "token": token,
+        "user": PublicUser::from(user),
+    }))
+}
+
+/// POST /api/users/me/password
+/// Changes the caller's password after checking the current one.
+/// Other sessions are signed out; the response carries a new token for this one.
+pub async fn change_password(
+    users: web::Data<dyn UserRepo>,
+    limits: web::Data<dyn RateLimitRepo>,
+    limit_config: web::Data<RateLimitConfig>,
+    req: web::Json<ChangePasswordRequest>,
+    http_req: HttpRequest
+) -> impl Responder {
+    // 1. Load the caller and check the current password
+    let user = match load_caller(users.get_ref(), &http_req).await {
+        Ok(user) => user,
+        Err(res) => return res,
+    };
+    if let Err(res) = check_password(limits.get_ref(), &limit_config, &user, &req.current_password).await {
+        return res;
+    }
+
+    // 2. Check the new one
+    if req.new_password.chars().count() < MIN_PASSWORD_LEN {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: format!("New password must be at least {} characters", MIN_PASSWORD_LEN) });
+    }
+    if req.new_password == req.current_password {
+        return HttpResponse::BadRequest().json(ErrorResponse { message: "New password must be different from the current one".to_string() });
+    }
+
+    // 3. Save it (this also clears any reset token or lockout)
+    let password_hash = match password::hash_password(&req.new_password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!(error = %e, "Password hashing error");
+            return HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to change password".to_string() });
+        }
+    };
+    let Some(user_id) = user.id else {
+        return HttpResponse::InternalServerError().json(ErrorResponse { message: "Invalid user record".to_string() });
+    };
+    if let Err(e) = users.update_password(user_id, &password_hash).await {
+        return database_error(e);
+    }
+    tracing::info!(email = %user.email, "Password changed");
+
+    // 4. Keep this device signed in
+    match jwt::create_token(&user.email, &user.name, user.token_version + 1) {
+        Ok(token) => HttpResponse::Ok().json(json!({ "message": "Password changed.", "token": token })),
+        Err(e) => {
+            tracing::error!(error = %e, "JWT creation error");
+            HttpResponse::InternalServerError().json(ErrorResponse { message: "Failed to create session".to_string() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        http::StatusCode,
+        middleware::from_fn,
+        test, App,
+    };
+    use actix_http::Request;
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::Value;
+
+    use crate::handlers::auth::signup;
+    use crate::middleware::session::reject_revoked_tokens;
+    use crate::models::favorite::Favorite;
+    use crate::models::loyalty::{LoyaltyEntry, LoyaltyKind};
+    use crate::models::promotion::{Promotion, PromotionKind};
+    use crate::repository::Repositories;
+    use crate::test_util::{bearer, call};
+
+    const PASSWORD: &str = "correct horse";
+
+    async fn app(repos: &Repositories) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+        test::init_service(
+            App::new()
+                .configure(|cfg| repos.configure(cfg))
+                .app_data(web::Data::new(RateLimitConfig::from_env()))
+                .wrap(from_fn(reject_revoked_tokens))
+                .route("/api/auth/signup", web::post().to(signup))
+                .service(
+                    web::scope("/api/users")
+                        .route("/me", web::get().to(get_profile))
+                        .route("/me/email", web::post().to(request_email_change))
+                        .route("/me/password", web::post().to(change_password))
+                        .route("/confirm-email", web::post().to(confirm_email_change))
+                )
+        ).await
+    }
+
+    fn post(uri: &str, token: Option<&str>, body: Value) -> test::TestRequest {
+        let req = test::TestRequest::post().uri(uri).set_json(body);
+        match token {
+            Some(token) => req.insert_header(("Authorization", format!("Bearer {}", token))),
+            None => req,
+        }
+    }
+
+    fn me(token: &str) -> test::TestRequest {
+        test::TestRequest::get().uri("/api/users/me").insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    async fn sign_up(app: &impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>, email: &str) -> String {
+        let (status, body) = call(app, post("/api/auth/signup", None, json!({ "name": "Jane", "email": email, "password": PASSWORD }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    // A favorite, some points and a promo code use for `email`
+    async fn seed_history(repos: &Repositories, email: &str) {
+        let favorite = Favorite {
+            id: None,
+            user_email: email.to_string(),
+            item_id: "party-platter".to_string(),
+            item_title: "Party Platter".to_string(),
+            item_image: String::new(),
+            item_price: 45.0,
+        };
+        repos.favorites.insert(&favorite).await.unwrap();
+        let earned = LoyaltyEntry {
+            id: "earn|seed".to_string(),
+            user_email: email.to_string(),
+            kind: LoyaltyKind::Earn,
+            points: 25,
+            order_id: None,
+            note: None,
+            created_at: Utc::now(),
+            expires_at: None,
+        };
+        repos.loyalty.record(&earned).await.unwrap();
+        let promotion = Promotion {
+            code: "WELCOME".to_string(),
+            description: None,
+            kind: PromotionKind::FreeDelivery,
+            active: true,
+            starts_at: None,
+            ends_at: None,
+            max_uses: None,
+            max_uses_per_user: Some(1),
+            min_order_total: 0.0,
+            categories: Vec::new(),
+            uses: 0,
+        };
+        repos.promotions.insert(&promotion).await.unwrap();
+        assert!(repos.promotions.redeem(&promotion, email).await.unwrap());
+    }
+
+    async fn assert_history_moved(repos: &Repositories, from: &str, to: &str) {
+        assert_eq!(repos.favorites.find_by_user(to).await.unwrap().len(), 1);
+        assert!(repos.favorites.find_by_user(from).await.unwrap().is_empty());
+        assert_eq!(repos.loyalty.balance(to).await.unwrap(), 25);
+        assert_eq!(repos.loyalty.balance(from).await.unwrap(), 0);
+        assert_eq!(repos.promotions.user_uses("WELCOME", to).await.unwrap(), 1);
+        assert_eq!(repos.promotions.user_uses("WELCOME", from).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn changing_password_signs_out_other_sessions() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+        let old_token = sign_up(&app, "jane@example.com").await;
+
+        let (status, body) = call(&app, post("/api/users/me/password", Some(&old_token), json!({
+            "current_password": PASSWORD,
+            "new_password": "battery staple",
+        }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let new_token = body["token"].as_str().unwrap();
+
+        assert_eq!(call(&app, me(&old_token)).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(&app, me(new_token)).await.0, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn confirmed_email_change_moves_history_and_revokes_old_sessions() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+        let old_token = sign_up(&app, "Jane@Example.com").await;
+        seed_history(&repos, "jane@example.com").await;
+
+        let (status, body) = call(&app, post("/api/users/me/email", Some(&old_token), json!({
+            "new_email": " New@Example.com ",
+            "password": PASSWORD,
+        }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["pending_email"], "new@example.com");
+
+        let user = repos.users.find_by_email("jane@example.com").await.unwrap().unwrap();
+        let link_token = user.email_change_token.unwrap();
+        let (status, body) = call(&app, post("/api/users/confirm-email", None, json!({ "token": link_token }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["user"]["email"], "new@example.com");
+        assert_history_moved(&repos, "jane@example.com", "new@example.com").await;
+
+        assert_eq!(call(&app, me(&old_token)).await.0, StatusCode::UNAUTHORIZED);
+        let (status, profile) = call(&app, me(body["token"].as_str().unwrap())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(profile["pending_email"], Value::Null);
+
+        // The link only works once
+        let (status, _) = call(&app, post("/api/users/confirm-email", None, json!({ "token": link_token }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn reopening_the_link_finishes_an_interrupted_move() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+        sign_up(&app, "jane@example.com").await;
+        seed_history(&repos, "jane@example.com").await;
+
+        // The account switched over but the history stayed behind
+        let user = repos.users.find_by_email("jane@example.com").await.unwrap().unwrap();
+        let expiry = (Utc::now() + Duration::hours(1)).timestamp_millis();
+        repos.users.set_email_change(&user.email, "new@example.com", "link-token", expiry).await.unwrap();
+        repos.users.change_email(user.id.unwrap(), "new@example.com", expiry).await.unwrap();
+
+        let (status, body) = call(&app, post("/api/users/confirm-email", None, json!({ "token": "link-token" }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_history_moved(&repos, "jane@example.com", "new@example.com").await;
+        assert_eq!(call(&app, me(body["token"].as_str().unwrap())).await.0, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn the_link_stops_finishing_a_move_once_the_retry_window_ends() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+        sign_up(&app, "jane@example.com").await;
+
+        let user = repos.users.find_by_email("jane@example.com").await.unwrap().unwrap();
+        let link_expiry = (Utc::now() + Duration::hours(1)).timestamp_millis();
+        repos.users.set_email_change(&user.email, "new@example.com", "link-token", link_expiry).await.unwrap();
+        let lapsed = (Utc::now() - Duration::minutes(1)).timestamp_millis();
+        repos.users.change_email(user.id.unwrap(), "new@example.com", lapsed).await.unwrap();
+
+        let (status, body) = call(&app, post("/api/users/confirm-email", None, json!({ "token": "link-token" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn google_accounts_sign_in_again_to_change_email() {
+        let repos = Repositories::in_memory();
+        let app = app(&repos).await;
+        repos.users.insert(&User {
+            id: Some(ObjectId::new()),
+            name: "Jane".to_string(),
+            email: "jane@example.com".to_string(),
+            google_id: Some("google-123".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        let session = bearer("jane@example.com");
+        let session = session.trim_start_matches("Bearer ");
+
+        let (status, body) = call(&app, post("/api/users/me/email", Some(session), json!({ "new_email": "new@example.com" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        let user = repos.users.find_by_email("jane@example.com").await.unwrap().unwrap();
+        assert_eq!(user.pending_email, None);
+    }
+}
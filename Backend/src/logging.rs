@@ -0,0 +1,77 @@
+// src/logging.rs
+use std::env;
+use std::io::{self, Write};
+use tracing_subscriber::{
+    fmt::{writer::BoxMakeWriter, MakeWriter},
+    EnvFilter,
+};
+
+use crate::utils::redact;
+
+/// Install the global tracing subscriber.
+///
+/// Logs are JSON lines by default; set `LOG_FORMAT=text` for human-readable
+/// output in local development. Levels come from `RUST_LOG` (default `info`).
+/// Records from crates using the `log` facade are captured as well.
+///
+/// Emails, phone numbers, account numbers and tokens are masked in every line
+/// unless `LOG_SHOW_PII=true` *and* `APP_ENV=development`.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let show_pii = show_pii();
+    let writer = if show_pii {
+        BoxMakeWriter::new(io::stdout)
+    } else {
+        BoxMakeWriter::new(RedactingStdout)
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+
+    let text = env::var("LOG_FORMAT").map(|f| f.eq_ignore_ascii_case("text")).unwrap_or(false);
+    if text {
+        builder.init();
+    } else {
+        builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init();
+    }
+
+    if show_pii {
+        tracing::warn!("PII redaction is disabled (LOG_SHOW_PII=true in development)");
+    }
+}
+
+fn show_pii() -> bool {
+    let development = env::var("APP_ENV").map(|e| e.eq_ignore_ascii_case("development")).unwrap_or(false);
+    let requested = env::var("LOG_SHOW_PII").map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false);
+    development && requested
+}
+
+// Writes formatted log lines to stdout after masking personal data.
+// The fmt layer hands over each event as a single buffer, so every line is redacted whole.
+struct RedactingStdout;
+
+impl<'a> MakeWriter<'a> for RedactingStdout {
+    type Writer = RedactingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter
+    }
+}
+
+struct RedactingWriter;
+
+impl Write for RedactingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = redact::redact(&String::from_utf8_lossy(buf));
+        io::stdout().lock().write_all(line.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
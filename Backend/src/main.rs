@@ -1,20 +1,40 @@
 // src/main.rs
-use actix_cors::Cors;
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{web, App, HttpServer, middleware::from_fn};
 use std::env;
+use std::sync::Arc;
 use dotenv::dotenv;
 
 // Import route handlers
-use crate::handlers::auth::{login, signup, verify_google_token};
-use crate::handlers::cart::{add_to_cart, process_checkout, get_user_orders};
-use crate::handlers::mpesa::initiate_stk_push;
+use crate::handlers::auth::{login, signup, unlock_account, verify_google_token};
+use crate::handlers::cart::{add_to_cart, apply_promo, process_checkout};
+use crate::handlers::promotions::{list_promotions, create_promotion, update_promotion};
+use crate::handlers::orders::{get_user_orders, get_order, cancel_order, reorder, list_pending_refunds, settle_refund};
+use crate::handlers::mpesa::{initiate_stk_push, mpesa_callback};
 use crate::handlers::favorites::{add_favorite, remove_favorite, get_favorites};
 use crate::handlers::password_reset::{forgot_password, reset_password};
+use crate::handlers::products::{get_product, list_products, search_products, admin_list_products, create_product, update_product, delete_product, get_price_history};
+use crate::handlers::stock::{set_sold_out, set_stock, stop_tracking_stock};
+use crate::handlers::uploads::{upload_reference_image, get_upload};
+use crate::handlers::images::{upload_product_image, get_product_image};
+use crate::handlers::users::{get_allergies, set_allergies, get_profile, update_profile, request_email_change, confirm_email_change, change_password};
+use crate::handlers::loyalty::{get_loyalty, adjust_points};
+use crate::handlers::slots::{list_available_slots, list_slot_rules, create_slot_rule, update_slot_rule, delete_slot_rule};
+use crate::handlers::health::{export_metrics, liveness, readiness, version};
+use crate::storage::{FileStorage, LocalDiskStorage};
 
 // Import modules
+mod config;
 mod db;
 mod handlers;
+mod logging;
+mod metrics;
+mod middleware;
+mod migrations;
 mod models;
+mod repository;
+mod storage;
+#[cfg(test)]
+mod test_util;
 mod utils;
 
 #[actix_web::main]
@@ -22,54 +42,107 @@ async fn main() -> std::io::Result<()> {
     // Load environment variables from .env file
     dotenv().ok();
     
-    // Initialize logger
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    // Initialize structured (JSON) logging
+    logging::init();
 
     // Get server address from .env
     let server_addr = env::var("SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
 
-    // Connect to MongoDB
-    let mongo_client = db::init_db().await.expect("Failed to connect to MongoDB.");
-    log::info!("Successfully connected to MongoDB");
+    // Connect to MongoDB (or in-memory storage) and build the repositories
+    let repositories = db::init_repositories().await.expect("Failed to initialise storage (MongoDB connection or migrations).");
+    middleware::admin::promote_listed_admins(repositories.users.as_ref()).await;
 
-    log::info!("Starting server at http://{}", server_addr);
+    // Register metrics up front so every series is exported from the first scrape
+    metrics::register_all();
+    if config::metrics_token().is_none() {
+        tracing::warn!("METRICS_TOKEN is not set; /metrics is switched off");
+    }
+
+    // CORS origins, methods and headers come from CORS_* variables in .env
+    let cors_config = config::CorsConfig::from_env();
+    tracing::info!(origins = ?cors_config.allowed_origins, "CORS configured");
+
+    // Brute-force protection for /api/auth and STK push abuse limits
+    let rate_limit_config = web::Data::new(config::RateLimitConfig::from_env());
+
+    // Retried checkouts with the same Idempotency-Key replay the first response
+    let idempotency_config = web::Data::new(config::IdempotencyConfig::from_env());
+
+    // Pickup branches and delivery fee rules
+    let fulfilment_config = web::Data::new(config::FulfilmentConfig::from_env());
+
+    // Daily kitchen capacity per category and time window
+    let slot_config = web::Data::new(config::SlotConfig::from_env());
+
+    // Points earned per amount paid, what they're worth and when they expire
+    let loyalty_config = web::Data::new(config::LoyaltyConfig::from_env());
+
+    // Cake reference photos and product images, stored on local disk
+    let upload_config = config::UploadConfig::from_env();
+    let file_storage: Arc<dyn FileStorage> = Arc::new(LocalDiskStorage::new(&upload_config.dir));
+    let file_storage = web::Data::from(file_storage);
+    let upload_limit = upload_config.max_bytes;
+    let image_limit = upload_config.image_max_bytes;
+
+    tracing::info!(addr = %server_addr, "Starting server");
 
     HttpServer::new(move || {
-        // Configure CORS to allow requests from your React app
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:5173") // Your React app's address
-            .allowed_methods(vec!["GET", "POST", "DELETE"]) // Added DELETE
-            .allowed_headers(vec!["Content-Type", "Authorization"])
-            .max_age(3600);
+        let cors = cors_config.to_cors();
 
         App::new()
-            // 1. Pass the MongoDB client to all handlers
-            .app_data(web::Data::new(mongo_client.clone()))
-            // 2. Enable CORS
+            // 1. Pass the repositories to all handlers
+            .configure(|cfg| repositories.configure(cfg))
+            .app_data(rate_limit_config.clone())
+            .app_data(idempotency_config.clone())
+            .app_data(fulfilment_config.clone())
+            .app_data(slot_config.clone())
+            .app_data(loyalty_config.clone())
+            .app_data(file_storage.clone())
+            // 2. Refuse session tokens revoked by a password or email change
+            .wrap(from_fn(middleware::session::reject_revoked_tokens))
+            // 3. Enable CORS
             .wrap(cors)
-            // 3. Enable Logger
-            .wrap(Logger::default())
-            // 4. Define API routes
+            // 4. Request IDs, request-scoped log span and access log
+            .wrap(from_fn(middleware::request_id::request_id))
+            // 5. Request counts and latency per route
+            .wrap(from_fn(middleware::metrics::record_http_metrics))
+            // 6. Health, metrics and build info for the process supervisor
+            .route("/healthz", web::get().to(liveness))
+            .route("/readyz", web::get().to(readiness))
+            .route("/version", web::get().to(version))
+            .route("/metrics", web::get().to(export_metrics))
+            // 7. Define API routes
             .service(
                 web::scope("/api/auth") // Base path for auth
+                    .wrap(from_fn(middleware::rate_limit::limit_by_ip))
                     .route("/signup", web::post().to(signup))
                     .route("/login", web::post().to(login))
                     .route("/google", web::post().to(verify_google_token))
                     .route("/forgot-password", web::post().to(forgot_password))
                     .route("/reset-password", web::post().to(reset_password))
+                    .route("/unlock", web::post().to(unlock_account))
             )
             .service(
                 web::scope("/api/cart")
                     .route("/add", web::post().to(add_to_cart))
-                    .route("/checkout", web::post().to(process_checkout))
+                    .route("/apply-promo", web::post().to(apply_promo))
+                    .service(
+                        web::resource("/checkout")
+                            .wrap(from_fn(middleware::idempotency::idempotent))
+                            .route(web::post().to(process_checkout))
+                    )
             )
             .service(
                 web::scope("/api/orders")
                     .route("", web::get().to(get_user_orders))
+                    .route("/{id}", web::get().to(get_order))
+                    .route("/{id}/cancel", web::post().to(cancel_order))
+                    .route("/{id}/reorder", web::post().to(reorder))
             )
             .service(
                 web::scope("/api/payment")
                     .route("/mpesa/stkpush", web::post().to(initiate_stk_push))
+                    .route("/mpesa/callback", web::post().to(mpesa_callback))
             )
             .service(
                 web::scope("/api/favorites")
@@ -77,6 +150,70 @@ async fn main() -> std::io::Result<()> {
                     .route("/add", web::post().to(add_favorite))
                     .route("/{item_id}", web::delete().to(remove_favorite))
             )
+            .service(
+                web::scope("/api/products")
+                    .route("", web::get().to(list_products))
+                    .route("/search", web::get().to(search_products))
+                    .route("/{item_id}", web::get().to(get_product))
+            )
+            .service(
+                web::scope("/api/uploads")
+                    .service(
+                        web::resource("/reference-image")
+                            .app_data(web::PayloadConfig::new(upload_limit))
+                            .route(web::post().to(upload_reference_image))
+                    )
+                    .route("/{id}", web::get().to(get_upload))
+            )
+            .service(
+                web::scope("/api/users")
+                    .route("/me", web::get().to(get_profile))
+                    .route("/me", web::patch().to(update_profile))
+                    .route("/me/email", web::post().to(request_email_change))
+                    .route("/me/password", web::post().to(change_password))
+                    .route("/confirm-email", web::post().to(confirm_email_change))
+                    .route("/me/allergies", web::get().to(get_allergies))
+                    .route("/me/allergies", web::put().to(set_allergies))
+            )
+            .service(
+                web::scope("/api/loyalty")
+                    .route("", web::get().to(get_loyalty))
+            )
+            .service(
+                web::scope("/api/images")
+                    .route("/{id}/{variant}", web::get().to(get_product_image))
+            )
+            .service(
+                web::scope("/api/slots")
+                    .route("", web::get().to(list_available_slots))
+            )
+            .service(
+                web::scope("/api/admin")
+                    .wrap(from_fn(middleware::admin::require_admin))
+                    .route("/slot-rules", web::get().to(list_slot_rules))
+                    .route("/slot-rules", web::post().to(create_slot_rule))
+                    .route("/slot-rules/{id}", web::put().to(update_slot_rule))
+                    .route("/slot-rules/{id}", web::delete().to(delete_slot_rule))
+                    .route("/products", web::get().to(admin_list_products))
+                    .route("/products", web::post().to(create_product))
+                    .route("/products/{item_id}", web::put().to(update_product))
+                    .route("/products/{item_id}", web::delete().to(delete_product))
+                    .route("/products/{item_id}/price-history", web::get().to(get_price_history))
+                    .route("/products/{item_id}/stock", web::put().to(set_stock))
+                    .route("/products/{item_id}/stock", web::delete().to(stop_tracking_stock))
+                    .route("/products/{item_id}/sold-out", web::put().to(set_sold_out))
+                    .route("/promotions", web::get().to(list_promotions))
+                    .route("/promotions", web::post().to(create_promotion))
+                    .route("/promotions/{code}", web::put().to(update_promotion))
+                    .route("/loyalty/adjust", web::post().to(adjust_points))
+                    .route("/refunds", web::get().to(list_pending_refunds))
+                    .route("/orders/{id}/refund", web::post().to(settle_refund))
+                    .service(
+                        web::resource("/images")
+                            .app_data(web::PayloadConfig::new(image_limit))
+                            .route(web::post().to(upload_product_image))
+                    )
+            )
     })
     .bind(&server_addr)?
     .run()
@@ -0,0 +1,104 @@
+// src/metrics.rs
+//
+// Prometheus metrics, registered in the default registry and exported at GET /metrics.
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec,
+    Histogram, HistogramVec, IntCounterVec,
+};
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests by method, route pattern and status code",
+        &["method", "route", "status"]
+    ).unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency by method and route pattern",
+        &["method", "route"]
+    ).unwrap()
+});
+
+pub static CHECKOUTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "checkouts_total",
+        "Checkout attempts by payment method and outcome",
+        &["payment_method", "outcome"]
+    ).unwrap()
+});
+
+pub static STK_PUSHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mpesa_stk_push_total",
+        "M-Pesa STK push requests by outcome (success or failure)",
+        &["outcome"]
+    ).unwrap()
+});
+
+pub static MPESA_PAYMENT_RESULTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mpesa_payment_results_total",
+        "M-Pesa payment callbacks by outcome (paid or failed)",
+        &["outcome"]
+    ).unwrap()
+});
+
+pub static MPESA_TOKEN_FETCH_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "mpesa_token_fetch_duration_seconds",
+        "Time taken to fetch an M-Pesa OAuth access token"
+    ).unwrap()
+});
+
+pub static MONGODB_OPERATION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "mongodb_operation_duration_seconds",
+        "MongoDB operation latency by collection and operation",
+        &["collection", "operation"],
+        vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]
+    ).unwrap()
+});
+
+pub static LOGIN_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "login_failures_total",
+        "Failed logins by reason",
+        &["reason"]
+    ).unwrap()
+});
+
+/// Force registration of every metric (they are otherwise registered on first use)
+pub fn register_all() {
+    LazyLock::force(&HTTP_REQUESTS);
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&CHECKOUTS);
+    LazyLock::force(&STK_PUSHES);
+    LazyLock::force(&MPESA_PAYMENT_RESULTS);
+    LazyLock::force(&MPESA_TOKEN_FETCH_DURATION);
+    LazyLock::force(&MONGODB_OPERATION_DURATION);
+    LazyLock::force(&LOGIN_FAILURES);
+}
+
+/// Run a MongoDB operation and record how long it took
+pub async fn time_db<T, F: Future<Output = T>>(collection: &str, operation: &str, fut: F) -> T {
+    let start = Instant::now();
+    let result = fut.await;
+    MONGODB_OPERATION_DURATION
+        .with_label_values(&[collection, operation])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+pub fn record_checkout(payment_method: &str, outcome: &str) {
+    CHECKOUTS.with_label_values(&[payment_method, outcome]).inc();
+}
+
+pub fn record_login_failure(reason: &str) {
+    LOGIN_FAILURES.with_label_values(&[reason]).inc();
+}
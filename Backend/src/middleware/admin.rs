@@ -0,0 +1,67 @@
+// src/middleware/admin.rs
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use serde_json::json;
+
+use crate::config;
+use crate::models::user::{normalise_email, User};
+use crate::repository::UserRepo;
+use crate::utils::jwt::get_user_email_from_req;
+
+/// Admins have `is_admin` set on their account
+pub fn is_admin(user: &User) -> bool {
+    user.is_admin
+}
+
+/// Set `is_admin` on the existing accounts listed in `ADMIN_EMAILS`. Run once at startup;
+/// signup doesn't verify addresses, so a listed email nobody has registered yet grants nothing.
+pub async fn promote_listed_admins(users: &dyn UserRepo) {
+    for email in config::admin_emails() {
+        let email = normalise_email(&email);
+        match users.set_admin(&email).await {
+            Ok(true) => tracing::info!(email = %email, "Admin access granted from ADMIN_EMAILS; remove it from the list now"),
+            Ok(false) => tracing::warn!(email = %email, "ADMIN_EMAILS lists an email with no account; it was not promoted"),
+            Err(e) => tracing::error!(email = %email, error = %e, "Failed to grant admin access"),
+        }
+    }
+}
+
+/// Only lets signed-in admins through.
+/// Needs `dyn UserRepo` app data.
+pub async fn require_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let email = match get_user_email_from_req(req.request()) {
+        Ok(email) => email,
+        Err(e) => {
+            let res = HttpResponse::Unauthorized().json(json!({ "message": e }));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    };
+
+    let is_admin = match req.app_data::<web::Data<dyn UserRepo>>() {
+        Some(users) => match users.find_by_email(&email).await {
+            Ok(Some(user)) => is_admin(&user),
+            Ok(None) => false,
+            Err(e) => {
+                tracing::error!(error = %e, "Database error checking admin access");
+                let res = HttpResponse::InternalServerError().json(json!({ "message": "Database error" }));
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+        },
+        None => false,
+    };
+
+    if !is_admin {
+        tracing::warn!(user = %email, path = %req.path(), "Admin access denied");
+        let res = HttpResponse::Forbidden().json(json!({ "message": "Admin access required" }));
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
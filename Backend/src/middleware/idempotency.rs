@@ -0,0 +1,139 @@
+// src/middleware/idempotency.rs
+//
+// Idempotency-Key support for endpoints that must not run twice (checkout).
+// The first request with a key is processed and its response stored; retries
+// with the same key and body get the stored response back instead of creating
+// another order and STK push.
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{header, StatusCode},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::config::IdempotencyConfig;
+use crate::repository::IdempotencyRepo;
+use crate::repository::idempotency::IdempotencyClaim;
+use crate::utils::jwt::get_user_email_from_req;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const MAX_KEY_LEN: usize = 255;
+
+fn reject(req: ServiceRequest, status: StatusCode, message: &str) -> ServiceResponse<BoxBody> {
+    let res = HttpResponse::build(status).json(json!({ "message": message }));
+    req.into_response(res)
+}
+
+// Same key must mean same request: method, path and body
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(req.path().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Deduplicate requests carrying an `Idempotency-Key` header.
+/// Requests without the header, or without a valid token, pass straight through.
+/// Needs `IdempotencyConfig` and `dyn IdempotencyRepo` app data.
+pub async fn idempotent(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    // 1. Read the key
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => match value.to_str().map(str::trim) {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+            _ => {
+                return Ok(reject(req, StatusCode::BAD_REQUEST, "Idempotency-Key must be 1-255 visible characters"));
+            }
+        },
+        None => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+
+    let config = req.app_data::<web::Data<IdempotencyConfig>>().cloned();
+    let store = req.app_data::<web::Data<dyn IdempotencyRepo>>().cloned();
+    let owner = get_user_email_from_req(req.request()).ok();
+    let (Some(config), Some(store), Some(owner)) = (config, store, owner) else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    // 2. Buffer the body so it can be hashed and still reach the handler
+    let bytes = req.extract::<web::Bytes>().await?;
+    req.set_payload(Payload::from(bytes.clone()));
+    let request_hash = fingerprint(&req, &bytes);
+
+    // Keys are scoped per user so two customers can't collide
+    let id = format!("{}:{}", owner, key);
+    let lease_ends = Utc::now() + Duration::seconds(config.lease_secs);
+
+    // 3. Claim the key for a short lease, or answer from the earlier request
+    match store.claim(&id, &request_hash, lease_ends).await {
+        Ok(IdempotencyClaim::Acquired) => (),
+        Ok(IdempotencyClaim::Existing(record)) => {
+            if record.request_hash != request_hash {
+                return Ok(reject(req, StatusCode::UNPROCESSABLE_ENTITY, "Idempotency-Key was already used with a different request"));
+            }
+            return Ok(match (record.response_status, record.response_body) {
+                (Some(status), Some(body)) => {
+                    tracing::info!(path = %req.path(), "Replaying idempotent response");
+                    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+                    let res = HttpResponse::build(status)
+                        .content_type(header::ContentType::json())
+                        .insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"))
+                        .body(body);
+                    req.into_response(res)
+                }
+                _ => reject(req, StatusCode::CONFLICT, "A request with this Idempotency-Key is still being processed"),
+            });
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Idempotency store unavailable");
+            return Ok(reject(req, StatusCode::SERVICE_UNAVAILABLE, "Please try again shortly"));
+        }
+    }
+
+    // 4. Run the handler, releasing the key if it fails so the client can retry
+    let res = match next.call(req).await {
+        Ok(res) => res.map_into_boxed_body(),
+        Err(e) => {
+            if let Err(e) = store.release(&id).await {
+                tracing::error!(error = %e, "Failed to release idempotency key");
+            }
+            return Err(e);
+        }
+    };
+
+    let status = res.status();
+    let (http_req, response) = res.into_parts();
+    let (response, body) = response.into_parts();
+    let bytes = match body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            if let Err(e) = store.release(&id).await {
+                tracing::error!(error = %e, "Failed to release idempotency key");
+            }
+            return Err(actix_web::error::ErrorInternalServerError(e));
+        }
+    };
+
+    // 5. Store the response for the full window; server errors and rate limits are retryable, so forget the key
+    let stored = if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        store.release(&id).await
+    } else {
+        let expires_at = Utc::now() + Duration::seconds(config.window_secs);
+        store.complete(&id, status.as_u16(), &String::from_utf8_lossy(&bytes), expires_at).await
+    };
+    if let Err(e) = stored {
+        tracing::error!(error = %e, "Failed to record idempotent response");
+    }
+
+    Ok(ServiceResponse::new(http_req, response.set_body(BoxBody::new(bytes))))
+}
@@ -0,0 +1,32 @@
+// src/middleware/metrics.rs
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    Error,
+};
+use std::time::Instant;
+
+use crate::metrics::{HTTP_REQUESTS, HTTP_REQUEST_DURATION};
+
+/// Count requests and record latency per route pattern (e.g. `/api/favorites/{item_id}`),
+/// so path parameters don't explode the label cardinality.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+
+    let res = next.call(req).await?;
+
+    let route = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let status = res.status().as_u16().to_string();
+
+    HTTP_REQUESTS.with_label_values(&[&method, &route, &status]).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+
+    Ok(res)
+}
@@ -0,0 +1,7 @@
+// src/middleware/mod.rs
+pub mod metrics;
+pub mod request_id;
+pub mod rate_limit;
+pub mod idempotency;
+pub mod admin;
+pub mod session;
@@ -0,0 +1,65 @@
+// src/middleware/rate_limit.rs
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use chrono::Utc;
+use serde_json::json;
+
+use crate::config::{RateLimitConfig, RateLimitRule};
+use crate::repository::RateLimitRepo;
+
+/// Count a hit against `key`. Returns `Err(retry_after_secs)` once the rule is exceeded.
+/// Store failures are logged and let the request through rather than locking everyone out.
+pub async fn check(store: &dyn RateLimitRepo, key: &str, rule: RateLimitRule) -> Result<(), i64> {
+    match store.hit(key, rule.window_secs).await {
+        Ok(window) if window.count > rule.max => {
+            Err((window.resets_at - Utc::now().timestamp()).max(1))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!(error = %e, "Rate limit store unavailable; allowing request");
+            Ok(())
+        }
+    }
+}
+
+/// 429 response with a Retry-After header
+pub fn too_many_requests(retry_after_secs: i64, message: &str) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after_secs.to_string()))
+        .json(json!({ "message": message, "retry_after": retry_after_secs }))
+}
+
+/// The client IP used for per-IP limits
+pub fn client_ip(req: &actix_web::HttpRequest, config: &RateLimitConfig) -> String {
+    let ip = if config.trust_proxy_headers {
+        req.connection_info().realip_remote_addr().map(str::to_string)
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    };
+    ip.unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Per-IP limit for a scope. Needs `RateLimitConfig` and `dyn RateLimitRepo` app data.
+pub async fn limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let config = req.app_data::<web::Data<RateLimitConfig>>().cloned();
+    let store = req.app_data::<web::Data<dyn RateLimitRepo>>().cloned();
+
+    if let (Some(config), Some(store)) = (config, store) {
+        let key = format!("ip:{}", client_ip(req.request(), &config));
+        if let Err(retry_after) = check(store.get_ref(), &key, config.per_ip).await {
+            tracing::warn!(path = %req.path(), "Per-IP rate limit exceeded");
+            let res = too_many_requests(retry_after, "Too many requests. Please try again later.");
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
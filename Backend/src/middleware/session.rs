@@ -0,0 +1,39 @@
+// src/middleware/session.rs
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use serde_json::json;
+
+use crate::repository::UserRepo;
+use crate::utils::jwt::get_claims_from_req;
+
+/// Refuses session tokens issued before the account's last password or email change.
+/// Requests without a valid token pass through for the handlers to reject.
+/// Needs `dyn UserRepo` app data.
+pub async fn reject_revoked_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let (Ok(claims), Some(users)) = (get_claims_from_req(req.request()), req.app_data::<web::Data<dyn UserRepo>>()) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+
+    let current = match users.find_by_email(&claims.sub).await {
+        Ok(user) => user.is_some_and(|user| user.token_version == claims.ver),
+        Err(e) => {
+            tracing::error!(error = %e, "Database error checking session token");
+            let res = HttpResponse::InternalServerError().json(json!({ "message": "Database error" }));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    };
+    if !current {
+        tracing::info!(user = %claims.sub, path = %req.path(), "Rejected revoked session token");
+        let res = HttpResponse::Unauthorized().json(json!({ "message": "Your session has expired. Please log in again." }));
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,445 @@
+// src/migrations.rs
+//
+// Versioned database migrations, run once at startup. Every applied version is
+// recorded in the `migrations` collection so each migration only runs once.
+use chrono::{DateTime, Utc};
+
+use crate::models::order::fixed_width_time;
+use crate::models::product::{custom_cake, menu_items, menu_specials, Product, CUSTOM_CAKE_ALLERGENS};
+use futures::future::BoxFuture;
+use futures::stream::StreamExt;
+use std::collections::HashSet;
+use std::time::Duration;
+use mongodb::{
+    Collection, Database, IndexModel,
+    bson::{doc, Bson, Document},
+    error::Result,
+    options::{IndexOptions, UpdateOptions},
+};
+
+type MigrationFn = for<'a> fn(&'a Database) -> BoxFuture<'a, Result<()>>;
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: MigrationFn,
+}
+
+// Append new migrations at the end; never reorder or renumber existing ones.
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "Remove users with neither password nor Google ID", up: remove_broken_users },
+    Migration { version: 2, description: "Remove duplicate favorites", up: remove_duplicate_favorites },
+    Migration { version: 3, description: "Create user, order and favorite indexes", up: create_core_indexes },
+    Migration { version: 4, description: "Create rate limit TTL and unlock token indexes", up: create_rate_limit_indexes },
+    Migration { version: 5, description: "Index orders by M-Pesa checkout request", up: create_checkout_request_index },
+    Migration { version: 6, description: "Expire idempotency keys", up: create_idempotency_indexes },
+    Migration { version: 7, description: "Index order history by status", up: create_order_status_index },
+    Migration { version: 8, description: "Expire old slot bookings", up: create_slot_indexes },
+    Migration { version: 9, description: "Add the custom cake builder product", up: seed_custom_cake },
+    Migration { version: 10, description: "Allow custom cake personalisation", up: make_custom_cake_personalisable },
+    Migration { version: 11, description: "Expire old daily stock levels", up: create_stock_indexes },
+    Migration { version: 12, description: "Index products by menu order and price changes by product", up: create_catalog_indexes },
+    Migration { version: 13, description: "Label custom cake allergens", up: label_custom_cake_allergens },
+    Migration { version: 14, description: "Index the loyalty ledger by customer", up: create_loyalty_indexes },
+    Migration { version: 15, description: "Index email change tokens", up: create_email_change_index },
+    Migration { version: 16, description: "Lowercase user emails", up: lowercase_emails },
+    Migration { version: 17, description: "Add the specials to the catalog and index products for search", up: seed_specials_and_search_index },
+    Migration { version: 18, description: "Add the regular menu to the catalog", up: seed_menu },
+    Migration { version: 19, description: "Report accounts that share an email", up: report_shared_emails },
+    Migration { version: 20, description: "Store order times at a fixed width", up: fix_order_time_width },
+];
+
+/// Apply every migration that has not been recorded yet, in version order
+pub async fn run(db: &Database) -> Result<()> {
+    let applied = db.collection::<Document>("migrations");
+
+    for migration in MIGRATIONS {
+        if applied.find_one(doc! { "_id": migration.version }, None).await?.is_some() {
+            continue;
+        }
+
+        tracing::info!(version = migration.version, description = migration.description, "Applying migration");
+        (migration.up)(db).await?;
+
+        applied.insert_one(doc! {
+            "_id": migration.version,
+            "description": migration.description,
+            "applied_at": Utc::now(),
+        }, None).await?;
+    }
+
+    Ok(())
+}
+
+// Documents are removed by MongoDB once their `expires_at` time has passed
+fn ttl_index(field: &str) -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! { field: 1 })
+        .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+        .build()
+}
+
+fn index(keys: Document, unique: bool, sparse: bool) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(unique).sparse(sparse).build())
+        .build()
+}
+
+// Users affected by the old bug where password_hash wasn't saved (was fix_db.js)
+fn remove_broken_users(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let result = db.collection::<Document>("users").delete_many(doc! {
+            "password_hash": Bson::Null,
+            "google_id": Bson::Null,
+        }, None).await?;
+        tracing::info!(count = result.deleted_count, "Removed broken user accounts");
+        Ok(())
+    })
+}
+
+// Keep the first favorite of each (user_email, item_id) pair so the unique index can be built
+fn remove_duplicate_favorites(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let favorites = db.collection::<Document>("favorites");
+        let pipeline = vec![
+            doc! { "$group": {
+                "_id": { "user_email": "$user_email", "item_id": "$item_id" },
+                "ids": { "$push": "$_id" },
+                "count": { "$sum": 1 },
+            }},
+            doc! { "$match": { "count": { "$gt": 1 } } },
+        ];
+
+        let mut cursor = favorites.aggregate(pipeline, None).await?;
+        let mut removed = 0;
+        while let Some(group) = cursor.next().await {
+            let group = group?;
+            let duplicates: Vec<Bson> = group.get_array("ids")
+                .map(|ids| ids.iter().skip(1).cloned().collect())
+                .unwrap_or_default();
+            removed += favorites.delete_many(doc! { "_id": { "$in": duplicates } }, None).await?.deleted_count;
+        }
+        tracing::info!(count = removed, "Removed duplicate favorites");
+        Ok(())
+    })
+}
+
+// `$email` as `normalise_email` stores it: trimmed and lowercase
+fn normalised_email() -> Bson {
+    doc! { "$toLower": { "$trim": { "input": "$email" } } }.into()
+}
+
+// Users grouped by `key`, for the groups holding more than one account
+async fn shared_emails(users: &Collection<Document>, key: Bson) -> Result<Vec<Document>> {
+    let pipeline = vec![
+        doc! { "$group": {
+            "_id": key,
+            "users": { "$push": { "id": "$_id", "email": "$email" } },
+            "count": { "$sum": 1 },
+        }},
+        doc! { "$match": { "count": { "$gt": 1 } } },
+    ];
+    let mut cursor = users.aggregate(pipeline, None).await?;
+    let mut groups = Vec::new();
+    while let Some(group) = cursor.next().await {
+        groups.push(group?);
+    }
+    Ok(groups)
+}
+
+fn create_core_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("users").create_indexes(vec![
+            index(doc! { "email": 1 }, true, false),
+            index(doc! { "google_id": 1 }, true, true),
+            index(doc! { "reset_token": 1 }, false, true),
+        ], None).await?;
+
+        db.collection::<Document>("orders").create_indexes(vec![
+            index(doc! { "user_email": 1, "created_at": -1 }, false, false),
+        ], None).await?;
+
+        db.collection::<Document>("favorites").create_indexes(vec![
+            index(doc! { "user_email": 1, "item_id": 1 }, true, false),
+        ], None).await?;
+
+        Ok(())
+    })
+}
+
+fn create_rate_limit_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("rate_limits").create_indexes(vec![
+            ttl_index("expires_at"),
+        ], None).await?;
+
+        db.collection::<Document>("users").create_indexes(vec![
+            index(doc! { "unlock_token": 1 }, false, true),
+        ], None).await?;
+
+        Ok(())
+    })
+}
+
+fn create_checkout_request_index(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("orders").create_indexes(vec![
+            index(doc! { "checkout_request_id": 1 }, false, true),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+fn create_idempotency_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("idempotency_keys").create_indexes(vec![
+            ttl_index("expires_at"),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+fn create_order_status_index(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("orders").create_indexes(vec![
+            index(doc! { "user_email": 1, "status": 1, "created_at": -1 }, false, false),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+fn create_slot_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("slot_bookings").create_indexes(vec![
+            ttl_index("expires_at"),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+// Options and prices used to live in the React builder (CAKE_SIZES, TOPPINGS, ...)
+fn seed_custom_cake(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let products = db.collection::<Product>("products");
+        let cake = custom_cake();
+        if products.find_one(doc! { "_id": &cake.item_id }, None).await?.is_none() {
+            products.insert_one(&cake, None).await?;
+        }
+        Ok(())
+    })
+}
+
+fn make_custom_cake_personalisable(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("products").update_one(
+            doc! { "_id": custom_cake().item_id },
+            doc! { "$set": { "personalisable": true } },
+            None
+        ).await?;
+        Ok(())
+    })
+}
+
+fn create_stock_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("stock").create_indexes(vec![
+            ttl_index("expires_at"),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+fn create_catalog_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("products").create_indexes(vec![
+            index(doc! { "sort_order": 1, "title": 1 }, false, false),
+        ], None).await?;
+        db.collection::<Document>("price_changes").create_indexes(vec![
+            index(doc! { "item_id": 1, "changed_at": -1 }, false, false),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+// Leaves anything staff have already labelled alone
+fn label_custom_cake_allergens(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let products = db.collection::<Document>("products");
+        let cake = custom_cake();
+        products.update_one(
+            doc! { "_id": &cake.item_id, "allergens": { "$exists": false } },
+            doc! { "$set": { "allergens": &cake.allergens } },
+            None
+        ).await?;
+
+        for (group, option, allergens) in CUSTOM_CAKE_ALLERGENS {
+            let options = UpdateOptions::builder()
+                .array_filters(vec![doc! { "g.id": *group }, doc! { "o.id": *option, "o.allergens": { "$exists": false } }])
+                .build();
+            products.update_one(
+                doc! { "_id": &cake.item_id },
+                doc! { "$set": { "option_groups.$[g].options.$[o].allergens": allergens.to_vec() } },
+                options
+            ).await?;
+        }
+        Ok(())
+    })
+}
+
+fn create_loyalty_indexes(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("loyalty_ledger").create_indexes(vec![
+            index(doc! { "user_email": 1, "created_at": -1 }, false, false),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+fn create_email_change_index(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>("users").create_indexes(vec![
+            index(doc! { "email_change_token": 1 }, false, true),
+        ], None).await?;
+        Ok(())
+    })
+}
+
+// Emails are now stored trimmed and lowercase (see `normalise_email`). Accounts whose email only
+// differs by case or spaces from another account are reported and left alone to be merged by hand;
+// the rest are lowercased along with everything recorded under their email.
+fn lowercase_emails(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let users = db.collection::<Document>("users");
+        let collisions = shared_emails(&users, normalised_email()).await?;
+        let mut skipped = HashSet::new();
+        for group in &collisions {
+            tracing::error!(email = ?group.get("_id"), users = ?group.get("users"), "Accounts differ only by email case or spaces; merge them by hand");
+            for user in group.get_array("users").map(|u| u.iter()).into_iter().flatten() {
+                if let Some(email) = user.as_document().and_then(|u| u.get_str("email").ok()) {
+                    skipped.insert(email.to_string());
+                }
+            }
+        }
+
+        let mut uses: Vec<Document> = Vec::new();
+        let mut cursor = db.collection::<Document>("promotion_uses").find(doc! {}, None).await?;
+        while let Some(found) = cursor.next().await {
+            uses.push(found?);
+        }
+
+        let mut cursor = users.find(doc! {}, None).await?;
+        let mut renamed = 0;
+        while let Some(user) = cursor.next().await {
+            let user = user?;
+            let Ok(email) = user.get_str("email") else { continue };
+            let lowered = email.trim().to_lowercase();
+            if lowered == email || skipped.contains(email) {
+                continue;
+            }
+
+            users.update_one(doc! { "_id": user.get("_id") }, doc! { "$set": { "email": &lowered } }, None).await?;
+            for (collection, field) in [("orders", "user_email"), ("favorites", "user_email"), ("uploads", "owner_email"), ("loyalty_ledger", "user_email")] {
+                db.collection::<Document>(collection).update_many(
+                    doc! { field: email },
+                    doc! { "$set": { field: &lowered } },
+                    None
+                ).await?;
+            }
+            // Per-customer promo use counts are keyed "<code>|<email>"
+            let promotion_uses = db.collection::<Document>("promotion_uses");
+            for used in &uses {
+                let Ok(id) = used.get_str("_id") else { continue };
+                let Some(code) = id.strip_suffix(email).and_then(|rest| rest.strip_suffix('|')) else { continue };
+                let count = used.get_i64("count").unwrap_or(0);
+                promotion_uses.update_one(
+                    doc! { "_id": format!("{}|{}", code, lowered) },
+                    doc! { "$inc": { "count": count } },
+                    UpdateOptions::builder().upsert(true).build()
+                ).await?;
+                promotion_uses.delete_one(doc! { "_id": id }, None).await?;
+            }
+            renamed += 1;
+        }
+        tracing::info!(count = renamed, skipped = skipped.len(), "Lowercased user emails");
+        Ok(())
+    })
+}
+
+// Weights keep title matches ahead of category, then description and tags
+fn seed_specials_and_search_index(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let products = db.collection::<Product>("products");
+        for special in menu_specials() {
+            if products.find_one(doc! { "_id": &special.item_id }, None).await?.is_none() {
+                products.insert_one(&special, None).await?;
+            }
+        }
+
+        let search_index = IndexModel::builder()
+            .keys(doc! { "title": "text", "category": "text", "description": "text", "dietary_tags": "text" })
+            .options(IndexOptions::builder()
+                .name("product_search".to_string())
+                .weights(doc! { "title": 6, "category": 3, "description": 2, "dietary_tags": 2 })
+                .default_language("english".to_string())
+                .build())
+            .build();
+        db.collection::<Document>("products").create_indexes(vec![search_index], None).await?;
+        Ok(())
+    })
+}
+
+// Checkout only sells catalog products, so the rest of the menu has to be there too.
+// Products staff have already added under the same id are left as they are.
+fn seed_menu(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let products = db.collection::<Product>("products");
+        let mut added = 0;
+        for item in menu_items() {
+            if products.find_one(doc! { "_id": &item.item_id }, None).await?.is_none() {
+                products.insert_one(&item, None).await?;
+                added += 1;
+            }
+        }
+        tracing::info!(count = added, "Added menu items to the catalog");
+        Ok(())
+    })
+}
+
+// Accounts can't be merged automatically. Those that would share an email once it is
+// trimmed and lowercased are left as they are and listed here to be merged by hand.
+fn report_shared_emails(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let shared = shared_emails(&db.collection::<Document>("users"), normalised_email()).await?;
+        for group in &shared {
+            tracing::error!(email = ?group.get("_id"), users = ?group.get("users"), "Several accounts share this email; merge them by hand");
+        }
+        tracing::info!(count = shared.len(), "Checked for accounts sharing an email");
+        Ok(())
+    })
+}
+
+// Order times used to be written with as many fractional digits as they had (none up to
+// nine), which breaks string comparison; rewrite them with exactly three.
+fn fix_order_time_width(db: &Database) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        let orders = db.collection::<Document>("orders");
+        let mut cursor = orders.find(doc! { "created_at": { "$type": "string" } }, None).await?;
+        let mut rewritten = 0;
+        while let Some(order) = cursor.next().await {
+            let order = order?;
+            let Ok(created_at) = order.get_str("created_at") else { continue };
+            let Ok(at) = DateTime::parse_from_rfc3339(created_at) else {
+                tracing::warn!(order_id = ?order.get("_id"), created_at, "Order time isn't RFC 3339; left as it is");
+                continue;
+            };
+            let fixed = fixed_width_time::format(&at.with_timezone(&Utc));
+            if fixed != created_at {
+                orders.update_one(doc! { "_id": order.get("_id") }, doc! { "$set": { "created_at": fixed } }, None).await?;
+                rewritten += 1;
+            }
+        }
+        tracing::info!(count = rewritten, "Rewrote order times at a fixed width");
+        Ok(())
+    })
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use mongodb::bson::oid::ObjectId;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Favorite {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
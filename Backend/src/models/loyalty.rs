@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use mongodb::bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+// One movement of a customer's loyalty points. The balance is the sum of their entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoyaltyEntry {
+    /// Entries tied to an order use a fixed id (e.g. "earn|<order id>") so they are never recorded twice
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub user_email: String,
+    pub kind: LoyaltyKind,
+    pub points: i64, // Negative for redeem and expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Earned points lapse after this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoyaltyKind {
+    Earn,
+    Redeem,
+    Expire,
+    /// Manual corrections, and reversals when an order is refunded
+    Adjust,
+}
@@ -1,4 +1,10 @@
 // src/models/mod.rs
 pub mod user; // Assuming you have src/models/user.rs
 pub mod order;
-pub mod favorite;
\ No newline at end of file
+pub mod favorite;
+pub mod loyalty;
+pub mod product;
+pub mod promotion;
+pub mod slot;
+pub mod upload;
+
@@ -2,23 +2,208 @@ use serde::{Deserialize, Serialize};
 use mongodb::bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
 
-#[derive(Debug, Serialize, Deserialize)]
+use super::promotion::Discount;
+use super::slot::SlotReservation;
+
+/// Order times are stored as RFC 3339 strings with exactly three fractional digits, so
+/// MongoDB's string comparisons and sorts on them follow time order
+pub mod fixed_width_time {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn format(at: &DateTime<Utc>) -> String {
+        at.to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+
+    pub fn serialize<S: Serializer>(at: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(at))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        DateTime::deserialize(deserializer)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderItem {
     pub item_id: String,
     pub title: String,
     pub quantity: u32,
     pub price: f64,
     pub image_src: String,
+    // Builder choices for configurable products such as custom cakes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<OrderItemOption>,
+    // Personalisation for the kitchen: text to pipe on the cake and an uploaded reference photo id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inscription: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_image: Option<String>,
+    // Allergens of the item as made, filled in by the server from the catalog
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<String>,
+}
+
+// A chosen product option. The client sends group and option; the server fills in the rest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderItemOption {
+    pub group: String,
+    pub option: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub price_delta: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+// How the customer gets their order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Fulfilment {
+    Pickup {
+        branch: String,
+        pickup_at: DateTime<Utc>,
+    },
+    Delivery {
+        address: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<GeoPoint>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        notes: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        deliver_at: Option<DateTime<Utc>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        zone: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        distance_km: Option<f64>,
+    },
+}
+
+impl Fulfilment {
+    /// When the customer wants the order, if they chose a time
+    pub fn requested_time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Fulfilment::Pickup { pickup_at, .. } => Some(*pickup_at),
+            Fulfilment::Delivery { deliver_at, .. } => *deliver_at,
+        }
+    }
+}
+
+// Order status values
+pub const STATUS_PENDING: &str = "Pending";
+pub const STATUS_PAYMENT_INITIATED: &str = "Payment Initiated";
+pub const STATUS_PAYMENT_FAILED: &str = "Payment Failed";
+pub const STATUS_PAID: &str = "Paid";
+pub const STATUS_CANCELLED: &str = "Cancelled";
+
+pub const REFUND_PENDING: &str = "Pending";
+pub const REFUND_REFUNDED: &str = "Refunded";
+
+// Stock held for an order line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StockReservation {
+    pub stock_id: String, // "<item_id>" or "<item_id>|<date>"
+    pub quantity: u32,
+}
+
+// Money owed back to the customer for a cancelled paid order.
+// Staff settle it (M-Pesa reversal or bank transfer) and mark it done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Refund {
+    pub amount: f64,
+    pub method: String,
+    pub status: String, // "Pending", "Refunded"
+    pub requested_at: DateTime<Utc>,
+    /// M-Pesa reversal or bank transfer reference, once settled
+    #[serde(default)]
+    pub reference: Option<String>,
+    /// The admin who marked it settled
+    #[serde(default)]
+    pub settled_by: Option<String>,
+    #[serde(default)]
+    pub settled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Order {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub user_email: String, // Using email as the link for now since we have it in the token
     pub items: Vec<OrderItem>,
-    pub total: f64,
-    pub status: String, // "Pending", "Paid", "Delivered"
+    pub total: f64, // Items plus delivery fee, less any discount and points
+    pub status: String, // "Pending", "Payment Initiated", "Payment Failed", "Paid", "Preparing", "Delivered", "Cancelled"
     pub payment_method: String,
+    #[serde(with = "fixed_width_time")]
     pub created_at: DateTime<Utc>,
+
+    // Missing on orders placed before fulfilment options existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fulfilment: Option<Fulfilment>,
+    #[serde(default)]
+    pub delivery_fee: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discount: Option<Discount>,
+    // Loyalty points spent on the order and what they took off
+    #[serde(default)]
+    pub points_redeemed: u32,
+    #[serde(default)]
+    pub points_discount: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slot_reservations: Vec<SlotReservation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stock_reservations: Vec<StockReservation>,
+    // Set while the order's stock, slots, promo use and points have been given back
+    // (failed payment or cancellation). Stored under its original name.
+    #[serde(default, rename = "stock_released")]
+    pub holds_released: bool,
+
+    // M-Pesa STK push tracking, filled in by the payment callback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkout_request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mpesa_receipt: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancelled_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund: Option<Refund>,
+}
+
+impl Order {
+    /// True while the customer can still be asked to pay
+    pub fn is_unpaid(&self) -> bool {
+        [STATUS_PENDING, STATUS_PAYMENT_INITIATED, STATUS_PAYMENT_FAILED].contains(&self.status.as_str())
+    }
+
+    /// True until the kitchen starts preparing the order. Orders waiting on an
+    /// STK push can't be cancelled until the payment result arrives.
+    pub fn is_cancellable(&self) -> bool {
+        [STATUS_PENDING, STATUS_PAYMENT_FAILED, STATUS_PAID].contains(&self.status.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn stored_times_sort_as_strings() {
+        let whole_second = Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap();
+        let later = whole_second + chrono::Duration::milliseconds(5);
+        let (a, b) = (fixed_width_time::format(&whole_second), fixed_width_time::format(&later));
+        assert_eq!(a, "2025-01-01T12:00:00.000Z");
+        assert!(a < b);
+
+        let order = Order { created_at: later, ..Default::default() };
+        let stored = mongodb::bson::to_document(&order).unwrap();
+        assert_eq!(stored.get_str("created_at").unwrap(), b);
+        let read: Order = mongodb::bson::from_document(stored).unwrap();
+        assert_eq!(read.created_at, later);
+    }
 }
@@ -0,0 +1,425 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use mongodb::bson::oid::ObjectId;
+
+use super::order::OrderItemOption;
+
+// Category for items the catalog doesn't classify
+pub const DEFAULT_CATEGORY: &str = "other";
+
+/// Allergens we label, in the order they're listed to customers
+pub const ALLERGENS: &[&str] = &["nuts", "peanuts", "gluten", "dairy", "eggs", "soy", "sesame"];
+
+// Dietary tags that stop being true once an item contains one of these allergens
+const TAG_CONFLICTS: &[(&str, &[&str])] = &[
+    ("vegan", &["dairy", "eggs"]),
+    ("dairy-free", &["dairy"]),
+    ("egg-free", &["eggs"]),
+    ("gluten-free", &["gluten"]),
+    ("nut-free", &["nuts", "peanuts"]),
+];
+
+/// Allergens added by custom cake choices (group, option, allergens), on top of the sponge's own
+pub const CUSTOM_CAKE_ALLERGENS: &[(&str, &str, &[&str])] = &[
+    ("base", "chocolate", &["soy"]),
+    ("base", "carrot", &["nuts"]),
+    ("frosting", "chocolate-ganache", &["soy"]),
+    ("filling", "chocolate-mousse", &["soy"]),
+    ("toppings", "macarons", &["nuts"]),
+    ("toppings", "drip", &["soy"]),
+];
+
+// A menu item as sold today. `_id` is the same item_id the React menu and orders use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Product {
+    #[serde(rename = "_id")]
+    pub item_id: String,
+    pub title: String,
+    pub price: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub image_src: String,
+    /// Extra photos shown after `image_src`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    /// Menu position; lower comes first
+    #[serde(default)]
+    pub sort_order: i32,
+    #[serde(default = "default_category")]
+    pub category: String, // "cakes", "pastries", "drinks", ...
+    /// e.g. "vegan", "gluten-free"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dietary_tags: Vec<String>,
+    /// From `ALLERGENS`; options can add more
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<String>,
+    #[serde(default = "default_available")]
+    pub available: bool,
+    /// Seasonal items are only sold between these times
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_from: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_until: Option<DateTime<Utc>>,
+    /// Choices the customer makes, e.g. size and toppings for a custom cake
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub option_groups: Vec<OptionGroup>,
+    /// Accepts an inscription and reference photo
+    #[serde(default)]
+    pub personalisable: bool,
+    /// None means stock isn't tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stock_tracking: Option<StockTracking>,
+    /// Removed from the menu; kept so past orders still resolve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+// A price edit made through the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceChange {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub item_id: String,
+    pub old_price: f64,
+    pub new_price: f64,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+// Running stock (e.g. ice-cream tubs) or a fresh batch per day (e.g. croissants)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StockTracking {
+    Total,
+    Daily,
+}
+
+// One set of choices, e.g. "size" (exactly one) or "toppings" (any number)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptionGroup {
+    pub id: String,
+    pub label: String,
+    pub min_select: u32,
+    /// None means no upper limit
+    #[serde(default)]
+    pub max_select: Option<u32>,
+    pub options: Vec<ProductOption>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductOption {
+    pub id: String,
+    pub label: String,
+    pub price_delta: f64, // Added to the product's base price
+    /// Allergens this choice adds to the product's own
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergens: Vec<String>,
+}
+
+impl Product {
+    /// Whether `now` falls inside the product's availability window
+    pub fn in_season(&self, now: DateTime<Utc>) -> bool {
+        self.available_from.is_none_or(|from| now >= from) && self.available_until.is_none_or(|until| now < until)
+    }
+
+    /// Whether customers can order it at `now`
+    pub fn is_on_sale(&self, now: DateTime<Utc>) -> bool {
+        self.available && self.deleted_at.is_none() && self.in_season(now)
+    }
+
+    /// Allergens of the product with the chosen options, in `ALLERGENS` order
+    pub fn allergens_with(&self, selected: &[OrderItemOption]) -> Vec<String> {
+        let chosen = self.option_groups.iter()
+            .flat_map(|g| g.options.iter().filter(|o| selected.iter().any(|s| s.group == g.id && s.option == o.id)))
+            .flat_map(|o| o.allergens.iter());
+        let present: Vec<&String> = self.allergens.iter().chain(chosen).collect();
+        ALLERGENS.iter().filter(|a| present.iter().any(|p| p == *a)).map(|a| a.to_string()).collect()
+    }
+
+    /// Key of the stock level that covers `date`, if stock is tracked
+    pub fn stock_id(&self, date: NaiveDate) -> Option<String> {
+        match self.stock_tracking? {
+            StockTracking::Total => Some(self.item_id.clone()),
+            StockTracking::Daily => Some(format!("{}|{}", self.item_id, date)),
+        }
+    }
+
+    /// Check the customer's choices against the option groups and price them.
+    /// Returns the unit price and the choices with their labels and prices filled in.
+    pub fn price_with_options(&self, selected: &[OrderItemOption]) -> Result<(f64, Vec<OrderItemOption>), String> {
+        let mut priced = Vec::with_capacity(selected.len());
+        for choice in selected {
+            let group = self.option_groups.iter().find(|g| g.id == choice.group)
+                .ok_or_else(|| format!("{} has no option group '{}'", self.title, choice.group))?;
+            let option = group.options.iter().find(|o| o.id == choice.option)
+                .ok_or_else(|| format!("'{}' is not a {} option", choice.option, group.label))?;
+            if priced.iter().any(|p: &OrderItemOption| p.group == choice.group && p.option == choice.option) {
+                return Err(format!("{} chosen more than once", option.label));
+            }
+            priced.push(OrderItemOption {
+                group: group.id.clone(),
+                option: option.id.clone(),
+                label: option.label.clone(),
+                price_delta: option.price_delta,
+            });
+        }
+
+        for group in &self.option_groups {
+            let count = priced.iter().filter(|p| p.group == group.id).count() as u32;
+            if count < group.min_select {
+                return Err(format!("Choose at least {} {}", group.min_select, group.label));
+            }
+            if group.max_select.is_some_and(|max| count > max) {
+                return Err(format!("Choose at most {} {}", group.max_select.unwrap_or_default(), group.label));
+            }
+        }
+
+        let price = self.price + priced.iter().map(|p| p.price_delta).sum::<f64>();
+        Ok((price, priced))
+    }
+}
+
+fn default_available() -> bool {
+    true
+}
+
+fn default_category() -> String {
+    DEFAULT_CATEGORY.to_string()
+}
+
+fn option_group(id: &str, label: &str, min_select: u32, max_select: Option<u32>, options: &[(&str, &str, f64)]) -> OptionGroup {
+    OptionGroup {
+        id: id.to_string(),
+        label: label.to_string(),
+        min_select,
+        max_select,
+        options: options.iter().map(|(id, label, price_delta)| ProductOption {
+            id: id.to_string(),
+            label: label.to_string(),
+            price_delta: *price_delta,
+            allergens: Vec::new(),
+        }).collect(),
+    }
+}
+
+/// Check allergen names against `ALLERGENS`; returns them deduplicated in `ALLERGENS` order
+pub fn normalise_allergens(allergens: &[String]) -> Result<Vec<String>, String> {
+    let given: Vec<String> = allergens.iter().map(|a| a.trim().to_lowercase()).filter(|a| !a.is_empty()).collect();
+    if let Some(unknown) = given.iter().find(|a| !ALLERGENS.contains(&a.as_str())) {
+        return Err(format!("Unknown allergen '{}' (use {})", unknown, ALLERGENS.join(", ")));
+    }
+    Ok(ALLERGENS.iter().filter(|a| given.iter().any(|g| g == *a)).map(|a| a.to_string()).collect())
+}
+
+/// Dietary tags that `allergens` make untrue, e.g. "vegan" for dairy
+pub fn conflicting_tags<'a>(tags: &'a [String], allergens: &[String]) -> Vec<&'a String> {
+    tags.iter().filter(|tag| {
+        TAG_CONFLICTS.iter().any(|(conflict, ruled_out)| {
+            conflict == tag && ruled_out.iter().any(|a| allergens.iter().any(|x| x == a))
+        })
+    }).collect()
+}
+
+/// The "Build Your Own Cake" special, as previously priced by the React builder
+pub fn custom_cake() -> Product {
+    let mut cake = Product {
+        item_id: "custom-cake".to_string(),
+        title: "Custom Cake Creation".to_string(),
+        price: 0.0, // The size sets the starting price
+        description: None,
+        image_src: "/Frontend/images/custom_cake.jpg".to_string(),
+        images: Vec::new(),
+        sort_order: 0,
+        category: "cakes".to_string(),
+        dietary_tags: Vec::new(),
+        allergens: vec!["gluten".to_string(), "dairy".to_string(), "eggs".to_string()],
+        available: true,
+        available_from: None,
+        available_until: None,
+        personalisable: true,
+        stock_tracking: None,
+        deleted_at: None,
+        option_groups: vec![
+            option_group("size", "size", 1, Some(1), &[
+                ("6inch", "6\" Round (Serves 8-10)", 30.0),
+                ("8inch", "8\" Round (Serves 12-15)", 45.0),
+                ("10inch", "10\" Round (Serves 20-25)", 60.0),
+            ]),
+            option_group("base", "base", 1, Some(1), &[
+                ("vanilla", "Vanilla Bean", 0.0),
+                ("chocolate", "Rich Chocolate", 0.0),
+                ("red-velvet", "Red Velvet", 0.0),
+                ("lemon", "Lemon Zest", 0.0),
+                ("carrot", "Carrot Cake", 5.0),
+            ]),
+            option_group("frosting", "frosting", 1, Some(1), &[
+                ("vanilla-bc", "Vanilla Buttercream", 0.0),
+                ("chocolate-ganache", "Chocolate Ganache", 0.0),
+                ("cream-cheese", "Cream Cheese", 0.0),
+                ("fondant", "Fondant Finish", 15.0),
+            ]),
+            option_group("filling", "filling", 1, Some(1), &[
+                ("none", "None", 0.0),
+                ("strawberry-jam", "Strawberry Jam", 5.0),
+                ("lemon-curd", "Lemon Curd", 5.0),
+                ("chocolate-mousse", "Chocolate Mousse", 8.0),
+            ]),
+            option_group("toppings", "toppings", 0, None, &[
+                ("sprinkles", "Rainbow Sprinkles", 2.0),
+                ("fresh-fruit", "Fresh Fruit", 10.0),
+                ("macarons", "Macarons (3pcs)", 12.0),
+                ("edible-flowers", "Edible Flowers", 8.0),
+                ("drip", "Chocolate Drip", 5.0),
+            ]),
+        ],
+    };
+
+    for (group, option, allergens) in CUSTOM_CAKE_ALLERGENS {
+        let option = cake.option_groups.iter_mut()
+            .filter(|g| g.id == *group)
+            .flat_map(|g| g.options.iter_mut())
+            .find(|o| o.id == *option);
+        if let Some(option) = option {
+            option.allergens = allergens.iter().map(|a| a.to_string()).collect();
+        }
+    }
+    cake
+}
+
+/// The other items on the Specials page, as previously listed only in the React app.
+/// Allergens are the usual ones for each bake; staff can refine them from the admin API.
+pub fn menu_specials() -> Vec<Product> {
+    let special = |item_id: &str, title: &str, description: &str, image: &str, price: f64, allergens: &[&str]| Product {
+        item_id: item_id.to_string(),
+        title: title.to_string(),
+        price,
+        description: Some(description.to_string()),
+        image_src: format!("/Frontend/images/{}", image),
+        images: Vec::new(),
+        sort_order: 0,
+        category: "specials".to_string(),
+        dietary_tags: Vec::new(),
+        allergens: allergens.iter().map(|a| a.to_string()).collect(),
+        available: true,
+        available_from: None,
+        available_until: None,
+        option_groups: Vec::new(),
+        personalisable: false,
+        stock_tracking: None,
+        deleted_at: None,
+    };
+    vec![
+        special("seasonal-pie", "Seasonal Fruit Pie", "Freshly baked pie using the best fruits of the season.", "seasonal_pie.jpg", 25.0, &["gluten", "dairy"]),
+        special("party-platter", "Party Platter", "An assortment of our best cookies, brownies, and mini-cupcakes.", "party_platter.jpg", 45.0, &["gluten", "dairy", "eggs"]),
+    ]
+}
+
+/// The regular menu (cakes, cookies, drinks and ice cream), as listed on the original shop pages.
+/// Cakes and ice cream were priced in store, so they stay off sale until staff set a price.
+pub fn menu_items() -> Vec<Product> {
+    let item = |category: &str, item_id: &str, title: &str, description: &str, image: &str, price: Option<f64>, allergens: &[&str]| Product {
+        item_id: item_id.to_string(),
+        title: title.to_string(),
+        price: price.unwrap_or_default(),
+        description: Some(description.to_string()),
+        image_src: format!("/Frontend/{}", image),
+        images: Vec::new(),
+        sort_order: 0,
+        category: category.to_string(),
+        dietary_tags: Vec::new(),
+        allergens: allergens.iter().map(|a| a.to_string()).collect(),
+        available: price.is_some(),
+        available_from: None,
+        available_until: None,
+        option_groups: Vec::new(),
+        personalisable: category == "cakes",
+        stock_tracking: None,
+        deleted_at: None,
+    };
+    const BAKE: &[&str] = &["gluten", "dairy", "eggs"];
+    const CHOCOLATE_BAKE: &[&str] = &["gluten", "dairy", "eggs", "soy"];
+    const ICE_CREAM: &[&str] = &["dairy", "eggs"];
+    vec![
+        item("cakes", "chocolate-cake", "Chocolate", "Rich, dark, and decadent fudge cake, a timeless indulgence.", "cakes/chocolate_cake.jpg.jpg", None, CHOCOLATE_BAKE),
+        item("cakes", "vanilla-bean-cake", "Vanilla Bean", "Light and fluffy sponge infused with real vanilla bean. Perfect with any filling.", "cakes/vanilla_cake.jpg.jpg", None, BAKE),
+        item("cakes", "red-velvet-cake", "Red Velvet", "Subtle cocoa flavor with a striking red hue, paired with tangy cream cheese frosting.", "cakes/red_velvet_cake.jpg.jpg", None, BAKE),
+        item("cakes", "lemon-zest-cake", "Lemon Zest", "Bright, zesty, and refreshing—a tart and sweet citrus delight.", "cakes/lemon_cake.jpg.jpg", None, BAKE),
+        item("cakes", "coconut-dream-cake", "Coconut Dream", "Moist cake studded with sweet coconut flakes and a creamy filling.", "cakes/coconut_cake.jpg.jpg", None, BAKE),
+        item("cakes", "pina-colada-cake", "Piña Colada", "A tropical blend of sweet pineapple and creamy coconut with a hint of rum flavor.", "cakes/pina_colada_cake.jpg.jpg", None, BAKE),
+        item("cakes", "orange-cake", "Orange", "A juicy splash.", "cakes/Orange cake.jpeg", None, BAKE),
+        item("cakes", "butter-cake", "Butter", "Soft cushy and tasty.", "cakes/Butter Cake.jpeg", None, BAKE),
+        item("cakes", "cinnamon-cake", "Cinnamon", "Cinnamon full of swirl equal double trouble.", "cakes/Cinnamon Swirl Bundt Cake.jpeg", None, BAKE),
+        item("cookies", "brown-butter-chocolate-chip", "Brown Butter Chocolate Chip", "Nutty brown butter base with pools of melted dark chocolate.", "cookies/Choco chips cookie.jpeg", Some(3.5), CHOCOLATE_BAKE),
+        item("cookies", "butter-cookies", "Butter Cookies", "Simple, delicate, and melt-in-your-mouth Danish classic.", "cookies/Butter Cookies.jpeg", Some(2.0), BAKE),
+        item("cookies", "caramel-cookies", "Caramel Cookies", "Soft cookies loaded with chewy caramel chunks.", "cookies/Caramel cookies.jpeg", Some(3.75), BAKE),
+        item("cookies", "gingerbread-men", "Classic Gingerbread Men", "Warm spice and molasses, decorated with royal icing.", "cookies/Classic Gingerbread Men Cookies.jpeg", Some(4.0), BAKE),
+        item("cookies", "double-chocolate-cookies", "Double Chocolate Cookies", "Rich cocoa cookie base with both milk and dark chocolate chips.", "cookies/Double Chocolate Cookies.jpeg", Some(3.25), CHOCOLATE_BAKE),
+        item("cookies", "german-spritz-cookies", "German Spritz Cookies", "Delicate, pressed shortbread, often dipped in chocolate.", "cookies/German Spritz Cookies.jpeg", Some(2.5), CHOCOLATE_BAKE),
+        item("cookies", "ginger-cookies", "Ginger Cookies", "Chewy, soft cookies with a strong, spicy ginger kick.", "cookies/Ginger Cookies.jpeg", Some(3.0), BAKE),
+        item("cookies", "valentine-sugar-cookies", "Valentine Sugar Cookies", "Our classic sugar cookie, cut into hearts and decorated.", "cookies/Valentine Sugar Cookies.jpeg", Some(4.5), BAKE),
+        item("cookies", "chocolate-pinwheel-cookies", "Chocolate Pinwheel Cookies", "With swirls of chocolate and vanilla in every bite.", "cookies/Chocolate and Vanilla Pinwheel Cookies.jpeg", Some(4.5), CHOCOLATE_BAKE),
+        item("drinks", "water", "Water", "Still or sparkling, served chilled.", "drinks/water.jpeg", Some(1.0), &[]),
+        item("drinks", "classic-mojito", "Classic Mojito", "Fresh mint, lime, sugar, and sparkling soda.", "drinks/Classic Mojito Mocktail.jpeg", Some(5.5), &[]),
+        item("drinks", "mango-juice", "Mango Juice", "Thick, sweet, and tropical mango puree.", "drinks/Mango juice.jpeg", Some(4.0), &[]),
+        item("drinks", "passion-fruit-smoothie", "Passion Fruit Smoothie", "Creamy blend of tart passion fruit and yogurt.", "drinks/Passion Fruit Smoothie.jpeg", Some(6.0), &["dairy"]),
+        item("drinks", "pineapple-juice", "Pineapple Juice", "Freshly squeezed, tangy, and sweet.", "drinks/Pineapple juice.jpeg", Some(3.5), &[]),
+        item("drinks", "soft-drinks", "Soft Drinks", "Assorted bottled sodas (Coke, Sprite, Fanta).", "drinks/Soft Drinks.jpeg", Some(2.5), &[]),
+        item("drinks", "tropical-orange", "Tropical Orange", "A blend of orange, mango, and a hint of ginger.", "drinks/Tropical Orange.jpeg", Some(4.5), &[]),
+        item("drinks", "watermelon-juice", "Watermelon Juice", "Cooling, hydrating, and naturally sweet.", "drinks/watermelon juice.jpeg", Some(3.75), &[]),
+        item("ice-cream", "chocolate-ice-cream", "Chocolate Ice Cream", "Rich, dark, and decadent fudge ice cream, a timeless indulgence.", "ice cream/chocolate ice cream.jpeg", None, &["dairy", "eggs", "soy"]),
+        item("ice-cream", "pineapple-ice-cream", "Pineapple Ice Cream", "A Fruity Dessert That Screams Summer.", "ice cream/Pineapple Ice Cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "coconut-ice-cream", "Coconut Ice Cream", "Subtle coconut flavor with a striking creamy hue.", "ice cream/coconut ice cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "lemon-custard-ice-cream", "Lemon Custard Ice Cream", "Bright, zesty, and refreshing—a tart and sweet citrus delight.", "ice cream/Lemon Custard Ice Cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "mango-ice-cream", "Mango Ice Cream", "Moist dessert studded with sweet mango flakes.", "ice cream/mango ice cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "orange-ice-cream", "Orange Ice Cream", "Orange like the color.", "ice cream/orange ice cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "passion-ice-cream", "Passion Ice Cream", "It's the seedy season.", "ice cream/passion ice cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "strawberry-ice-cream", "Strawberry Ice Cream", "Match your tongue's energy.", "ice cream/strawberry ice cream.jpeg", None, ICE_CREAM),
+        item("ice-cream", "vanilla-ice-cream", "Vanilla Ice Cream", "One of a kind flavor.", "ice cream/vanilla (2).jpeg", None, ICE_CREAM),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choice(group: &str, option: &str) -> OrderItemOption {
+        OrderItemOption { group: group.to_string(), option: option.to_string(), label: String::new(), price_delta: 0.0 }
+    }
+
+    fn cake_choices(extra: &[(&str, &str)]) -> Vec<OrderItemOption> {
+        [("size", "8inch"), ("base", "carrot"), ("frosting", "fondant"), ("filling", "none")]
+            .iter()
+            .chain(extra)
+            .map(|(group, option)| choice(group, option))
+            .collect()
+    }
+
+    #[test]
+    fn prices_the_chosen_options() {
+        let (price, priced) = custom_cake().price_with_options(&cake_choices(&[("toppings", "drip"), ("toppings", "macarons")])).unwrap();
+        assert_eq!(price, 45.0 + 5.0 + 15.0 + 5.0 + 12.0);
+        assert_eq!(priced.len(), 6);
+        assert_eq!(priced[0].label, "8\" Round (Serves 12-15)");
+        assert_eq!(priced[0].price_delta, 45.0);
+    }
+
+    #[test]
+    fn ignores_client_labels_and_prices() {
+        let mut choices = cake_choices(&[]);
+        choices[0].price_delta = -45.0;
+        choices[0].label = "Free".to_string();
+        let (price, priced) = custom_cake().price_with_options(&choices).unwrap();
+        assert_eq!(price, 65.0);
+        assert_eq!(priced[0].price_delta, 45.0);
+    }
+
+    #[test]
+    fn rejects_invalid_choices() {
+        let cake = custom_cake();
+        assert!(cake.price_with_options(&cake_choices(&[("icing", "pink")])).is_err());
+        assert!(cake.price_with_options(&cake_choices(&[("toppings", "gold-leaf")])).is_err());
+        assert!(cake.price_with_options(&cake_choices(&[("toppings", "drip"), ("toppings", "drip")])).is_err());
+        assert!(cake.price_with_options(&cake_choices(&[("size", "6inch")])).is_err());
+        assert_eq!(cake.price_with_options(&cake_choices(&[])[1..]).unwrap_err(), "Choose at least 1 size");
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// A promo code. `_id` is the code itself, stored uppercase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Promotion {
+    #[serde(rename = "_id")]
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub kind: PromotionKind,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Across all customers; None means unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses_per_user: Option<u32>,
+    /// Items total (before delivery) needed to use the code
+    #[serde(default)]
+    pub min_order_total: f64,
+    /// Only items in these categories are discounted; empty means everything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// Times the code has been redeemed, kept by the promotion repository
+    #[serde(default)]
+    pub uses: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromotionKind {
+    /// Percent off the qualifying items
+    Percentage { percent: f64 },
+    /// Fixed amount off the qualifying items, never more than they cost
+    FixedAmount { amount: f64 },
+    FreeDelivery,
+}
+
+// What a promo code took off an order, kept on the order so totals can be audited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discount {
+    pub code: String,
+    pub kind: String, // "percentage", "fixed_amount", "free_delivery"
+    /// Items total the code applied to (its categories only)
+    pub eligible_total: f64,
+    pub items_discount: f64,
+    pub delivery_discount: f64,
+    pub amount: f64, // items_discount + delivery_discount
+}
+
+fn default_active() -> bool {
+    true
+}
+
+fn round_money(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+impl PromotionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PromotionKind::Percentage { .. } => "percentage",
+            PromotionKind::FixedAmount { .. } => "fixed_amount",
+            PromotionKind::FreeDelivery => "free_delivery",
+        }
+    }
+}
+
+impl Promotion {
+    /// Work out the discount for a cart. `lines` are (category, line total) for each item;
+    /// `user_uses` is how often this customer has already used the code.
+    pub fn evaluate(&self, lines: &[(String, f64)], delivery_fee: f64, user_uses: u32, now: DateTime<Utc>) -> Result<Discount, String> {
+        if !self.active || self.ends_at.is_some_and(|end| now >= end) {
+            return Err(format!("{} has expired", self.code));
+        }
+        if self.starts_at.is_some_and(|start| now < start) {
+            return Err(format!("{} isn't valid yet", self.code));
+        }
+        if self.max_uses.is_some_and(|max| self.uses >= max) {
+            return Err(format!("{} has been fully redeemed", self.code));
+        }
+        if self.max_uses_per_user.is_some_and(|max| user_uses >= max) {
+            return Err(format!("You've already used {}", self.code));
+        }
+
+        let items_total: f64 = lines.iter().fold(0.0, |sum, (_, total)| sum + total);
+        if items_total < self.min_order_total {
+            return Err(format!("Spend at least {:.2} to use {}", self.min_order_total, self.code));
+        }
+        let eligible_total = lines.iter()
+            .filter(|(category, _)| self.categories.is_empty() || self.categories.contains(category))
+            .fold(0.0, |sum, (_, total)| sum + total);
+
+        if !matches!(self.kind, PromotionKind::FreeDelivery) && eligible_total == 0.0 {
+            return Err(format!("{} doesn't apply to anything in your cart", self.code));
+        }
+
+        let (items_discount, delivery_discount) = match self.kind {
+            PromotionKind::Percentage { percent } => (eligible_total * percent / 100.0, 0.0),
+            PromotionKind::FixedAmount { amount } => (amount.min(eligible_total), 0.0),
+            PromotionKind::FreeDelivery if delivery_fee > 0.0 => (0.0, delivery_fee),
+            PromotionKind::FreeDelivery => return Err(format!("{} only applies to delivery orders", self.code)),
+        };
+
+        let items_discount = round_money(items_discount);
+        let delivery_discount = round_money(delivery_discount);
+        Ok(Discount {
+            code: self.code.clone(),
+            kind: self.kind.name().to_string(),
+            eligible_total: round_money(eligible_total),
+            items_discount,
+            delivery_discount,
+            amount: round_money(items_discount + delivery_discount),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn promotion(kind: PromotionKind) -> Promotion {
+        Promotion {
+            code: "SWEET".to_string(),
+            description: None,
+            kind,
+            active: true,
+            starts_at: None,
+            ends_at: None,
+            max_uses: None,
+            max_uses_per_user: None,
+            min_order_total: 0.0,
+            categories: Vec::new(),
+            uses: 0,
+        }
+    }
+
+    fn lines() -> Vec<(String, f64)> {
+        vec![("cakes".to_string(), 40.0), ("cookies".to_string(), 10.333)]
+    }
+
+    #[test]
+    fn percentage_applies_to_its_categories_only() {
+        let promo = Promotion { categories: vec!["cakes".to_string()], ..promotion(PromotionKind::Percentage { percent: 15.0 }) };
+        let discount = promo.evaluate(&lines(), 5.0, 0, Utc::now()).unwrap();
+        assert_eq!(discount.eligible_total, 40.0);
+        assert_eq!(discount.items_discount, 6.0);
+        assert_eq!(discount.delivery_discount, 0.0);
+        assert_eq!(discount.amount, 6.0);
+        assert_eq!(discount.kind, "percentage");
+    }
+
+    #[test]
+    fn fixed_amount_never_exceeds_the_items() {
+        let promo = Promotion { categories: vec!["cookies".to_string()], ..promotion(PromotionKind::FixedAmount { amount: 25.0 }) };
+        let discount = promo.evaluate(&lines(), 0.0, 0, Utc::now()).unwrap();
+        assert_eq!(discount.amount, 10.33);
+    }
+
+    #[test]
+    fn free_delivery_needs_a_delivery_fee() {
+        let promo = promotion(PromotionKind::FreeDelivery);
+        let discount = promo.evaluate(&lines(), 4.5, 0, Utc::now()).unwrap();
+        assert_eq!((discount.items_discount, discount.delivery_discount), (0.0, 4.5));
+        assert!(promo.evaluate(&lines(), 0.0, 0, Utc::now()).is_err());
+    }
+
+    #[test]
+    fn refuses_codes_outside_their_limits() {
+        let now = Utc::now();
+        let percent = || promotion(PromotionKind::Percentage { percent: 10.0 });
+
+        assert!(Promotion { active: false, ..percent() }.evaluate(&lines(), 0.0, 0, now).is_err());
+        assert!(Promotion { ends_at: Some(now), ..percent() }.evaluate(&lines(), 0.0, 0, now).is_err());
+        assert!(Promotion { starts_at: Some(now + Duration::hours(1)), ..percent() }.evaluate(&lines(), 0.0, 0, now).is_err());
+        assert!(Promotion { max_uses: Some(3), uses: 3, ..percent() }.evaluate(&lines(), 0.0, 0, now).is_err());
+        assert!(Promotion { max_uses_per_user: Some(1), ..percent() }.evaluate(&lines(), 0.0, 1, now).is_err());
+        assert!(Promotion { min_order_total: 60.0, ..percent() }.evaluate(&lines(), 0.0, 0, now).is_err());
+        assert!(Promotion { categories: vec!["pies".to_string()], ..percent() }.evaluate(&lines(), 0.0, 0, now).is_err());
+    }
+}
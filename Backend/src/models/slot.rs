@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use mongodb::bson::oid::ObjectId;
+
+// Daily capacity for one product category in one time window, e.g. 10 cakes between 09:00 and 12:00.
+// Times are bakery local time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotRule {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub category: String,
+    pub start: String, // "HH:MM"
+    pub end: String,   // "HH:MM", exclusive
+    pub capacity: u32,
+}
+
+// Capacity taken by an order, so it can be given back on cancellation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotReservation {
+    pub slot_id: String, // "<date>|<category>|<start>"
+    pub quantity: u32,
+}
+
+/// Identifies one window of one rule on one day
+pub fn slot_id(date: &str, category: &str, start: &str) -> String {
+    format!("{}|{}|{}", date, category, start)
+}
@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// A file uploaded by a customer, e.g. a reference photo for a custom cake
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub owner_email: String,
+    pub key: String, // Location in file storage
+    pub content_type: String,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,7 +1,10 @@
 use serde::{Serialize, Deserialize};
 use mongodb::bson::oid::ObjectId;
+use chrono::NaiveDate;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use super::order::GeoPoint;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>, // MongoDB's unique ID
@@ -18,6 +21,76 @@ pub struct User {
     pub reset_token: Option<String>, // For password reset
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reset_token_expiry: Option<i64>, // Timestamp for token expiry
+
+    // Lockout after repeated failed logins
+    #[serde(default)]
+    pub failed_login_attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<i64>, // Timestamp (ms) until which logins are refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unlock_token: Option<String>, // Emailed so the owner can unlock early
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unlock_token_expiry: Option<i64>, // Timestamp (ms) after which the unlock link stops working
+
+    // Bumped on every password or email change; session tokens carrying an older
+    // version are refused, so changing either signs out other devices
+    #[serde(default)]
+    pub token_version: u32,
+
+    // Staff who can manage the bakery settings under /api/admin
+    #[serde(default)]
+    pub is_admin: bool,
+
+    // Allergens the customer wants to be warned about at checkout
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allergies: Vec<String>,
+
+    // Profile details the customer manages themselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>, // 2547XXXXXXXX, also offered for M-Pesa
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_address: Option<DeliveryAddress>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birthday: Option<NaiveDate>, // For the birthday cake promo
+    #[serde(default)]
+    pub marketing: MarketingPreferences,
+
+    // A new email waiting for the owner to confirm it from that inbox
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_change_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_change_expiry: Option<i64>, // Timestamp (ms)
+    // The address being left, kept until the customer's records have all moved over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_email: Option<String>,
+}
+
+/// Emails are stored and looked up trimmed and lowercased, so one inbox is one account
+pub fn normalise_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// Where the customer usually wants deliveries, used to prefill checkout
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryAddress {
+    pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<GeoPoint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+}
+
+// What the customer has agreed to hear from us about. Everything is opt-in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct MarketingPreferences {
+    #[serde(default)]
+    pub email: bool,
+    #[serde(default)]
+    pub sms: bool,
 }
 
 // Model for sending user data back to the client (without sensitive info)
@@ -38,4 +111,40 @@ impl From<User> for PublicUser {
             email: user.email,
         }
     }
-}
\ No newline at end of file
+}
+
+// The signed-in user's own profile, as shown on the Profile page
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub phone: Option<String>,
+    pub default_address: Option<DeliveryAddress>,
+    pub birthday: Option<NaiveDate>,
+    pub marketing: MarketingPreferences,
+    pub allergies: Vec<String>,
+    /// Waiting to be confirmed from the new inbox
+    pub pending_email: Option<String>,
+    /// False for Google accounts that never set a password
+    pub has_password: bool,
+    pub google_linked: bool,
+}
+
+impl From<User> for Profile {
+    fn from(user: User) -> Self {
+        Profile {
+            id: user.id.map(|id| id.to_hex()).unwrap_or_default(),
+            has_password: user.password_hash.is_some(),
+            google_linked: user.google_id.is_some(),
+            name: user.name,
+            email: user.email,
+            phone: user.phone,
+            default_address: user.default_address,
+            birthday: user.birthday,
+            marketing: user.marketing,
+            allergies: user.allergies,
+            pending_email: user.pending_email,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// src/repository/favorite.rs
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use mongodb::{Collection, Database, bson::{doc, oid::ObjectId}};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::favorite::Favorite;
+use super::{RepoError, RepoResult};
+
+#[async_trait]
+pub trait FavoriteRepo: Send + Sync {
+    async fn find_by_user(&self, user_email: &str) -> RepoResult<Vec<Favorite>>;
+    /// Fails with `RepoError::Duplicate` if the item is already a favorite
+    async fn insert(&self, favorite: &Favorite) -> RepoResult<()>;
+    /// Returns true if a favorite was removed
+    async fn delete(&self, user_email: &str, item_id: &str) -> RepoResult<bool>;
+    /// Move everything recorded under `from` to `to` once the customer confirms a new email
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoFavoriteRepo {
+    collection: Collection<Favorite>,
+}
+
+impl MongoFavoriteRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoFavoriteRepo { collection: db.collection("favorites") }
+    }
+}
+
+#[async_trait]
+impl FavoriteRepo for MongoFavoriteRepo {
+    async fn find_by_user(&self, user_email: &str) -> RepoResult<Vec<Favorite>> {
+        let mut cursor = time_db("favorites", "find", self.collection.find(doc! { "user_email": user_email }, None)).await?;
+
+        let mut favorites = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(fav) => favorites.push(fav),
+                Err(e) => tracing::error!(error = %e, "Error deserializing favorite"),
+            }
+        }
+        Ok(favorites)
+    }
+
+    async fn insert(&self, favorite: &Favorite) -> RepoResult<()> {
+        time_db("favorites", "insert_one", self.collection.insert_one(favorite, None)).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_email: &str, item_id: &str) -> RepoResult<bool> {
+        let result = time_db("favorites", "delete_one", self.collection.delete_one(doc! { "user_email": user_email, "item_id": item_id }, None)).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        time_db("favorites", "update_many", self.collection.update_many(
+            doc! { "user_email": from },
+            doc! { "$set": { "user_email": to } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryFavoriteRepo {
+    favorites: Mutex<Vec<Favorite>>,
+}
+
+#[async_trait]
+impl FavoriteRepo for MemoryFavoriteRepo {
+    async fn find_by_user(&self, user_email: &str) -> RepoResult<Vec<Favorite>> {
+        Ok(self.favorites.lock().unwrap().iter().filter(|f| f.user_email == user_email).cloned().collect())
+    }
+
+    async fn insert(&self, favorite: &Favorite) -> RepoResult<()> {
+        let mut favorites = self.favorites.lock().unwrap();
+        if favorites.iter().any(|f| f.user_email == favorite.user_email && f.item_id == favorite.item_id) {
+            return Err(RepoError::Duplicate);
+        }
+
+        let mut favorite = favorite.clone();
+        favorite.id.get_or_insert_with(ObjectId::new);
+        favorites.push(favorite);
+        Ok(())
+    }
+
+    async fn delete(&self, user_email: &str, item_id: &str) -> RepoResult<bool> {
+        let mut favorites = self.favorites.lock().unwrap();
+        let before = favorites.len();
+        favorites.retain(|f| !(f.user_email == user_email && f.item_id == item_id));
+        Ok(favorites.len() != before)
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        for favorite in self.favorites.lock().unwrap().iter_mut().filter(|f| f.user_email == from) {
+            favorite.user_email = to.to_string();
+        }
+        Ok(())
+    }
+}
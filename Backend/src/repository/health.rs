@@ -0,0 +1,44 @@
+// src/repository/health.rs
+use async_trait::async_trait;
+use mongodb::{Database, bson::doc};
+
+use crate::metrics::time_db;
+use super::RepoResult;
+
+#[async_trait]
+pub trait HealthRepo: Send + Sync {
+    /// Round-trip to the database; fails if it is unreachable
+    async fn ping(&self) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoHealthRepo {
+    db: Database,
+}
+
+impl MongoHealthRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoHealthRepo { db: db.clone() }
+    }
+}
+
+#[async_trait]
+impl HealthRepo for MongoHealthRepo {
+    async fn ping(&self) -> RepoResult<()> {
+        time_db("admin", "ping", self.db.run_command(doc! { "ping": 1 }, None)).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryHealthRepo;
+
+#[async_trait]
+impl HealthRepo for MemoryHealthRepo {
+    async fn ping(&self) -> RepoResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+// src/repository/idempotency.rs
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::{Collection, Database, bson::doc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use super::{RepoError, RepoResult};
+
+/// A stored Idempotency-Key and, once the request finished, its response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    #[serde(rename = "_id")]
+    pub id: String, // "<owner>:<key>"
+    pub request_hash: String,
+    pub response_status: Option<u16>,
+    pub response_body: Option<String>,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Outcome of trying to claim a key
+pub enum IdempotencyClaim {
+    /// First use of the key: the caller must process the request, then complete or release it
+    Acquired,
+    /// The key was already used
+    Existing(IdempotencyRecord),
+}
+
+#[async_trait]
+pub trait IdempotencyRepo: Send + Sync {
+    /// Claim a key until `expires_at`, the lease for a request still in progress
+    async fn claim(&self, id: &str, request_hash: &str, expires_at: DateTime<Utc>) -> RepoResult<IdempotencyClaim>;
+    /// Store the response of a claimed key and keep it until `expires_at`
+    async fn complete(&self, id: &str, status: u16, body: &str, expires_at: DateTime<Utc>) -> RepoResult<()>;
+    /// Forget a claimed key so the request can be retried (used after server errors)
+    async fn release(&self, id: &str) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoIdempotencyRepo {
+    collection: Collection<IdempotencyRecord>,
+}
+
+impl MongoIdempotencyRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoIdempotencyRepo { collection: db.collection("idempotency_keys") }
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepo for MongoIdempotencyRepo {
+    async fn claim(&self, id: &str, request_hash: &str, expires_at: DateTime<Utc>) -> RepoResult<IdempotencyClaim> {
+        let record = IdempotencyRecord {
+            id: id.to_string(),
+            request_hash: request_hash.to_string(),
+            response_status: None,
+            response_body: None,
+            expires_at,
+        };
+
+        for _ in 0..2 {
+            match time_db("idempotency_keys", "insert_one", self.collection.insert_one(&record, None)).await {
+                Ok(_) => return Ok(IdempotencyClaim::Acquired),
+                Err(e) => match RepoError::from(e) {
+                    RepoError::Duplicate => (),
+                    other => return Err(other),
+                },
+            }
+
+            let existing = time_db("idempotency_keys", "find_one", self.collection.find_one(doc! { "_id": id }, None)).await?;
+            match existing {
+                // The TTL monitor only runs every minute, so skip records that have already expired
+                Some(existing) if existing.expires_at <= Utc::now() => {
+                    time_db("idempotency_keys", "delete_one", self.collection.delete_one(doc! { "_id": id }, None)).await?;
+                }
+                Some(existing) => return Ok(IdempotencyClaim::Existing(existing)),
+                None => (),
+            }
+        }
+        Err(RepoError::Database("could not claim idempotency key".to_string()))
+    }
+
+    async fn complete(&self, id: &str, status: u16, body: &str, expires_at: DateTime<Utc>) -> RepoResult<()> {
+        time_db("idempotency_keys", "update_one", self.collection.update_one(
+            doc! { "_id": id },
+            doc! { "$set": { "response_status": status as i32, "response_body": body, "expires_at": mongodb::bson::DateTime::from_chrono(expires_at) } },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn release(&self, id: &str) -> RepoResult<()> {
+        time_db("idempotency_keys", "delete_one", self.collection.delete_one(doc! { "_id": id }, None)).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryIdempotencyRepo {
+    records: Mutex<HashMap<String, IdempotencyRecord>>,
+}
+
+#[async_trait]
+impl IdempotencyRepo for MemoryIdempotencyRepo {
+    async fn claim(&self, id: &str, request_hash: &str, expires_at: DateTime<Utc>) -> RepoResult<IdempotencyClaim> {
+        let mut records = self.records.lock().unwrap();
+        let now = Utc::now();
+        records.retain(|_, r| r.expires_at > now);
+
+        if let Some(existing) = records.get(id) {
+            return Ok(IdempotencyClaim::Existing(existing.clone()));
+        }
+        records.insert(id.to_string(), IdempotencyRecord {
+            id: id.to_string(),
+            request_hash: request_hash.to_string(),
+            response_status: None,
+            response_body: None,
+            expires_at,
+        });
+        Ok(IdempotencyClaim::Acquired)
+    }
+
+    async fn complete(&self, id: &str, status: u16, body: &str, expires_at: DateTime<Utc>) -> RepoResult<()> {
+        if let Some(record) = self.records.lock().unwrap().get_mut(id) {
+            record.response_status = Some(status);
+            record.response_body = Some(body.to_string());
+            record.expires_at = expires_at;
+        }
+        Ok(())
+    }
+
+    async fn release(&self, id: &str) -> RepoResult<()> {
+        self.records.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[actix_web::test]
+    async fn an_unfinished_claim_lapses_after_its_lease() {
+        let store = MemoryIdempotencyRepo::default();
+        let lapsed = Utc::now() - Duration::seconds(1);
+        assert!(matches!(store.claim("jane:k1", "hash", lapsed).await.unwrap(), IdempotencyClaim::Acquired));
+        assert!(matches!(store.claim("jane:k1", "hash", Utc::now() + Duration::seconds(60)).await.unwrap(), IdempotencyClaim::Acquired));
+    }
+
+    #[actix_web::test]
+    async fn completing_keeps_the_response_for_the_window() {
+        let store = MemoryIdempotencyRepo::default();
+        store.claim("jane:k1", "hash", Utc::now() + Duration::seconds(60)).await.unwrap();
+        let window_ends = Utc::now() + Duration::hours(24);
+        store.complete("jane:k1", 200, "{}", window_ends).await.unwrap();
+
+        match store.claim("jane:k1", "hash", Utc::now() + Duration::seconds(60)).await.unwrap() {
+            IdempotencyClaim::Existing(record) => {
+                assert_eq!(record.response_status, Some(200));
+                assert_eq!(record.expires_at, window_ends);
+            }
+            IdempotencyClaim::Acquired => panic!("completed key was claimed again"),
+        }
+    }
+}
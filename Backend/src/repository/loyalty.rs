@@ -0,0 +1,164 @@
+// src/repository/loyalty.rs
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use mongodb::{
+    Collection, Database,
+    bson::{doc, Bson, Document},
+    options::FindOptions,
+};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::loyalty::LoyaltyEntry;
+use super::{RepoError, RepoResult};
+
+// The ledger is the only record of points: a balance is always the sum of its entries,
+// so every change is a single insert and nothing can drift out of step.
+#[async_trait]
+pub trait LoyaltyRepo: Send + Sync {
+    async fn balance(&self, user_email: &str) -> RepoResult<i64>;
+    /// The customer's ledger, newest first
+    async fn entries(&self, user_email: &str, limit: Option<i64>) -> RepoResult<Vec<LoyaltyEntry>>;
+    async fn find(&self, id: &str) -> RepoResult<Option<LoyaltyEntry>>;
+    /// Add an entry. Returns false if an entry with this id exists.
+    async fn record(&self, entry: &LoyaltyEntry) -> RepoResult<bool>;
+    /// Like `record` for a negative entry, but returns false instead of taking the balance below zero
+    async fn spend(&self, entry: &LoyaltyEntry) -> RepoResult<bool>;
+    /// Move everything recorded under `from` to `to` once the customer confirms a new email
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoLoyaltyRepo {
+    ledger: Collection<LoyaltyEntry>,
+}
+
+impl MongoLoyaltyRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoLoyaltyRepo { ledger: db.collection("loyalty_ledger") }
+    }
+}
+
+#[async_trait]
+impl LoyaltyRepo for MongoLoyaltyRepo {
+    async fn balance(&self, user_email: &str) -> RepoResult<i64> {
+        let pipeline = vec![
+            doc! { "$match": { "user_email": user_email } },
+            doc! { "$group": { "_id": Bson::Null, "balance": { "$sum": "$points" } } },
+        ];
+        let mut cursor = time_db("loyalty_ledger", "aggregate", self.ledger.aggregate(pipeline, None)).await?;
+        let total: Option<Document> = match cursor.next().await {
+            Some(result) => Some(result?),
+            None => None,
+        };
+        Ok(total.and_then(|d| d.get_i64("balance").ok()).unwrap_or(0))
+    }
+
+    async fn entries(&self, user_email: &str, limit: Option<i64>) -> RepoResult<Vec<LoyaltyEntry>> {
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(limit).build();
+        let mut cursor = time_db("loyalty_ledger", "find", self.ledger.find(doc! { "user_email": user_email }, options)).await?;
+
+        let mut entries = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::error!(error = %e, "Error deserializing loyalty entry"),
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn find(&self, id: &str) -> RepoResult<Option<LoyaltyEntry>> {
+        Ok(time_db("loyalty_ledger", "find_one", self.ledger.find_one(doc! { "_id": id }, None)).await?)
+    }
+
+    async fn record(&self, entry: &LoyaltyEntry) -> RepoResult<bool> {
+        match time_db("loyalty_ledger", "insert_one", self.ledger.insert_one(entry, None)).await {
+            Ok(_) => Ok(true),
+            Err(e) => match RepoError::from(e) {
+                RepoError::Duplicate => Ok(false),
+                e => Err(e),
+            },
+        }
+    }
+
+    async fn spend(&self, entry: &LoyaltyEntry) -> RepoResult<bool> {
+        // Write the spend, then take it back out if it overdrew the balance. Two spends racing
+        // for the same points both see the overdraft and both back out; neither goes through.
+        if !self.record(entry).await? {
+            return Ok(false);
+        }
+        if self.balance(&entry.user_email).await? >= 0 {
+            return Ok(true);
+        }
+        time_db("loyalty_ledger", "delete_one", self.ledger.delete_one(doc! { "_id": &entry.id }, None)).await?;
+        Ok(false)
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        time_db("loyalty_ledger", "update_many", self.ledger.update_many(
+            doc! { "user_email": from },
+            doc! { "$set": { "user_email": to } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryLoyaltyRepo {
+    ledger: Mutex<Vec<LoyaltyEntry>>,
+}
+
+impl MemoryLoyaltyRepo {
+    fn apply(&self, entry: &LoyaltyEntry, guard_balance: bool) -> bool {
+        let mut ledger = self.ledger.lock().unwrap();
+        let balance: i64 = ledger.iter().filter(|e| e.user_email == entry.user_email).map(|e| e.points).sum();
+        if ledger.iter().any(|e| e.id == entry.id) || (guard_balance && balance + entry.points < 0) {
+            return false;
+        }
+        ledger.push(entry.clone());
+        true
+    }
+}
+
+#[async_trait]
+impl LoyaltyRepo for MemoryLoyaltyRepo {
+    async fn balance(&self, user_email: &str) -> RepoResult<i64> {
+        Ok(self.ledger.lock().unwrap().iter().filter(|e| e.user_email == user_email).map(|e| e.points).sum())
+    }
+
+    async fn entries(&self, user_email: &str, limit: Option<i64>) -> RepoResult<Vec<LoyaltyEntry>> {
+        let mut entries: Vec<LoyaltyEntry> = self.ledger.lock().unwrap().iter()
+            .filter(|e| e.user_email == user_email)
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+        if let Some(limit) = limit {
+            entries.truncate(limit.max(0) as usize);
+        }
+        Ok(entries)
+    }
+
+    async fn find(&self, id: &str) -> RepoResult<Option<LoyaltyEntry>> {
+        Ok(self.ledger.lock().unwrap().iter().find(|e| e.id == id).cloned())
+    }
+
+    async fn record(&self, entry: &LoyaltyEntry) -> RepoResult<bool> {
+        Ok(self.apply(entry, false))
+    }
+
+    async fn spend(&self, entry: &LoyaltyEntry) -> RepoResult<bool> {
+        Ok(self.apply(entry, true))
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        for entry in self.ledger.lock().unwrap().iter_mut().filter(|e| e.user_email == from) {
+            entry.user_email = to.to_string();
+        }
+        Ok(())
+    }
+}
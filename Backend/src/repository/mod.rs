@@ -0,0 +1,154 @@
+// src/repository/mod.rs
+use actix_web::web;
+use mongodb::{Client, error::{ErrorKind, WriteFailure}};
+use std::fmt;
+use std::sync::Arc;
+
+pub mod favorite;
+pub mod health;
+pub mod idempotency;
+pub mod loyalty;
+pub mod order;
+pub mod price_change;
+pub mod product;
+pub mod promotion;
+pub mod rate_limit;
+pub mod slot;
+pub mod stock;
+pub mod upload;
+pub mod user;
+
+pub use favorite::FavoriteRepo;
+pub use health::HealthRepo;
+pub use idempotency::IdempotencyRepo;
+pub use loyalty::LoyaltyRepo;
+pub use order::OrderRepo;
+pub use price_change::PriceChangeRepo;
+pub use product::ProductRepo;
+pub use promotion::PromotionRepo;
+pub use rate_limit::RateLimitRepo;
+pub use slot::SlotRepo;
+pub use stock::StockRepo;
+pub use upload::UploadRepo;
+pub use user::UserRepo;
+
+/// Errors returned by every repository implementation
+#[derive(Debug)]
+pub enum RepoError {
+    /// A unique index rejected the write (MongoDB E11000)
+    Duplicate,
+    Database(String),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::Duplicate => write!(f, "duplicate key"),
+            RepoError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+impl From<mongodb::error::Error> for RepoError {
+    fn from(e: mongodb::error::Error) -> Self {
+        let duplicate = match e.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(we)) => we.code == DUPLICATE_KEY_CODE,
+            ErrorKind::Command(ce) => ce.code == DUPLICATE_KEY_CODE,
+            _ => false,
+        };
+
+        if duplicate {
+            RepoError::Duplicate
+        } else {
+            RepoError::Database(e.to_string())
+        }
+    }
+}
+
+pub type RepoResult<T> = Result<T, RepoError>;
+
+/// All repositories used by the handlers.
+///
+/// Each one is registered as `web::Data<dyn XRepo>`, so handlers depend on the
+/// trait and never on MongoDB directly.
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepo>,
+    pub orders: Arc<dyn OrderRepo>,
+    pub products: Arc<dyn ProductRepo>,
+    pub favorites: Arc<dyn FavoriteRepo>,
+    pub health: Arc<dyn HealthRepo>,
+    pub rate_limits: Arc<dyn RateLimitRepo>,
+    pub idempotency: Arc<dyn IdempotencyRepo>,
+    pub slots: Arc<dyn SlotRepo>,
+    pub uploads: Arc<dyn UploadRepo>,
+    pub stock: Arc<dyn StockRepo>,
+    pub price_changes: Arc<dyn PriceChangeRepo>,
+    pub promotions: Arc<dyn PromotionRepo>,
+    pub loyalty: Arc<dyn LoyaltyRepo>,
+}
+
+impl Repositories {
+    /// Repositories backed by the given MongoDB database
+    pub fn mongo(client: &Client, db_name: &str) -> Self {
+        let db = client.database(db_name);
+        Repositories {
+            users: Arc::new(user::MongoUserRepo::new(&db)),
+            orders: Arc::new(order::MongoOrderRepo::new(&db)),
+            products: Arc::new(product::MongoProductRepo::new(&db)),
+            favorites: Arc::new(favorite::MongoFavoriteRepo::new(&db)),
+            health: Arc::new(health::MongoHealthRepo::new(&db)),
+            rate_limits: Arc::new(rate_limit::MongoRateLimitRepo::new(&db)),
+            idempotency: Arc::new(idempotency::MongoIdempotencyRepo::new(&db)),
+            slots: Arc::new(slot::MongoSlotRepo::new(&db)),
+            uploads: Arc::new(upload::MongoUploadRepo::new(&db)),
+            stock: Arc::new(stock::MongoStockRepo::new(&db)),
+            price_changes: Arc::new(price_change::MongoPriceChangeRepo::new(&db)),
+            promotions: Arc::new(promotion::MongoPromotionRepo::new(&db)),
+            loyalty: Arc::new(loyalty::MongoLoyaltyRepo::new(&db)),
+        }
+    }
+
+    /// Repositories that keep everything in process memory (local dev and tests)
+    pub fn in_memory() -> Self {
+        Repositories {
+            users: Arc::new(user::MemoryUserRepo::default()),
+            orders: Arc::new(order::MemoryOrderRepo::default()),
+            products: Arc::new(product::MemoryProductRepo::with_products(
+                std::iter::once(crate::models::product::custom_cake())
+                    .chain(crate::models::product::menu_specials())
+                    .chain(crate::models::product::menu_items())
+                    .collect()
+            )),
+            favorites: Arc::new(favorite::MemoryFavoriteRepo::default()),
+            health: Arc::new(health::MemoryHealthRepo),
+            rate_limits: Arc::new(rate_limit::MemoryRateLimitRepo::default()),
+            idempotency: Arc::new(idempotency::MemoryIdempotencyRepo::default()),
+            slots: Arc::new(slot::MemorySlotRepo::default()),
+            uploads: Arc::new(upload::MemoryUploadRepo::default()),
+            stock: Arc::new(stock::MemoryStockRepo::default()),
+            price_changes: Arc::new(price_change::MemoryPriceChangeRepo::default()),
+            promotions: Arc::new(promotion::MemoryPromotionRepo::default()),
+            loyalty: Arc::new(loyalty::MemoryLoyaltyRepo::default()),
+        }
+    }
+
+    /// Register every repository as app data
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(web::Data::from(self.users.clone()))
+            .app_data(web::Data::from(self.orders.clone()))
+            .app_data(web::Data::from(self.products.clone()))
+            .app_data(web::Data::from(self.favorites.clone()))
+            .app_data(web::Data::from(self.health.clone()))
+            .app_data(web::Data::from(self.rate_limits.clone()))
+            .app_data(web::Data::from(self.idempotency.clone()))
+            .app_data(web::Data::from(self.slots.clone()))
+            .app_data(web::Data::from(self.uploads.clone()))
+            .app_data(web::Data::from(self.stock.clone()))
+            .app_data(web::Data::from(self.price_changes.clone()))
+            .app_data(web::Data::from(self.promotions.clone()))
+            .app_data(web::Data::from(self.loyalty.clone()));
+    }
+}
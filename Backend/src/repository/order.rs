@@ -0,0 +1,344 @@
+// src/repository/order.rs
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::StreamExt;
+use mongodb::{
+    Collection, Database,
+    bson::{self, doc, oid::ObjectId, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::order::{fixed_width_time, Order, Refund, REFUND_PENDING, REFUND_REFUNDED, STATUS_CANCELLED, STATUS_PAYMENT_FAILED, STATUS_PAYMENT_INITIATED, STATUS_PENDING};
+use super::{RepoError, RepoResult};
+
+/// Which of a user's orders to list
+#[derive(Debug, Clone, Default)]
+pub struct OrderFilter {
+    pub user_email: String,
+    /// Empty means any status
+    pub statuses: Vec<String>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+}
+
+impl OrderFilter {
+    fn matches(&self, order: &Order) -> bool {
+        order.user_email == self.user_email
+            && (self.statuses.is_empty() || self.statuses.contains(&order.status))
+            && self.created_from.is_none_or(|from| order.created_at >= from)
+            && self.created_to.is_none_or(|to| order.created_at < to)
+    }
+}
+
+/// One page of orders, newest first
+#[derive(Debug, Default)]
+pub struct OrderPage {
+    pub orders: Vec<Order>,
+    /// Orders matching the filter across all pages
+    pub total: u64,
+    /// Documents on this page that could not be read
+    pub unreadable: u64,
+}
+
+#[async_trait]
+pub trait OrderRepo: Send + Sync {
+    async fn insert(&self, order: &Order) -> RepoResult<()>;
+    async fn find_by_id(&self, id: ObjectId) -> RepoResult<Option<Order>>;
+    async fn find_by_checkout_request_id(&self, checkout_request_id: &str) -> RepoResult<Option<Order>>;
+    /// A page of the orders matching `filter`, newest first
+    async fn find_page(&self, filter: &OrderFilter, skip: u64, limit: u64) -> RepoResult<OrderPage>;
+    /// Record a new STK push for an unpaid order; returns false if the order is paid
+    /// or already waiting on another push
+    async fn set_payment_request(&self, id: ObjectId, checkout_request_id: &str) -> RepoResult<bool>;
+    /// Apply an M-Pesa callback to the order awaiting it. Returns the updated order,
+    /// or None if no order is waiting on this request (unknown or already processed).
+    async fn record_payment_result(&self, checkout_request_id: &str, status: &str, receipt: Option<&str>) -> RepoResult<Option<Order>>;
+    /// Cancel an order that is still in `expected_status`, recording a refund if one is owed.
+    /// Returns the cancelled order, or None if the status changed in the meantime.
+    async fn cancel(&self, id: ObjectId, expected_status: &str, refund: Option<Refund>) -> RepoResult<Option<Order>>;
+    /// Flip the order's `holds_released` flag. Returns false if it already had that value,
+    /// so stock, slots, promo uses and points are never released or re-taken twice.
+    async fn set_holds_released(&self, id: ObjectId, released: bool) -> RepoResult<bool>;
+    /// Cancelled orders whose refund hasn't been paid out yet, oldest first
+    async fn find_pending_refunds(&self) -> RepoResult<Vec<Order>>;
+    /// Mark a pending refund as paid out. Returns the updated order, or None if there
+    /// is no pending refund on it (never owed, or already settled).
+    async fn settle_refund(&self, id: ObjectId, reference: &str, settled_by: &str) -> RepoResult<Option<Order>>;
+    /// Move everything recorded under `from` to `to` once the customer confirms a new email
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoOrderRepo {
+    collection: Collection<Order>,
+}
+
+impl MongoOrderRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoOrderRepo { collection: db.collection("orders") }
+    }
+}
+
+#[async_trait]
+impl OrderRepo for MongoOrderRepo {
+    async fn insert(&self, order: &Order) -> RepoResult<()> {
+        time_db("orders", "insert_one", self.collection.insert_one(order, None)).await?;
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> RepoResult<Option<Order>> {
+        Ok(time_db("orders", "find_one", self.collection.find_one(doc! { "_id": id }, None)).await?)
+    }
+
+    async fn find_by_checkout_request_id(&self, checkout_request_id: &str) -> RepoResult<Option<Order>> {
+        Ok(time_db("orders", "find_one", self.collection.find_one(doc! { "checkout_request_id": checkout_request_id }, None)).await?)
+    }
+
+    async fn find_page(&self, filter: &OrderFilter, skip: u64, limit: u64) -> RepoResult<OrderPage> {
+        // created_at is stored as a fixed-width RFC 3339 string, so ranges compare in the same format
+        let mut query = doc! { "user_email": &filter.user_email };
+        if !filter.statuses.is_empty() {
+            query.insert("status", doc! { "$in": &filter.statuses });
+        }
+        let mut created_at = Document::new();
+        if let Some(from) = filter.created_from {
+            created_at.insert("$gte", fixed_width_time::format(&from));
+        }
+        if let Some(to) = filter.created_to {
+            created_at.insert("$lt", fixed_width_time::format(&to));
+        }
+        if !created_at.is_empty() {
+            query.insert("created_at", created_at);
+        }
+
+        let raw = self.collection.clone_with_type::<Document>();
+        let total = time_db("orders", "count_documents", raw.count_documents(query.clone(), None)).await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": -1, "_id": -1 })
+            .skip(skip)
+            .limit(limit as i64)
+            .build();
+        // Read raw documents so a bad order is reported rather than silently dropped
+        let mut cursor = time_db("orders", "find", raw.find(query, options)).await?;
+
+        let mut page = OrderPage { total, ..Default::default() };
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let id = document.get_object_id("_id").ok();
+            match bson::from_document::<Order>(document) {
+                Ok(order) => page.orders.push(order),
+                Err(e) => {
+                    tracing::error!(order_id = ?id, error = %e, "Error deserializing order");
+                    page.unreadable += 1;
+                }
+            }
+        }
+        Ok(page)
+    }
+
+    async fn set_payment_request(&self, id: ObjectId, checkout_request_id: &str) -> RepoResult<bool> {
+        let result = time_db("orders", "update_one", self.collection.update_one(
+            doc! {
+                "_id": id,
+                "status": { "$in": [STATUS_PENDING, STATUS_PAYMENT_FAILED] }
+            },
+            doc! { "$set": { "status": STATUS_PAYMENT_INITIATED, "checkout_request_id": checkout_request_id } },
+            None
+        )).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn record_payment_result(&self, checkout_request_id: &str, status: &str, receipt: Option<&str>) -> RepoResult<Option<Order>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let mut set = doc! { "status": status };
+        if let Some(receipt) = receipt {
+            set.insert("mpesa_receipt", receipt);
+        }
+
+        Ok(time_db("orders", "find_one_and_update", self.collection.find_one_and_update(
+            doc! { "checkout_request_id": checkout_request_id, "status": STATUS_PAYMENT_INITIATED },
+            doc! { "$set": set },
+            options
+        )).await?)
+    }
+
+    async fn cancel(&self, id: ObjectId, expected_status: &str, refund: Option<Refund>) -> RepoResult<Option<Order>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let mut set = doc! { "status": STATUS_CANCELLED, "cancelled_at": bson::to_bson(&Utc::now()).map_err(|e| RepoError::Database(e.to_string()))? };
+        if let Some(refund) = &refund {
+            set.insert("refund", bson::to_bson(refund).map_err(|e| RepoError::Database(e.to_string()))?);
+        }
+
+        Ok(time_db("orders", "find_one_and_update", self.collection.find_one_and_update(
+            doc! { "_id": id, "status": expected_status },
+            doc! { "$set": set },
+            options
+        )).await?)
+    }
+
+    async fn set_holds_released(&self, id: ObjectId, released: bool) -> RepoResult<bool> {
+        let result = time_db("orders", "update_one", self.collection.update_one(
+            doc! { "_id": id, "stock_released": { "$ne": released } },
+            doc! { "$set": { "stock_released": released } },
+            None
+        )).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn find_pending_refunds(&self) -> RepoResult<Vec<Order>> {
+        let options = FindOptions::builder().sort(doc! { "cancelled_at": 1, "_id": 1 }).build();
+        let cursor = time_db("orders", "find", self.collection.find(doc! { "refund.status": REFUND_PENDING }, options)).await?;
+        Ok(cursor.collect::<Vec<_>>().await.into_iter().collect::<Result<_, _>>()?)
+    }
+
+    async fn settle_refund(&self, id: ObjectId, reference: &str, settled_by: &str) -> RepoResult<Option<Order>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        Ok(time_db("orders", "find_one_and_update", self.collection.find_one_and_update(
+            doc! { "_id": id, "refund.status": REFUND_PENDING },
+            doc! { "$set": {
+                "refund.status": REFUND_REFUNDED,
+                "refund.reference": reference,
+                "refund.settled_by": settled_by,
+                "refund.settled_at": bson::to_bson(&Utc::now()).map_err(|e| RepoError::Database(e.to_string()))?,
+            } },
+            options
+        )).await?)
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        time_db("orders", "update_many", self.collection.update_many(
+            doc! { "user_email": from },
+            doc! { "$set": { "user_email": to } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryOrderRepo {
+    orders: Mutex<Vec<Order>>,
+}
+
+#[async_trait]
+impl OrderRepo for MemoryOrderRepo {
+    async fn insert(&self, order: &Order) -> RepoResult<()> {
+        let mut order = order.clone();
+        order.id.get_or_insert_with(ObjectId::new);
+        self.orders.lock().unwrap().push(order);
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> RepoResult<Option<Order>> {
+        Ok(self.orders.lock().unwrap().iter().find(|o| o.id == Some(id)).cloned())
+    }
+
+    async fn find_by_checkout_request_id(&self, checkout_request_id: &str) -> RepoResult<Option<Order>> {
+        Ok(self.orders.lock().unwrap().iter().find(|o| o.checkout_request_id.as_deref() == Some(checkout_request_id)).cloned())
+    }
+
+    async fn find_page(&self, filter: &OrderFilter, skip: u64, limit: u64) -> RepoResult<OrderPage> {
+        let mut matching: Vec<Order> = self.orders.lock().unwrap().iter().filter(|o| filter.matches(o)).cloned().collect();
+        matching.sort_by_key(|o| std::cmp::Reverse(o.created_at));
+
+        Ok(OrderPage {
+            total: matching.len() as u64,
+            orders: matching.into_iter().skip(skip as usize).take(limit as usize).collect(),
+            unreadable: 0,
+        })
+    }
+
+    async fn set_payment_request(&self, id: ObjectId, checkout_request_id: &str) -> RepoResult<bool> {
+        let mut orders = self.orders.lock().unwrap();
+        match orders.iter_mut().find(|o| o.id == Some(id) && [STATUS_PENDING, STATUS_PAYMENT_FAILED].contains(&o.status.as_str())) {
+            Some(order) => {
+                order.status = STATUS_PAYMENT_INITIATED.to_string();
+                order.checkout_request_id = Some(checkout_request_id.to_string());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn record_payment_result(&self, checkout_request_id: &str, status: &str, receipt: Option<&str>) -> RepoResult<Option<Order>> {
+        let mut orders = self.orders.lock().unwrap();
+        let order = orders.iter_mut().find(|o| {
+            o.checkout_request_id.as_deref() == Some(checkout_request_id) && o.status == STATUS_PAYMENT_INITIATED
+        });
+
+        Ok(order.map(|order| {
+            order.status = status.to_string();
+            if let Some(receipt) = receipt {
+                order.mpesa_receipt = Some(receipt.to_string());
+            }
+            order.clone()
+        }))
+    }
+
+    async fn cancel(&self, id: ObjectId, expected_status: &str, refund: Option<Refund>) -> RepoResult<Option<Order>> {
+        let mut orders = self.orders.lock().unwrap();
+        let order = orders.iter_mut().find(|o| o.id == Some(id) && o.status == expected_status);
+
+        Ok(order.map(|order| {
+            order.status = STATUS_CANCELLED.to_string();
+            order.cancelled_at = Some(Utc::now());
+            order.refund = refund;
+            order.clone()
+        }))
+    }
+
+    async fn set_holds_released(&self, id: ObjectId, released: bool) -> RepoResult<bool> {
+        let mut orders = self.orders.lock().unwrap();
+        match orders.iter_mut().find(|o| o.id == Some(id) && o.holds_released != released) {
+            Some(order) => {
+                order.holds_released = released;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn find_pending_refunds(&self) -> RepoResult<Vec<Order>> {
+        let mut pending: Vec<Order> = self.orders.lock().unwrap().iter()
+            .filter(|o| o.refund.as_ref().is_some_and(|r| r.status == REFUND_PENDING))
+            .cloned()
+            .collect();
+        pending.sort_by_key(|o| o.cancelled_at);
+        Ok(pending)
+    }
+
+    async fn settle_refund(&self, id: ObjectId, reference: &str, settled_by: &str) -> RepoResult<Option<Order>> {
+        let mut orders = self.orders.lock().unwrap();
+        let order = orders.iter_mut()
+            .find(|o| o.id == Some(id) && o.refund.as_ref().is_some_and(|r| r.status == REFUND_PENDING));
+
+        Ok(order.map(|order| {
+            if let Some(refund) = order.refund.as_mut() {
+                refund.status = REFUND_REFUNDED.to_string();
+                refund.reference = Some(reference.to_string());
+                refund.settled_by = Some(settled_by.to_string());
+                refund.settled_at = Some(Utc::now());
+            }
+            order.clone()
+        }))
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        for order in self.orders.lock().unwrap().iter_mut().filter(|o| o.user_email == from) {
+            order.user_email = to.to_string();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+// src/repository/price_change.rs
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use mongodb::{Collection, Database, bson::doc, options::FindOptions};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::product::PriceChange;
+use super::RepoResult;
+
+#[async_trait]
+pub trait PriceChangeRepo: Send + Sync {
+    async fn insert(&self, change: &PriceChange) -> RepoResult<()>;
+    /// Price changes for one product, newest first
+    async fn list_for_item(&self, item_id: &str) -> RepoResult<Vec<PriceChange>>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoPriceChangeRepo {
+    collection: Collection<PriceChange>,
+}
+
+impl MongoPriceChangeRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoPriceChangeRepo { collection: db.collection("price_changes") }
+    }
+}
+
+#[async_trait]
+impl PriceChangeRepo for MongoPriceChangeRepo {
+    async fn insert(&self, change: &PriceChange) -> RepoResult<()> {
+        time_db("price_changes", "insert_one", self.collection.insert_one(change, None)).await?;
+        Ok(())
+    }
+
+    async fn list_for_item(&self, item_id: &str) -> RepoResult<Vec<PriceChange>> {
+        let options = FindOptions::builder().sort(doc! { "changed_at": -1 }).build();
+        let mut cursor = time_db("price_changes", "find", self.collection.find(doc! { "item_id": item_id }, options)).await?;
+
+        let mut changes = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(change) => changes.push(change),
+                Err(e) => tracing::error!(error = %e, "Error deserializing price change"),
+            }
+        }
+        Ok(changes)
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryPriceChangeRepo {
+    changes: Mutex<Vec<PriceChange>>,
+}
+
+#[async_trait]
+impl PriceChangeRepo for MemoryPriceChangeRepo {
+    async fn insert(&self, change: &PriceChange) -> RepoResult<()> {
+        self.changes.lock().unwrap().push(change.clone());
+        Ok(())
+    }
+
+    async fn list_for_item(&self, item_id: &str) -> RepoResult<Vec<PriceChange>> {
+        let mut changes: Vec<PriceChange> = self.changes.lock().unwrap().iter()
+            .filter(|c| c.item_id == item_id)
+            .cloned()
+            .collect();
+        changes.sort_by_key(|c| std::cmp::Reverse(c.changed_at));
+        Ok(changes)
+    }
+}
@@ -0,0 +1,209 @@
+// src/repository/product.rs
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use chrono::{DateTime, Utc};
+use mongodb::{Collection, Database, bson::{self, doc, Document}, options::FindOptions};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::product::{Product, StockTracking};
+use crate::utils::search;
+use super::{RepoError, RepoResult};
+
+#[async_trait]
+pub trait ProductRepo: Send + Sync {
+    /// Catalog entries for the given item ids; unknown ids are left out
+    async fn find_by_ids(&self, item_ids: &[String]) -> RepoResult<Vec<Product>>;
+    /// The whole catalog in menu order; deleted products only if asked for
+    async fn list(&self, include_deleted: bool) -> RepoResult<Vec<Product>>;
+    /// Products (not deleted) matching a search phrase with their relevance, best first
+    async fn search(&self, phrase: &str) -> RepoResult<Vec<(f64, Product)>>;
+    /// Fails with `RepoError::Duplicate` if the item id is taken, even by a deleted product
+    async fn insert(&self, product: &Product) -> RepoResult<()>;
+    /// Replace a product that hasn't been deleted; returns the previous version, or None if not found
+    async fn update(&self, product: &Product) -> RepoResult<Option<Product>>;
+    /// Returns false if the product doesn't exist or is already deleted
+    async fn soft_delete(&self, item_id: &str, at: DateTime<Utc>) -> RepoResult<bool>;
+    /// Returns false if the product doesn't exist
+    async fn set_stock_tracking(&self, item_id: &str, tracking: Option<StockTracking>) -> RepoResult<bool>;
+    /// Returns false if the product doesn't exist
+    async fn set_available(&self, item_id: &str, available: bool) -> RepoResult<bool>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoProductRepo {
+    collection: Collection<Product>,
+}
+
+impl MongoProductRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoProductRepo { collection: db.collection("products") }
+    }
+}
+
+#[async_trait]
+impl ProductRepo for MongoProductRepo {
+    async fn find_by_ids(&self, item_ids: &[String]) -> RepoResult<Vec<Product>> {
+        let mut cursor = time_db("products", "find", self.collection.find(doc! { "_id": { "$in": item_ids } }, None)).await?;
+
+        let mut products = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(product) => products.push(product),
+                Err(e) => tracing::error!(error = %e, "Error deserializing product"),
+            }
+        }
+        Ok(products)
+    }
+
+    async fn list(&self, include_deleted: bool) -> RepoResult<Vec<Product>> {
+        let filter = if include_deleted { doc! {} } else { doc! { "deleted_at": null } };
+        let options = FindOptions::builder().sort(doc! { "sort_order": 1, "title": 1 }).build();
+        let mut cursor = time_db("products", "find", self.collection.find(filter, options)).await?;
+
+        let mut products = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(product) => products.push(product),
+                Err(e) => tracing::error!(error = %e, "Error deserializing product"),
+            }
+        }
+        Ok(products)
+    }
+
+    async fn search(&self, phrase: &str) -> RepoResult<Vec<(f64, Product)>> {
+        // The text index (title, category, description, dietary tags) stems words but
+        // doesn't forgive typos, so fall back to scoring the menu when it finds nothing
+        let options = FindOptions::builder()
+            .projection(doc! { "score": { "$meta": "textScore" } })
+            .sort(doc! { "score": { "$meta": "textScore" } })
+            .build();
+        let filter = doc! { "$text": { "$search": phrase }, "deleted_at": null };
+        let documents = self.collection.clone_with_type::<Document>();
+        let mut cursor = time_db("products", "find", documents.find(filter, options)).await?;
+
+        let mut matches = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let score = document.get_f64("score").unwrap_or(0.0);
+            match bson::from_document::<Product>(document) {
+                Ok(product) => matches.push((score, product)),
+                Err(e) => tracing::error!(error = %e, "Error deserializing product"),
+            }
+        }
+        if matches.is_empty() {
+            return Ok(search::rank(self.list(false).await?, phrase));
+        }
+        Ok(matches)
+    }
+
+    async fn insert(&self, product: &Product) -> RepoResult<()> {
+        time_db("products", "insert_one", self.collection.insert_one(product, None)).await?;
+        Ok(())
+    }
+
+    async fn update(&self, product: &Product) -> RepoResult<Option<Product>> {
+        Ok(time_db("products", "find_one_and_replace", self.collection.find_one_and_replace(
+            doc! { "_id": &product.item_id, "deleted_at": null },
+            product,
+            None
+        )).await?)
+    }
+
+    async fn soft_delete(&self, item_id: &str, at: DateTime<Utc>) -> RepoResult<bool> {
+        let at = mongodb::bson::to_bson(&at).map_err(|e| RepoError::Database(e.to_string()))?;
+        let result = time_db("products", "update_one", self.collection.update_one(
+            doc! { "_id": item_id, "deleted_at": null },
+            doc! { "$set": { "deleted_at": at } },
+            None
+        )).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn set_stock_tracking(&self, item_id: &str, tracking: Option<StockTracking>) -> RepoResult<bool> {
+        let update = match tracking {
+            Some(tracking) => doc! { "$set": { "stock_tracking": mongodb::bson::to_bson(&tracking).map_err(|e| RepoError::Database(e.to_string()))? } },
+            None => doc! { "$unset": { "stock_tracking": "" } },
+        };
+        let result = time_db("products", "update_one", self.collection.update_one(doc! { "_id": item_id }, update, None)).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn set_available(&self, item_id: &str, available: bool) -> RepoResult<bool> {
+        let result = time_db("products", "update_one", self.collection.update_one(
+            doc! { "_id": item_id },
+            doc! { "$set": { "available": available } },
+            None
+        )).await?;
+        Ok(result.matched_count == 1)
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryProductRepo {
+    products: Mutex<Vec<Product>>,
+}
+
+impl MemoryProductRepo {
+    pub fn with_products(products: Vec<Product>) -> Self {
+        MemoryProductRepo { products: Mutex::new(products) }
+    }
+}
+
+#[async_trait]
+impl ProductRepo for MemoryProductRepo {
+    async fn find_by_ids(&self, item_ids: &[String]) -> RepoResult<Vec<Product>> {
+        Ok(self.products.lock().unwrap().iter().filter(|p| item_ids.contains(&p.item_id)).cloned().collect())
+    }
+
+    async fn list(&self, include_deleted: bool) -> RepoResult<Vec<Product>> {
+        let mut products: Vec<Product> = self.products.lock().unwrap().iter()
+            .filter(|p| include_deleted || p.deleted_at.is_none())
+            .cloned()
+            .collect();
+        products.sort_by(|a, b| a.sort_order.cmp(&b.sort_order).then_with(|| a.title.cmp(&b.title)));
+        Ok(products)
+    }
+
+    async fn search(&self, phrase: &str) -> RepoResult<Vec<(f64, Product)>> {
+        Ok(search::rank(self.list(false).await?, phrase))
+    }
+
+    async fn insert(&self, product: &Product) -> RepoResult<()> {
+        let mut products = self.products.lock().unwrap();
+        if products.iter().any(|p| p.item_id == product.item_id) {
+            return Err(RepoError::Duplicate);
+        }
+        products.push(product.clone());
+        Ok(())
+    }
+
+    async fn update(&self, product: &Product) -> RepoResult<Option<Product>> {
+        let mut products = self.products.lock().unwrap();
+        match products.iter_mut().find(|p| p.item_id == product.item_id && p.deleted_at.is_none()) {
+            Some(existing) => Ok(Some(std::mem::replace(existing, product.clone()))),
+            None => Ok(None),
+        }
+    }
+
+    async fn soft_delete(&self, item_id: &str, at: DateTime<Utc>) -> RepoResult<bool> {
+        let mut products = self.products.lock().unwrap();
+        Ok(products.iter_mut()
+            .find(|p| p.item_id == item_id && p.deleted_at.is_none())
+            .map(|p| p.deleted_at = Some(at))
+            .is_some())
+    }
+
+    async fn set_stock_tracking(&self, item_id: &str, tracking: Option<StockTracking>) -> RepoResult<bool> {
+        let mut products = self.products.lock().unwrap();
+        Ok(products.iter_mut().find(|p| p.item_id == item_id).map(|p| p.stock_tracking = tracking).is_some())
+    }
+
+    async fn set_available(&self, item_id: &str, available: bool) -> RepoResult<bool> {
+        let mut products = self.products.lock().unwrap();
+        Ok(products.iter_mut().find(|p| p.item_id == item_id).map(|p| p.available = available).is_some())
+    }
+}
@@ -0,0 +1,252 @@
+// src/repository/promotion.rs
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use mongodb::{
+    Collection, Database,
+    bson::{doc, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, UpdateOptions},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::promotion::Promotion;
+use super::{RepoError, RepoResult};
+
+// Optional fields cleared when an update leaves them out
+const OPTIONAL_FIELDS: &[&str] = &["description", "starts_at", "ends_at", "max_uses", "max_uses_per_user", "categories"];
+
+#[async_trait]
+pub trait PromotionRepo: Send + Sync {
+    async fn find(&self, code: &str) -> RepoResult<Option<Promotion>>;
+    async fn list(&self) -> RepoResult<Vec<Promotion>>;
+    /// Fails with `RepoError::Duplicate` if the code is taken
+    async fn insert(&self, promotion: &Promotion) -> RepoResult<()>;
+    /// Replace everything but the use count; returns false if the code doesn't exist
+    async fn update(&self, promotion: &Promotion) -> RepoResult<bool>;
+    /// How many times `user_email` has redeemed the code
+    async fn user_uses(&self, code: &str, user_email: &str) -> RepoResult<u32>;
+    /// Atomically count one use against the global and per-user limits; false if either is used up
+    async fn redeem(&self, promotion: &Promotion, user_email: &str) -> RepoResult<bool>;
+    /// Give back a use (failed checkout or cancelled order)
+    async fn release(&self, code: &str, user_email: &str) -> RepoResult<()>;
+    /// Move a customer's per-code use counts to their new email, merging any already there
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()>;
+}
+
+fn use_id(code: &str, user_email: &str) -> String {
+    format!("{}|{}", code, user_email)
+}
+
+// --- MongoDB ---
+
+pub struct MongoPromotionRepo {
+    collection: Collection<Promotion>,
+    uses: Collection<Document>,
+}
+
+impl MongoPromotionRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoPromotionRepo { collection: db.collection("promotions"), uses: db.collection("promotion_uses") }
+    }
+}
+
+#[async_trait]
+impl PromotionRepo for MongoPromotionRepo {
+    async fn find(&self, code: &str) -> RepoResult<Option<Promotion>> {
+        Ok(time_db("promotions", "find_one", self.collection.find_one(doc! { "_id": code }, None)).await?)
+    }
+
+    async fn list(&self) -> RepoResult<Vec<Promotion>> {
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        let mut cursor = time_db("promotions", "find", self.collection.find(doc! {}, options)).await?;
+
+        let mut promotions = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(promotion) => promotions.push(promotion),
+                Err(e) => tracing::error!(error = %e, "Error deserializing promotion"),
+            }
+        }
+        Ok(promotions)
+    }
+
+    async fn insert(&self, promotion: &Promotion) -> RepoResult<()> {
+        time_db("promotions", "insert_one", self.collection.insert_one(promotion, None)).await?;
+        Ok(())
+    }
+
+    async fn update(&self, promotion: &Promotion) -> RepoResult<bool> {
+        let mut set = mongodb::bson::to_document(promotion).map_err(|e| RepoError::Database(e.to_string()))?;
+        set.remove("_id");
+        set.remove("uses");
+        let mut unset = Document::new();
+        for field in OPTIONAL_FIELDS.iter().filter(|f| !set.contains_key(**f)) {
+            unset.insert(*field, "");
+        }
+
+        let mut update = doc! { "$set": set };
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+        let result = time_db("promotions", "update_one", self.collection.update_one(doc! { "_id": &promotion.code }, update, None)).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn user_uses(&self, code: &str, user_email: &str) -> RepoResult<u32> {
+        let found = time_db("promotion_uses", "find_one", self.uses.find_one(doc! { "_id": use_id(code, user_email) }, None)).await?;
+        Ok(found.and_then(|d| d.get_i64("count").ok()).unwrap_or(0).max(0) as u32)
+    }
+
+    async fn redeem(&self, promotion: &Promotion, user_email: &str) -> RepoResult<bool> {
+        // 1. Global limit: only increment while under max_uses
+        let mut filter = doc! { "_id": &promotion.code };
+        if let Some(max) = promotion.max_uses {
+            filter.insert("uses", doc! { "$lt": max as i64 });
+        }
+        let result = time_db("promotions", "update_one", self.collection.update_one(filter, doc! { "$inc": { "uses": 1_i64 } }, None)).await?;
+        if result.modified_count == 0 {
+            return Ok(false);
+        }
+
+        // 2. Per-user limit: at the limit the filter misses and the upsert collides with the existing document
+        let mut filter = doc! { "_id": use_id(&promotion.code, user_email) };
+        if let Some(max) = promotion.max_uses_per_user {
+            filter.insert("count", doc! { "$lt": max as i64 });
+        }
+        let options = FindOneAndUpdateOptions::builder().upsert(true).build();
+        match time_db("promotion_uses", "find_one_and_update", self.uses.find_one_and_update(filter, doc! { "$inc": { "count": 1_i64 } }, options)).await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                time_db("promotions", "update_one", self.collection.update_one(
+                    doc! { "_id": &promotion.code },
+                    doc! { "$inc": { "uses": -1_i64 } },
+                    None
+                )).await?;
+                match RepoError::from(e) {
+                    RepoError::Duplicate => Ok(false),
+                    e => Err(e),
+                }
+            }
+        }
+    }
+
+    async fn release(&self, code: &str, user_email: &str) -> RepoResult<()> {
+        time_db("promotions", "update_one", self.collection.update_one(
+            doc! { "_id": code, "uses": { "$gt": 0 } },
+            doc! { "$inc": { "uses": -1_i64 } },
+            None
+        )).await?;
+        time_db("promotion_uses", "update_one", self.uses.update_one(
+            doc! { "_id": use_id(code, user_email), "count": { "$gt": 0 } },
+            doc! { "$inc": { "count": -1_i64 } },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        let filter = doc! { "_id": { "$regex": format!("\\|{}$", regex::escape(from)) } };
+        let mut cursor = time_db("promotion_uses", "find", self.uses.find(filter, None)).await?;
+        let mut moved = Vec::new();
+        while let Some(used) = cursor.next().await {
+            moved.push(used?);
+        }
+
+        for used in moved {
+            let Ok(id) = used.get_str("_id") else { continue };
+            let Some(code) = id.strip_suffix(from).and_then(|rest| rest.strip_suffix('|')) else { continue };
+            time_db("promotion_uses", "update_one", self.uses.update_one(
+                doc! { "_id": use_id(code, to) },
+                doc! { "$inc": { "count": used.get_i64("count").unwrap_or(0) } },
+                UpdateOptions::builder().upsert(true).build()
+            )).await?;
+            time_db("promotion_uses", "delete_one", self.uses.delete_one(doc! { "_id": id }, None)).await?;
+        }
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryPromotionRepo {
+    promotions: Mutex<Vec<Promotion>>,
+    uses: Mutex<HashMap<String, u32>>,
+}
+
+#[async_trait]
+impl PromotionRepo for MemoryPromotionRepo {
+    async fn find(&self, code: &str) -> RepoResult<Option<Promotion>> {
+        Ok(self.promotions.lock().unwrap().iter().find(|p| p.code == code).cloned())
+    }
+
+    async fn list(&self) -> RepoResult<Vec<Promotion>> {
+        let mut promotions = self.promotions.lock().unwrap().clone();
+        promotions.sort_by(|a, b| a.code.cmp(&b.code));
+        Ok(promotions)
+    }
+
+    async fn insert(&self, promotion: &Promotion) -> RepoResult<()> {
+        let mut promotions = self.promotions.lock().unwrap();
+        if promotions.iter().any(|p| p.code == promotion.code) {
+            return Err(RepoError::Duplicate);
+        }
+        promotions.push(promotion.clone());
+        Ok(())
+    }
+
+    async fn update(&self, promotion: &Promotion) -> RepoResult<bool> {
+        let mut promotions = self.promotions.lock().unwrap();
+        Ok(match promotions.iter_mut().find(|p| p.code == promotion.code) {
+            Some(existing) => {
+                *existing = Promotion { uses: existing.uses, ..promotion.clone() };
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn user_uses(&self, code: &str, user_email: &str) -> RepoResult<u32> {
+        Ok(self.uses.lock().unwrap().get(&use_id(code, user_email)).copied().unwrap_or(0))
+    }
+
+    async fn redeem(&self, promotion: &Promotion, user_email: &str) -> RepoResult<bool> {
+        let mut promotions = self.promotions.lock().unwrap();
+        let mut uses = self.uses.lock().unwrap();
+        let Some(stored) = promotions.iter_mut().find(|p| p.code == promotion.code) else { return Ok(false) };
+        let user_count = uses.entry(use_id(&promotion.code, user_email)).or_insert(0);
+
+        if promotion.max_uses.is_some_and(|max| stored.uses >= max)
+            || promotion.max_uses_per_user.is_some_and(|max| *user_count >= max) {
+            return Ok(false);
+        }
+        stored.uses += 1;
+        *user_count += 1;
+        Ok(true)
+    }
+
+    async fn release(&self, code: &str, user_email: &str) -> RepoResult<()> {
+        if let Some(stored) = self.promotions.lock().unwrap().iter_mut().find(|p| p.code == code) {
+            stored.uses = stored.uses.saturating_sub(1);
+        }
+        if let Some(count) = self.uses.lock().unwrap().get_mut(&use_id(code, user_email)) {
+            *count = count.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        let mut uses = self.uses.lock().unwrap();
+        let moved: Vec<String> = uses.keys()
+            .filter(|id| id.strip_suffix(from).is_some_and(|rest| rest.ends_with('|')))
+            .cloned()
+            .collect();
+        for id in moved {
+            let count = uses.remove(&id).unwrap_or(0);
+            let code = &id[..id.len() - from.len() - 1];
+            *uses.entry(use_id(code, to)).or_insert(0) += count;
+        }
+        Ok(())
+    }
+}
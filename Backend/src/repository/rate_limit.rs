@@ -0,0 +1,126 @@
+// src/repository/rate_limit.rs
+use async_trait::async_trait;
+use chrono::Utc;
+use mongodb::{
+    Collection, Database,
+    bson::{doc, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use super::{RepoError, RepoResult};
+
+/// State of a fixed rate-limit window after recording a hit
+#[derive(Debug, Clone, Copy)]
+pub struct RateWindow {
+    /// Hits in the current window, including this one
+    pub count: u64,
+    /// Unix time (seconds) at which the window resets
+    pub resets_at: i64,
+}
+
+#[async_trait]
+pub trait RateLimitRepo: Send + Sync {
+    /// Record one hit against `key` in the current fixed window of `window_secs`
+    async fn hit(&self, key: &str, window_secs: i64) -> RepoResult<RateWindow>;
+}
+
+fn window_bounds(window_secs: i64) -> (i64, i64) {
+    let now = Utc::now().timestamp();
+    let start = now - now.rem_euclid(window_secs);
+    (start, start + window_secs)
+}
+
+// --- MongoDB ---
+
+pub struct MongoRateLimitRepo {
+    collection: Collection<Document>,
+}
+
+impl MongoRateLimitRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoRateLimitRepo { collection: db.collection("rate_limits") }
+    }
+
+    async fn increment(&self, id: &str, resets_at: i64) -> RepoResult<Option<Document>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let expires_at = mongodb::bson::DateTime::from_millis(resets_at * 1000);
+
+        Ok(time_db("rate_limits", "find_one_and_update", self.collection.find_one_and_update(
+            doc! { "_id": id },
+            doc! { "$inc": { "count": 1_i64 }, "$setOnInsert": { "expires_at": expires_at } },
+            options
+        )).await?)
+    }
+}
+
+#[async_trait]
+impl RateLimitRepo for MongoRateLimitRepo {
+    async fn hit(&self, key: &str, window_secs: i64) -> RepoResult<RateWindow> {
+        let (start, resets_at) = window_bounds(window_secs);
+        let id = format!("{}:{}", key, start);
+
+        // Two concurrent upserts of a new window can collide; the retry finds the winner's document
+        let updated = match self.increment(&id, resets_at).await {
+            Err(RepoError::Duplicate) => self.increment(&id, resets_at).await?,
+            other => other?,
+        };
+
+        let count = updated
+            .and_then(|d| d.get_i64("count").ok())
+            .unwrap_or(1) as u64;
+        Ok(RateWindow { count, resets_at })
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryRateLimitRepo {
+    // key -> (window start, window end, hits)
+    windows: Mutex<HashMap<String, (i64, i64, u64)>>,
+}
+
+#[async_trait]
+impl RateLimitRepo for MemoryRateLimitRepo {
+    async fn hit(&self, key: &str, window_secs: i64) -> RepoResult<RateWindow> {
+        let (start, resets_at) = window_bounds(window_secs);
+        let mut windows = self.windows.lock().unwrap();
+
+        // Drop finished windows now and then so the map doesn't grow forever.
+        // Each key keeps its own window length, so prune on every entry's own end.
+        if windows.len() > 10_000 {
+            let now = Utc::now().timestamp();
+            windows.retain(|_, (_, window_end, _)| *window_end > now);
+        }
+
+        let entry = windows.entry(key.to_string()).or_insert((start, resets_at, 0));
+        if entry.0 != start {
+            *entry = (start, resets_at, 0);
+        }
+        entry.2 += 1;
+
+        Ok(RateWindow { count: entry.2, resets_at })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn pruning_keeps_longer_windows() {
+        let store = MemoryRateLimitRepo::default();
+        store.hit("lockout", 3600).await.unwrap();
+        store.hit("lockout", 3600).await.unwrap();
+        for i in 0..10_001 {
+            store.hit(&format!("ip:{}", i), 1).await.unwrap();
+        }
+        assert_eq!(store.hit("lockout", 3600).await.unwrap().count, 3);
+    }
+}
@@ -0,0 +1,201 @@
+// src/repository/slot.rs
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::StreamExt;
+use mongodb::{
+    Collection, Database,
+    bson::{doc, oid::ObjectId, Document},
+    options::UpdateOptions,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::slot::SlotRule;
+use super::{RepoError, RepoResult};
+
+#[async_trait]
+pub trait SlotRepo: Send + Sync {
+    async fn list_rules(&self) -> RepoResult<Vec<SlotRule>>;
+    async fn insert_rule(&self, rule: &SlotRule) -> RepoResult<ObjectId>;
+    /// Returns false if no rule has this id
+    async fn update_rule(&self, id: ObjectId, rule: &SlotRule) -> RepoResult<bool>;
+    /// Returns false if no rule has this id
+    async fn delete_rule(&self, id: ObjectId) -> RepoResult<bool>;
+    /// Units already booked for each of the given slots (missing slots have none)
+    async fn booked(&self, slot_ids: &[String]) -> RepoResult<HashMap<String, u32>>;
+    /// Atomically book `quantity` units if the slot stays within `capacity`.
+    /// Returns false if the slot is full.
+    async fn reserve(&self, slot_id: &str, quantity: u32, capacity: u32, expires_at: DateTime<Utc>) -> RepoResult<bool>;
+    async fn release(&self, slot_id: &str, quantity: u32) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoSlotRepo {
+    rules: Collection<SlotRule>,
+    bookings: Collection<Document>,
+}
+
+impl MongoSlotRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoSlotRepo {
+            rules: db.collection("slot_rules"),
+            bookings: db.collection("slot_bookings"),
+        }
+    }
+
+    async fn try_reserve(&self, slot_id: &str, quantity: u32, capacity: u32, expires_at: DateTime<Utc>) -> RepoResult<()> {
+        // Matches only while there is room; on a full slot the upsert collides with the existing _id
+        let options = UpdateOptions::builder().upsert(true).build();
+        time_db("slot_bookings", "update_one", self.bookings.update_one(
+            doc! { "_id": slot_id, "booked": { "$lte": (capacity - quantity) as i64 } },
+            doc! {
+                "$inc": { "booked": quantity as i64 },
+                "$setOnInsert": { "expires_at": mongodb::bson::DateTime::from_chrono(expires_at) },
+            },
+            options
+        )).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SlotRepo for MongoSlotRepo {
+    async fn list_rules(&self) -> RepoResult<Vec<SlotRule>> {
+        let mut cursor = time_db("slot_rules", "find", self.rules.find(None, None)).await?;
+
+        let mut rules = Vec::new();
+        while let Some(result) = cursor.next().await {
+            match result {
+                Ok(rule) => rules.push(rule),
+                Err(e) => tracing::error!(error = %e, "Error deserializing slot rule"),
+            }
+        }
+        Ok(rules)
+    }
+
+    async fn insert_rule(&self, rule: &SlotRule) -> RepoResult<ObjectId> {
+        let id = rule.id.unwrap_or_default();
+        let rule = SlotRule { id: Some(id), ..rule.clone() };
+        time_db("slot_rules", "insert_one", self.rules.insert_one(&rule, None)).await?;
+        Ok(id)
+    }
+
+    async fn update_rule(&self, id: ObjectId, rule: &SlotRule) -> RepoResult<bool> {
+        let result = time_db("slot_rules", "update_one", self.rules.update_one(
+            doc! { "_id": id },
+            doc! { "$set": {
+                "category": &rule.category,
+                "start": &rule.start,
+                "end": &rule.end,
+                "capacity": rule.capacity as i64,
+            }},
+            None
+        )).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn delete_rule(&self, id: ObjectId) -> RepoResult<bool> {
+        let result = time_db("slot_rules", "delete_one", self.rules.delete_one(doc! { "_id": id }, None)).await?;
+        Ok(result.deleted_count == 1)
+    }
+
+    async fn booked(&self, slot_ids: &[String]) -> RepoResult<HashMap<String, u32>> {
+        let mut cursor = time_db("slot_bookings", "find", self.bookings.find(doc! { "_id": { "$in": slot_ids } }, None)).await?;
+
+        let mut booked = HashMap::new();
+        while let Some(result) = cursor.next().await {
+            let booking = result?;
+            if let Ok(id) = booking.get_str("_id") {
+                booked.insert(id.to_string(), booking.get_i64("booked").unwrap_or(0).max(0) as u32);
+            }
+        }
+        Ok(booked)
+    }
+
+    async fn reserve(&self, slot_id: &str, quantity: u32, capacity: u32, expires_at: DateTime<Utc>) -> RepoResult<bool> {
+        if quantity > capacity {
+            return Ok(false);
+        }
+        // Two first bookings of a slot can race on the insert; retry once against the winner's document
+        for _ in 0..2 {
+            match self.try_reserve(slot_id, quantity, capacity, expires_at).await {
+                Ok(()) => return Ok(true),
+                Err(RepoError::Duplicate) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(false)
+    }
+
+    async fn release(&self, slot_id: &str, quantity: u32) -> RepoResult<()> {
+        time_db("slot_bookings", "update_one", self.bookings.update_one(
+            doc! { "_id": slot_id, "booked": { "$gte": quantity as i64 } },
+            doc! { "$inc": { "booked": -(quantity as i64) } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemorySlotRepo {
+    rules: Mutex<Vec<SlotRule>>,
+    bookings: Mutex<HashMap<String, u32>>,
+}
+
+#[async_trait]
+impl SlotRepo for MemorySlotRepo {
+    async fn list_rules(&self) -> RepoResult<Vec<SlotRule>> {
+        Ok(self.rules.lock().unwrap().clone())
+    }
+
+    async fn insert_rule(&self, rule: &SlotRule) -> RepoResult<ObjectId> {
+        let id = rule.id.unwrap_or_default();
+        self.rules.lock().unwrap().push(SlotRule { id: Some(id), ..rule.clone() });
+        Ok(id)
+    }
+
+    async fn update_rule(&self, id: ObjectId, rule: &SlotRule) -> RepoResult<bool> {
+        let mut rules = self.rules.lock().unwrap();
+        match rules.iter_mut().find(|r| r.id == Some(id)) {
+            Some(existing) => {
+                *existing = SlotRule { id: Some(id), ..rule.clone() };
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_rule(&self, id: ObjectId) -> RepoResult<bool> {
+        let mut rules = self.rules.lock().unwrap();
+        let before = rules.len();
+        rules.retain(|r| r.id != Some(id));
+        Ok(rules.len() < before)
+    }
+
+    async fn booked(&self, slot_ids: &[String]) -> RepoResult<HashMap<String, u32>> {
+        let bookings = self.bookings.lock().unwrap();
+        Ok(slot_ids.iter().filter_map(|id| bookings.get(id).map(|n| (id.clone(), *n))).collect())
+    }
+
+    async fn reserve(&self, slot_id: &str, quantity: u32, capacity: u32, _expires_at: DateTime<Utc>) -> RepoResult<bool> {
+        let mut bookings = self.bookings.lock().unwrap();
+        let booked = bookings.entry(slot_id.to_string()).or_insert(0);
+        if *booked + quantity > capacity {
+            return Ok(false);
+        }
+        *booked += quantity;
+        Ok(true)
+    }
+
+    async fn release(&self, slot_id: &str, quantity: u32) -> RepoResult<()> {
+        if let Some(booked) = self.bookings.lock().unwrap().get_mut(slot_id) {
+            *booked = booked.saturating_sub(quantity);
+        }
+        Ok(())
+    }
+}
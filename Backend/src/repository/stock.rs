@@ -0,0 +1,116 @@
+// src/repository/stock.rs
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::StreamExt;
+use mongodb::{
+    Collection, Database,
+    bson::{doc, Document},
+    options::UpdateOptions,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use super::RepoResult;
+
+#[async_trait]
+pub trait StockRepo: Send + Sync {
+    /// Units left for each of the given stock ids (ids never stocked are missing)
+    async fn levels(&self, stock_ids: &[String]) -> RepoResult<HashMap<String, u32>>;
+    /// Set the units left. Daily levels pass `expires_at` so old days are cleaned up.
+    async fn set_level(&self, stock_id: &str, item_id: &str, quantity: u32, expires_at: Option<DateTime<Utc>>) -> RepoResult<()>;
+    /// Atomically take `quantity` units; returns false if there aren't enough
+    async fn reserve(&self, stock_id: &str, quantity: u32) -> RepoResult<bool>;
+    async fn release(&self, stock_id: &str, quantity: u32) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoStockRepo {
+    collection: Collection<Document>,
+}
+
+impl MongoStockRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoStockRepo { collection: db.collection("stock") }
+    }
+}
+
+#[async_trait]
+impl StockRepo for MongoStockRepo {
+    async fn levels(&self, stock_ids: &[String]) -> RepoResult<HashMap<String, u32>> {
+        let mut cursor = time_db("stock", "find", self.collection.find(doc! { "_id": { "$in": stock_ids } }, None)).await?;
+
+        let mut levels = HashMap::new();
+        while let Some(result) = cursor.next().await {
+            let level = result?;
+            if let Ok(id) = level.get_str("_id") {
+                levels.insert(id.to_string(), level.get_i64("available").unwrap_or(0).max(0) as u32);
+            }
+        }
+        Ok(levels)
+    }
+
+    async fn set_level(&self, stock_id: &str, item_id: &str, quantity: u32, expires_at: Option<DateTime<Utc>>) -> RepoResult<()> {
+        let mut set = doc! { "item_id": item_id, "available": quantity as i64 };
+        if let Some(expires_at) = expires_at {
+            set.insert("expires_at", mongodb::bson::DateTime::from_chrono(expires_at));
+        }
+        let options = UpdateOptions::builder().upsert(true).build();
+        time_db("stock", "update_one", self.collection.update_one(doc! { "_id": stock_id }, doc! { "$set": set }, options)).await?;
+        Ok(())
+    }
+
+    async fn reserve(&self, stock_id: &str, quantity: u32) -> RepoResult<bool> {
+        let result = time_db("stock", "update_one", self.collection.update_one(
+            doc! { "_id": stock_id, "available": { "$gte": quantity as i64 } },
+            doc! { "$inc": { "available": -(quantity as i64) } },
+            None
+        )).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    async fn release(&self, stock_id: &str, quantity: u32) -> RepoResult<()> {
+        time_db("stock", "update_one", self.collection.update_one(
+            doc! { "_id": stock_id },
+            doc! { "$inc": { "available": quantity as i64 } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryStockRepo {
+    levels: Mutex<HashMap<String, u32>>,
+}
+
+#[async_trait]
+impl StockRepo for MemoryStockRepo {
+    async fn levels(&self, stock_ids: &[String]) -> RepoResult<HashMap<String, u32>> {
+        let levels = self.levels.lock().unwrap();
+        Ok(stock_ids.iter().filter_map(|id| levels.get(id).map(|n| (id.clone(), *n))).collect())
+    }
+
+    async fn set_level(&self, stock_id: &str, _item_id: &str, quantity: u32, _expires_at: Option<DateTime<Utc>>) -> RepoResult<()> {
+        self.levels.lock().unwrap().insert(stock_id.to_string(), quantity);
+        Ok(())
+    }
+
+    async fn reserve(&self, stock_id: &str, quantity: u32) -> RepoResult<bool> {
+        match self.levels.lock().unwrap().get_mut(stock_id) {
+            Some(available) if *available >= quantity => {
+                *available -= quantity;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn release(&self, stock_id: &str, quantity: u32) -> RepoResult<()> {
+        *self.levels.lock().unwrap().entry(stock_id.to_string()).or_insert(0) += quantity;
+        Ok(())
+    }
+}
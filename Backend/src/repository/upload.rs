@@ -0,0 +1,75 @@
+// src/repository/upload.rs
+use async_trait::async_trait;
+use mongodb::{Collection, Database, bson::doc};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::upload::Upload;
+use super::RepoResult;
+
+#[async_trait]
+pub trait UploadRepo: Send + Sync {
+    async fn insert(&self, upload: &Upload) -> RepoResult<()>;
+    async fn find_by_id(&self, id: &str) -> RepoResult<Option<Upload>>;
+    /// Move everything recorded under `from` to `to` once the customer confirms a new email
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoUploadRepo {
+    collection: Collection<Upload>,
+}
+
+impl MongoUploadRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoUploadRepo { collection: db.collection("uploads") }
+    }
+}
+
+#[async_trait]
+impl UploadRepo for MongoUploadRepo {
+    async fn insert(&self, upload: &Upload) -> RepoResult<()> {
+        time_db("uploads", "insert_one", self.collection.insert_one(upload, None)).await?;
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: &str) -> RepoResult<Option<Upload>> {
+        Ok(time_db("uploads", "find_one", self.collection.find_one(doc! { "_id": id }, None)).await?)
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        time_db("uploads", "update_many", self.collection.update_many(
+            doc! { "owner_email": from },
+            doc! { "$set": { "owner_email": to } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryUploadRepo {
+    uploads: Mutex<Vec<Upload>>,
+}
+
+#[async_trait]
+impl UploadRepo for MemoryUploadRepo {
+    async fn insert(&self, upload: &Upload) -> RepoResult<()> {
+        self.uploads.lock().unwrap().push(upload.clone());
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: &str) -> RepoResult<Option<Upload>> {
+        Ok(self.uploads.lock().unwrap().iter().find(|u| u.id == id).cloned())
+    }
+
+    async fn reassign_user(&self, from: &str, to: &str) -> RepoResult<()> {
+        for upload in self.uploads.lock().unwrap().iter_mut().filter(|u| u.owner_email == from) {
+            upload.owner_email = to.to_string();
+        }
+        Ok(())
+    }
+}
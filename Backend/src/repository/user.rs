@@ -0,0 +1,400 @@
+// src/repository/user.rs
+use async_trait::async_trait;
+use mongodb::{
+    Collection, Database,
+    bson::{self, doc, oid::ObjectId, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use std::sync::Mutex;
+
+use crate::metrics::time_db;
+use crate::models::user::User;
+use super::{RepoError, RepoResult};
+
+// Fields saved by `update_profile`; optional ones left empty are removed
+const PROFILE_FIELDS: &[&str] = &["name", "phone", "default_address", "birthday", "marketing"];
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    async fn find_by_email(&self, email: &str) -> RepoResult<Option<User>>;
+    async fn find_by_google_id(&self, google_id: &str) -> RepoResult<Option<User>>;
+    async fn find_by_reset_token(&self, token: &str) -> RepoResult<Option<User>>;
+    /// Fails with `RepoError::Duplicate` if the email or Google ID is taken
+    async fn insert(&self, user: &User) -> RepoResult<()>;
+    async fn set_google_id(&self, email: &str, google_id: &str) -> RepoResult<()>;
+    async fn set_reset_token(&self, email: &str, token: &str, expiry: i64) -> RepoResult<()>;
+    /// Set a new password hash, clear any pending reset token and lockout, and revoke existing sessions
+    async fn update_password(&self, id: ObjectId, password_hash: &str) -> RepoResult<()>;
+    async fn find_by_unlock_token(&self, token: &str) -> RepoResult<Option<User>>;
+    /// Atomically count a failed login; returns the new number of consecutive failures
+    async fn increment_failed_logins(&self, email: &str) -> RepoResult<u32>;
+    /// Refuse logins until `until`; `unlock_token` lifts the lock early until `token_expiry`
+    async fn lock(&self, email: &str, until: i64, unlock_token: &str, token_expiry: i64) -> RepoResult<()>;
+    /// Reset the failure counter and lift any lockout
+    async fn clear_failed_logins(&self, email: &str) -> RepoResult<()>;
+    async fn set_allergies(&self, email: &str, allergies: &[String]) -> RepoResult<()>;
+    /// Give the account admin access; returns false if there is no account with this email
+    async fn set_admin(&self, email: &str) -> RepoResult<bool>;
+    /// Save the fields the customer edits on their profile: name, phone, default address, birthday and marketing
+    async fn update_profile(&self, user: &User) -> RepoResult<()>;
+    /// Hold a new email until the owner confirms it with `token`
+    async fn set_email_change(&self, email: &str, new_email: &str, token: &str, expiry: i64) -> RepoResult<()>;
+    async fn find_by_email_change_token(&self, token: &str) -> RepoResult<Option<User>>;
+    /// Switch to the confirmed email, remember the old one and revoke existing sessions.
+    /// The pending change stays until `finish_email_change` so the link can be retried
+    /// until `retry_expiry`.
+    /// Fails with `RepoError::Duplicate` if another account has taken the email since.
+    async fn change_email(&self, id: ObjectId, new_email: &str, retry_expiry: i64) -> RepoResult<()>;
+    /// Clear the pending change once the customer's records have moved to the new email
+    async fn finish_email_change(&self, id: ObjectId) -> RepoResult<()>;
+}
+
+// --- MongoDB ---
+
+pub struct MongoUserRepo {
+    collection: Collection<User>,
+}
+
+impl MongoUserRepo {
+    pub fn new(db: &Database) -> Self {
+        MongoUserRepo { collection: db.collection("users") }
+    }
+}
+
+#[async_trait]
+impl UserRepo for MongoUserRepo {
+    async fn find_by_email(&self, email: &str) -> RepoResult<Option<User>> {
+        Ok(time_db("users", "find_one", self.collection.find_one(doc! { "email": email }, None)).await?)
+    }
+
+    async fn find_by_google_id(&self, google_id: &str) -> RepoResult<Option<User>> {
+        Ok(time_db("users", "find_one", self.collection.find_one(doc! { "google_id": google_id }, None)).await?)
+    }
+
+    async fn find_by_reset_token(&self, token: &str) -> RepoResult<Option<User>> {
+        Ok(time_db("users", "find_one", self.collection.find_one(doc! { "reset_token": token }, None)).await?)
+    }
+
+    async fn insert(&self, user: &User) -> RepoResult<()> {
+        time_db("users", "insert_one", self.collection.insert_one(user, None)).await?;
+        Ok(())
+    }
+
+    async fn set_google_id(&self, email: &str, google_id: &str) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! { "$set": { "google_id": google_id } },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn set_reset_token(&self, email: &str, token: &str, expiry: i64) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! { "$set": {
+                "reset_token": token,
+                "reset_token_expiry": expiry
+            }},
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn update_password(&self, id: ObjectId, password_hash: &str) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "_id": id },
+            doc! {
+                "$set": { "password_hash": password_hash, "failed_login_attempts": 0 },
+                "$unset": { "reset_token": "", "reset_token_expiry": "", "locked_until": "", "unlock_token": "", "unlock_token_expiry": "" },
+                "$inc": { "token_version": 1 }
+            },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn find_by_unlock_token(&self, token: &str) -> RepoResult<Option<User>> {
+        Ok(time_db("users", "find_one", self.collection.find_one(doc! { "unlock_token": token }, None)).await?)
+    }
+
+    async fn increment_failed_logins(&self, email: &str) -> RepoResult<u32> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let user = time_db("users", "find_one_and_update", self.collection.find_one_and_update(
+            doc! { "email": email },
+            doc! { "$inc": { "failed_login_attempts": 1 } },
+            options
+        )).await?;
+        Ok(user.map(|u| u.failed_login_attempts).unwrap_or(0))
+    }
+
+    async fn lock(&self, email: &str, until: i64, unlock_token: &str, token_expiry: i64) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! { "$set": { "locked_until": until, "unlock_token": unlock_token, "unlock_token_expiry": token_expiry } },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn clear_failed_logins(&self, email: &str) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! {
+                "$set": { "failed_login_attempts": 0 },
+                "$unset": { "locked_until": "", "unlock_token": "", "unlock_token_expiry": "" }
+            },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn set_allergies(&self, email: &str, allergies: &[String]) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! { "$set": { "allergies": allergies } },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn set_admin(&self, email: &str) -> RepoResult<bool> {
+        let result = time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! { "$set": { "is_admin": true } },
+            None
+        )).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn update_profile(&self, user: &User) -> RepoResult<()> {
+        let document = bson::to_document(user).map_err(|e| RepoError::Database(e.to_string()))?;
+        let mut set = Document::new();
+        let mut unset = Document::new();
+        for field in PROFILE_FIELDS {
+            match document.get(*field) {
+                Some(value) => set.insert(*field, value.clone()),
+                None => unset.insert(*field, ""),
+            };
+        }
+
+        let mut update = doc! { "$set": set };
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+        time_db("users", "update_one", self.collection.update_one(doc! { "email": &user.email }, update, None)).await?;
+        Ok(())
+    }
+
+    async fn set_email_change(&self, email: &str, new_email: &str, token: &str, expiry: i64) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "email": email },
+            doc! { "$set": {
+                "pending_email": new_email,
+                "email_change_token": token,
+                "email_change_expiry": expiry
+            }},
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn find_by_email_change_token(&self, token: &str) -> RepoResult<Option<User>> {
+        Ok(time_db("users", "find_one", self.collection.find_one(doc! { "email_change_token": token }, None)).await?)
+    }
+
+    async fn change_email(&self, id: ObjectId, new_email: &str, retry_expiry: i64) -> RepoResult<()> {
+        let Some(user) = time_db("users", "find_one", self.collection.find_one(doc! { "_id": id }, None)).await? else {
+            return Ok(());
+        };
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "_id": id },
+            doc! {
+                "$set": { "email": new_email, "previous_email": &user.email, "email_change_expiry": retry_expiry },
+                "$inc": { "token_version": 1 }
+            },
+            None
+        )).await?;
+        Ok(())
+    }
+
+    async fn finish_email_change(&self, id: ObjectId) -> RepoResult<()> {
+        time_db("users", "update_one", self.collection.update_one(
+            doc! { "_id": id },
+            doc! { "$unset": { "pending_email": "", "email_change_token": "", "email_change_expiry": "", "previous_email": "" } },
+            None
+        )).await?;
+        Ok(())
+    }
+}
+
+// --- In-memory ---
+
+#[derive(Default)]
+pub struct MemoryUserRepo {
+    users: Mutex<Vec<User>>,
+}
+
+impl MemoryUserRepo {
+    fn find_by<F: Fn(&User) -> bool>(&self, pred: F) -> Option<User> {
+        self.users.lock().unwrap().iter().find(|u| pred(u)).cloned()
+    }
+
+    fn update_where<P: Fn(&User) -> bool, F: Fn(&mut User)>(&self, pred: P, apply: F) {
+        if let Some(user) = self.users.lock().unwrap().iter_mut().find(|u| pred(u)) {
+            apply(user);
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepo for MemoryUserRepo {
+    async fn find_by_email(&self, email: &str) -> RepoResult<Option<User>> {
+        Ok(self.find_by(|u| u.email == email))
+    }
+
+    async fn find_by_google_id(&self, google_id: &str) -> RepoResult<Option<User>> {
+        Ok(self.find_by(|u| u.google_id.as_deref() == Some(google_id)))
+    }
+
+    async fn find_by_reset_token(&self, token: &str) -> RepoResult<Option<User>> {
+        Ok(self.find_by(|u| u.reset_token.as_deref() == Some(token)))
+    }
+
+    async fn insert(&self, user: &User) -> RepoResult<()> {
+        let mut users = self.users.lock().unwrap();
+
+        // Mirror the unique indexes on email and google_id
+        let taken = users.iter().any(|u| {
+            u.email == user.email || (user.google_id.is_some() && u.google_id == user.google_id)
+        });
+        if taken {
+            return Err(RepoError::Duplicate);
+        }
+
+        let mut user = user.clone();
+        user.id.get_or_insert_with(ObjectId::new);
+        users.push(user);
+        Ok(())
+    }
+
+    async fn set_google_id(&self, email: &str, google_id: &str) -> RepoResult<()> {
+        self.update_where(|u| u.email == email, |u| u.google_id = Some(google_id.to_string()));
+        Ok(())
+    }
+
+    async fn set_reset_token(&self, email: &str, token: &str, expiry: i64) -> RepoResult<()> {
+        self.update_where(|u| u.email == email, |u| {
+            u.reset_token = Some(token.to_string());
+            u.reset_token_expiry = Some(expiry);
+        });
+        Ok(())
+    }
+
+    async fn update_password(&self, id: ObjectId, password_hash: &str) -> RepoResult<()> {
+        self.update_where(|u| u.id == Some(id), |u| {
+            u.password_hash = Some(password_hash.to_string());
+            u.reset_token = None;
+            u.reset_token_expiry = None;
+            u.failed_login_attempts = 0;
+            u.locked_until = None;
+            u.unlock_token = None;
+            u.unlock_token_expiry = None;
+            u.token_version += 1;
+        });
+        Ok(())
+    }
+
+    async fn find_by_unlock_token(&self, token: &str) -> RepoResult<Option<User>> {
+        Ok(self.find_by(|u| u.unlock_token.as_deref() == Some(token)))
+    }
+
+    async fn increment_failed_logins(&self, email: &str) -> RepoResult<u32> {
+        let mut users = self.users.lock().unwrap();
+        Ok(match users.iter_mut().find(|u| u.email == email) {
+            Some(user) => {
+                user.failed_login_attempts += 1;
+                user.failed_login_attempts
+            }
+            None => 0,
+        })
+    }
+
+    async fn lock(&self, email: &str, until: i64, unlock_token: &str, token_expiry: i64) -> RepoResult<()> {
+        self.update_where(|u| u.email == email, |u| {
+            u.locked_until = Some(until);
+            u.unlock_token = Some(unlock_token.to_string());
+            u.unlock_token_expiry = Some(token_expiry);
+        });
+        Ok(())
+    }
+
+    async fn clear_failed_logins(&self, email: &str) -> RepoResult<()> {
+        self.update_where(|u| u.email == email, |u| {
+            u.failed_login_attempts = 0;
+            u.locked_until = None;
+            u.unlock_token = None;
+            u.unlock_token_expiry = None;
+        });
+        Ok(())
+    }
+    async fn set_allergies(&self, email: &str, allergies: &[String]) -> RepoResult<()> {
+        self.update_where(|u| u.email == email, |u| u.allergies = allergies.to_vec());
+        Ok(())
+    }
+
+    async fn set_admin(&self, email: &str) -> RepoResult<bool> {
+        let found = self.find_by(|u| u.email == email).is_some();
+        self.update_where(|u| u.email == email, |u| u.is_admin = true);
+        Ok(found)
+    }
+
+    async fn update_profile(&self, user: &User) -> RepoResult<()> {
+        self.update_where(|u| u.email == user.email, |u| {
+            u.name = user.name.clone();
+            u.phone = user.phone.clone();
+            u.default_address = user.default_address.clone();
+            u.birthday = user.birthday;
+            u.marketing = user.marketing;
+        });
+        Ok(())
+    }
+
+    async fn set_email_change(&self, email: &str, new_email: &str, token: &str, expiry: i64) -> RepoResult<()> {
+        self.update_where(|u| u.email == email, |u| {
+            u.pending_email = Some(new_email.to_string());
+            u.email_change_token = Some(token.to_string());
+            u.email_change_expiry = Some(expiry);
+        });
+        Ok(())
+    }
+
+    async fn find_by_email_change_token(&self, token: &str) -> RepoResult<Option<User>> {
+        Ok(self.find_by(|u| u.email_change_token.as_deref() == Some(token)))
+    }
+
+    async fn change_email(&self, id: ObjectId, new_email: &str, retry_expiry: i64) -> RepoResult<()> {
+        let mut users = self.users.lock().unwrap();
+        if users.iter().any(|u| u.email == new_email && u.id != Some(id)) {
+            return Err(RepoError::Duplicate);
+        }
+        if let Some(user) = users.iter_mut().find(|u| u.id == Some(id)) {
+            user.previous_email = Some(std::mem::replace(&mut user.email, new_email.to_string()));
+            user.email_change_expiry = Some(retry_expiry);
+            user.token_version += 1;
+        }
+        Ok(())
+    }
+
+    async fn finish_email_change(&self, id: ObjectId) -> RepoResult<()> {
+        self.update_where(|u| u.id == Some(id), |u| {
+            u.pending_email = None;
+            u.email_change_token = None;
+            u.email_change_expiry = None;
+            u.previous_email = None;
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+// src/storage.rs
+//
+// Where uploaded files live. Handlers depend on the `FileStorage` trait so the
+// local disk store can be swapped for object storage later.
+use async_trait::async_trait;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub struct StorageError(String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "storage error: {}", self.0)
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[async_trait]
+pub trait FileStorage: Send + Sync {
+    /// Store `bytes` under `key` (e.g. "reference/<id>.jpg"), replacing any existing file
+    async fn put(&self, key: &str, bytes: &[u8]) -> StorageResult<()>;
+    /// None if nothing is stored under `key`
+    async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>>;
+}
+
+// Keys are generated by the server, but never let one escape the storage root
+fn is_safe_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with('/') && key.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+// --- Local disk ---
+
+pub struct LocalDiskStorage {
+    root: PathBuf,
+}
+
+impl LocalDiskStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalDiskStorage { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> StorageResult<PathBuf> {
+        if !is_safe_key(key) {
+            return Err(StorageError(format!("invalid key '{}'", key)));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl FileStorage for LocalDiskStorage {
+    async fn put(&self, key: &str, bytes: &[u8]) -> StorageResult<()> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        // Write to a temporary file first so readers never see a half-written image
+        let tmp = path.with_extension("part");
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+// src/test_util.rs
+//
+// Helpers for handler tests: handlers run inside an actix test service over the
+// in-memory repositories, so no mongod is needed.
+use actix_http::Request;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test,
+};
+use serde_json::Value;
+
+use crate::utils::jwt;
+
+/// A session token for `email`, as issued at login to an account that never changed its password
+pub fn bearer(email: &str) -> String {
+    std::env::set_var("JWT_SECRET", "test-secret");
+    format!("Bearer {}", jwt::create_token(email, "Test", 0).unwrap())
+}
+
+/// Send `req` to the test service; returns the status and the JSON body (Null if there is none)
+pub async fn call<S, B>(app: &S, req: test::TestRequest) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    std::env::set_var("JWT_SECRET", "test-secret");
+    let res = test::call_service(app, req.to_request()).await;
+    let status = res.status();
+    let body = test::read_body(res).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
@@ -0,0 +1,18 @@
+// src/utils/geo.rs
+use crate::models::order::GeoPoint;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+pub fn is_valid(point: &GeoPoint) -> bool {
+    (-90.0..=90.0).contains(&point.lat) && (-180.0..=180.0).contains(&point.lng)
+}
+
+/// Straight-line (haversine) distance between two points in kilometres
+pub fn distance_km(a: &GeoPoint, b: &GeoPoint) -> f64 {
+    let (lat_a, lat_b) = (a.lat.to_radians(), b.lat.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lng = (b.lng - a.lng).to_radians();
+
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
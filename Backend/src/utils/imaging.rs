@@ -0,0 +1,85 @@
+// src/utils/imaging.rs
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageReader, Limits};
+use std::io::Cursor;
+
+// Refuse images that would decode to something huge (decompression bombs)
+const MAX_DIMENSION: u32 = 8000;
+const MAX_DECODED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// The sizes generated for every product image
+#[derive(Debug, Clone, Copy)]
+pub enum Variant {
+    /// Square crop for lists and the cart
+    Thumb,
+    /// Menu card
+    Card,
+    /// Product page, never larger than the original
+    Full,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::Thumb, Variant::Card, Variant::Full];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Thumb => "thumb",
+            Variant::Card => "card",
+            Variant::Full => "full",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Variant> {
+        Variant::ALL.into_iter().find(|v| v.name() == name)
+    }
+
+    fn render(self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Variant::Thumb => image.resize_to_fill(160, 160, FilterType::Lanczos3),
+            Variant::Card => image.resize_to_fill(480, 360, FilterType::Lanczos3),
+            Variant::Full if image.width() > 1600 || image.height() > 1600 => image.resize(1600, 1600, FilterType::Lanczos3),
+            Variant::Full => image.clone(),
+        }
+    }
+}
+
+/// Work out the image type from the file's magic bytes rather than trusting the client
+pub fn sniff(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(("image/jpeg", "jpg"))
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(("image/png", "png"))
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(("image/webp", "webp"))
+    } else {
+        None
+    }
+}
+
+/// Decode an uploaded JPEG, PNG or WebP within the size limits
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, String> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODED_BYTES);
+
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format().map_err(|e| e.to_string())?;
+    reader.limits(limits);
+    reader.decode().map_err(|e| e.to_string())
+}
+
+/// Render one variant as WebP
+pub fn encode_variant(image: &DynamicImage, variant: Variant) -> Result<Vec<u8>, String> {
+    // The WebP encoder only takes 8-bit RGB(A)
+    let resized = variant.render(image);
+    let resized = if resized.color().has_alpha() {
+        DynamicImage::ImageRgba8(resized.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(resized.to_rgb8())
+    };
+
+    let mut bytes = Vec::new();
+    resized.write_with_encoder(WebPEncoder::new_lossless(&mut bytes)).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
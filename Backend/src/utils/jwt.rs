@@ -1,4 +1,5 @@
 // src/utils/jwt.rs
+use actix_web::HttpRequest;
 use jsonwebtoken::{encode, Header, Algorithm, EncodingKey};
 use chrono::{Utc, Duration};
 use serde::{Serialize, Deserialize};
@@ -9,6 +10,8 @@ pub struct Claims {
     pub sub: String, // Subject (user email)
     pub exp: i64,    // Expiration time
     pub name: String,
+    #[serde(default)]
+    pub ver: u32,    // The user's token_version when issued
 }
 
 // Get the JWT secret from .env
@@ -17,7 +20,7 @@ fn get_jwt_secret() -> String {
 }
 
 // Create a new JWT
-pub fn create_token(email: &str, name: &str) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn create_token(email: &str, name: &str, version: u32) -> Result<String, jsonwebtoken::errors::Error> {
     let expiration = Utc::now()
         .checked_add_signed(Duration::days(7)) // Token is valid for 7 days
         .expect("Failed to create expiration")
@@ -27,6 +30,7 @@ pub fn create_token(email: &str, name: &str) -> Result<String, jsonwebtoken::err
         sub: email.to_owned(),
         exp: expiration,
         name: name.to_owned(),
+        ver: version,
     };
 
     let header = Header::new(Algorithm::HS256);
@@ -41,4 +45,24 @@ pub fn decode_token(token: &str) -> Result<jsonwebtoken::TokenData<Claims>, json
     let validation = jsonwebtoken::Validation::new(Algorithm::HS256);
     
     jsonwebtoken::decode::<Claims>(token, &key, &validation)
-}
\ No newline at end of file
+}
+
+// The claims of the "Authorization: Bearer <jwt>" header
+pub fn get_claims_from_req(req: &HttpRequest) -> Result<Claims, String> {
+    if let Some(auth_header) = req.headers().get("Authorization") {
+        if let Ok(auth_str) = auth_header.to_str() {
+            if let Some(token) = auth_str.strip_prefix("Bearer ") {
+                match decode_token(token) {
+                    Ok(token_data) => return Ok(token_data.claims),
+                    Err(_) => return Err("Invalid token".to_string()),
+                }
+            }
+        }
+    }
+    Err("No authorization header".to_string())
+}
+
+// Extract the user's email from the "Authorization: Bearer <jwt>" header
+pub fn get_user_email_from_req(req: &HttpRequest) -> Result<String, String> {
+    get_claims_from_req(req).map(|claims| claims.sub)
+}
@@ -1,3 +1,7 @@
 // src/utils/mod.rs
 pub mod jwt; // Assuming you have src/utils/jwt.rs
-pub mod password; // Assuming you have src/utils/password.rs
\ No newline at end of file
+pub mod password; // Assuming you have src/utils/password.rs
+pub mod redact;
+pub mod geo;
+pub mod imaging;
+pub mod search;
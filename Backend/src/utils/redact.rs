@@ -0,0 +1,71 @@
+// src/utils/redact.rs
+//
+// Masks personal data (emails, phone numbers, account numbers) and secrets
+// (tokens, JWTs) in free text such as log lines.
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+// JSON fields whose whole value is a secret, e.g. "reset_token":"...", escaped quotes included
+static TOKEN_FIELD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#""(\w*(?:token|secret|password)\w*)":"(?:[^"\\]|\\.)*""#).unwrap()
+});
+// token=... in URLs such as password reset links
+static TOKEN_PARAM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(token=)[^&\s\\]+").unwrap());
+static BEARER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(Bearer )[\w\-.]+").unwrap());
+static JWT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\beyJ[\w-]+\.[\w-]+\.[\w-]+").unwrap()
+});
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b([A-Za-z0-9._%+-])[A-Za-z0-9._%+-]*@([A-Za-z0-9.-]+\.[A-Za-z]{2,})\b").unwrap()
+});
+// Kenyan MSISDNs: 2547XXXXXXXX, +2541XXXXXXXX, 07XXXXXXXX
+static MSISDN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\+?\b(?:254|0)[17]\d{6}(\d{2})\b").unwrap()
+});
+// Digits given for a phone, account or card field, e.g. "account_number":"12345678" or msisdn=...
+// Other numbers (timestamps, totals, ids) are left readable.
+static ACCOUNT_NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)("?\w*(?:phone|account|msisdn|card|party[ab])\w*"?\s*[:=]\s*"?)\+?\d{4,}(\d{4})\b"#).unwrap()
+});
+
+/// Return `text` with secrets removed and personal data masked
+pub fn redact(text: &str) -> String {
+    let text = TOKEN_FIELD.replace_all(text, r#""$1":"[REDACTED]""#);
+    let text = TOKEN_PARAM.replace_all(&text, "${1}[REDACTED]");
+    let text = BEARER.replace_all(&text, "${1}[REDACTED]");
+    let text = JWT.replace_all(&text, "[REDACTED_JWT]");
+    let text = EMAIL.replace_all(&text, |c: &Captures| format!("{}***@{}", &c[1], &c[2]));
+    let text = MSISDN.replace_all(&text, "[PHONE ***$1]");
+    let text = ACCOUNT_NUMBER.replace_all(&text, "${1}[NUMBER ***$2]");
+    text.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_personal_data() {
+        assert_eq!(redact("order for jane.doe@example.com"), "order for j***@example.com");
+        assert_eq!(redact("phone 254712345678"), "phone [PHONE ***78]");
+        assert_eq!(redact("phone 0712345678"), "phone [PHONE ***78]");
+        assert_eq!(redact(r#"{"account_number":"1234567890123"}"#), r#"{"account_number":"[NUMBER ***0123]"}"#);
+        assert_eq!(redact("bank account=00123456789"), "bank account=[NUMBER ***6789]");
+    }
+
+    #[test]
+    fn removes_secrets() {
+        assert_eq!(redact(r#"{"reset_token":"abc","name":"x"}"#), r#"{"reset_token":"[REDACTED]","name":"x"}"#);
+        assert_eq!(redact(r#"{"client_secret":"ab\"cd\\","name":"x"}"#), r#"{"client_secret":"[REDACTED]","name":"x"}"#);
+        assert_eq!(redact("https://shop.test/reset?token=abc-123&x=1"), "https://shop.test/reset?token=[REDACTED]&x=1");
+        assert_eq!(redact("Authorization: Bearer abc.def-ghi"), "Authorization: Bearer [REDACTED]");
+        assert_eq!(redact("jwt eyJhbGci.eyJzdWIi.c2lnbmF0dXJl"), "jwt [REDACTED_JWT]");
+    }
+
+    #[test]
+    fn leaves_other_numbers_readable() {
+        assert_eq!(redact("2 cupcakes, order 42"), "2 cupcakes, order 42");
+        assert_eq!(redact(r#"{"locked_until":1792400000000,"total_cents":1234500}"#), r#"{"locked_until":1792400000000,"total_cents":1234500}"#);
+        assert_eq!(redact("checkout ws_CO_19102026120000123456"), "checkout ws_CO_19102026120000123456");
+    }
+}
@@ -0,0 +1,135 @@
+// src/utils/search.rs
+//
+// Relevance ranking for the product catalog. MongoDB's text index answers most
+// searches; this scores in process when it finds nothing, to forgive typos
+// ("choclate", "croisant"), and backs the in-memory store.
+use strsim::damerau_levenshtein;
+
+use crate::models::product::Product;
+
+// Where a query term matched, by weight
+const TITLE_WEIGHT: f64 = 3.0;
+const CATEGORY_WEIGHT: f64 = 1.5;
+const TEXT_WEIGHT: f64 = 1.0;
+// Bonus when the whole query appears in the title as typed
+const PHRASE_BONUS: f64 = 2.0;
+
+/// Lowercase words of letters and digits
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Canonical form of a dietary tag, e.g. "Gluten Free" -> "gluten-free"
+pub fn normalise_tag(tag: &str) -> String {
+    tokenize(tag).join("-")
+}
+
+// Edits allowed before a word stops counting as a typo of the term
+fn max_typos(term: &str) -> usize {
+    match term.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+// How well one query term matches one word: exact, prefix (still typing) or typo
+fn word_score(term: &str, word: &str) -> f64 {
+    if word == term {
+        1.0
+    } else if term.chars().count() >= 2 && word.starts_with(term) {
+        0.8
+    } else {
+        let allowed = max_typos(term);
+        if allowed > 0 && damerau_levenshtein(term, word) <= allowed { 0.6 } else { 0.0 }
+    }
+}
+
+fn best_score(term: &str, words: &[String]) -> f64 {
+    words.iter().map(|word| word_score(term, word)).fold(0.0, f64::max)
+}
+
+/// Relevance of `product` to the query terms, or None if any term matches nothing
+pub fn score(product: &Product, terms: &[String], phrase: &str) -> Option<f64> {
+    let title = tokenize(&product.title);
+    let category = tokenize(&product.category);
+    let mut text = tokenize(product.description.as_deref().unwrap_or_default());
+    text.extend(product.dietary_tags.iter().flat_map(|tag| tokenize(tag)));
+
+    let mut total = 0.0;
+    for term in terms {
+        let best = [
+            best_score(term, &title) * TITLE_WEIGHT,
+            best_score(term, &category) * CATEGORY_WEIGHT,
+            best_score(term, &text) * TEXT_WEIGHT,
+        ].into_iter().fold(0.0, f64::max);
+        if best == 0.0 {
+            return None;
+        }
+        total += best;
+    }
+
+    if !phrase.is_empty() && product.title.to_lowercase().contains(phrase) {
+        total += PHRASE_BONUS;
+    }
+    Some(total)
+}
+
+/// The products matching `phrase`, best first; ties keep their menu order
+pub fn rank(products: Vec<Product>, phrase: &str) -> Vec<(f64, Product)> {
+    let phrase = phrase.trim().to_lowercase();
+    let terms = tokenize(&phrase);
+    let mut matches: Vec<(f64, Product)> = products.into_iter()
+        .filter_map(|p| score(&p, &terms, &phrase).map(|score| (score, p)))
+        .collect();
+    matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::product::{custom_cake, menu_specials};
+
+    fn terms(query: &str) -> Vec<String> {
+        tokenize(query)
+    }
+
+    #[test]
+    fn title_matches_outrank_description_matches() {
+        let pie = &menu_specials()[0];
+        let title = score(pie, &terms("pie"), "pie").unwrap();
+        let description = score(pie, &terms("fruits"), "fruits").unwrap();
+        assert!(title > description, "{} <= {}", title, description);
+    }
+
+    #[test]
+    fn forgives_typos_and_prefixes() {
+        let pie = &menu_specials()[0];
+        assert!(score(pie, &terms("seasnal"), "seasnal").is_some());
+        assert!(score(pie, &terms("seas"), "seas").is_some());
+        assert!(score(pie, &terms("pye"), "pye").is_none(), "short terms must match exactly");
+    }
+
+    #[test]
+    fn every_term_has_to_match() {
+        let pie = &menu_specials()[0];
+        assert!(score(pie, &terms("fruit pie"), "fruit pie").is_some());
+        assert!(score(pie, &terms("fruit cupcakes"), "fruit cupcakes").is_none());
+    }
+
+    #[test]
+    fn rank_puts_the_best_match_first() {
+        let mut products = menu_specials();
+        products.push(custom_cake());
+        let ranked = rank(products, "  Party PLATTER ");
+        assert_eq!(ranked.len(), 1);
+        assert_eq!(ranked[0].1.item_id, "party-platter");
+
+        let ranked = rank(menu_specials().into_iter().chain([custom_cake()]).collect(), "cake");
+        assert_eq!(ranked[0].1.item_id, "custom-cake");
+    }
+}
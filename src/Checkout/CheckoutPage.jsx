@@ -41,11 +41,12 @@ const CheckoutPage = () => {
         try {
             // Prepare items for backend
             const orderItems = cartItems.map(item => ({
-                item_id: item.id,
+                item_id: item.itemId || item.id,
                 title: item.title,
                 quantity: item.quantity,
                 price: item.price || 0,
-                image_src: item.imageSrc || ''
+                image_src: item.imageSrc || '',
+                options: item.options || []
             }));
 
             const checkoutData = {
@@ -166,8 +166,19 @@ const SpecialsPage = () => {
     const handleAddCustomCakeToCart = () => {
         const description = `${customCake.size.label}, ${customCake.base.label}, ${customCake.frosting.label}, ${customCake.filling.label !== 'None' ? customCake.filling.label + ' Filling' : 'No Filling'}`;
 
+        // Each cake is its own cart line, but the server prices it as the custom cake product
+        const options = [
+            { group: 'size', option: customCake.size.id },
+            { group: 'base', option: customCake.base.id },
+            { group: 'frosting', option: customCake.frosting.id },
+            { group: 'filling', option: customCake.filling.id },
+            ...customCake.toppings.map(topping => ({ group: 'toppings', option: topping.id })),
+        ];
+
         const customItem = {
             id: `custom-cake-${Date.now()}`,
+            itemId: 'custom-cake',
+            options,
             title: 'Custom Cake Creation',
             description: description,
             price: customPrice,
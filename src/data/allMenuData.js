@@ -0,0 +1,332 @@
+// src/data/allMenuData.js
+// The regular menu. Ids match the backend catalog, which prices every checkout;
+// items without a price are sold in store only.
+
+export const cakeMenuData = [
+    {
+        id: 'chocolate-cake',
+        title: 'Chocolate',
+        description: 'Rich, dark, and decadent fudge cake, a timeless indulgence.',
+        imageSrc: '/Frontend/cakes/chocolate_cake.jpg.jpg',
+        imageAlt: 'A slice of rich chocolate fudge cake.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'vanilla-bean-cake',
+        title: 'Vanilla Bean',
+        description: 'Light and fluffy sponge infused with real vanilla bean. Perfect with any filling.',
+        imageSrc: '/Frontend/cakes/vanilla_cake.jpg.jpg',
+        imageAlt: 'A piece of vanilla bean cake.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'red-velvet-cake',
+        title: 'Red Velvet',
+        description: 'Subtle cocoa flavor with a striking red hue, paired with tangy cream cheese frosting.',
+        imageSrc: '/Frontend/cakes/red_velvet_cake.jpg.jpg',
+        imageAlt: 'Red velvet cake with cream cheese frosting.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'lemon-zest-cake',
+        title: 'Lemon Zest',
+        description: 'Bright, zesty, and refreshing—a tart and sweet citrus delight.',
+        imageSrc: '/Frontend/cakes/lemon_cake.jpg.jpg',
+        imageAlt: 'A bright lemon cake with glaze.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'coconut-dream-cake',
+        title: 'Coconut Dream',
+        description: 'Moist cake studded with sweet coconut flakes and a creamy filling.',
+        imageSrc: '/Frontend/cakes/coconut_cake.jpg.jpg',
+        imageAlt: 'A white layer cake topped with shredded coconut.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'pina-colada-cake',
+        title: 'Piña Colada',
+        description: 'A tropical blend of sweet pineapple and creamy coconut with a hint of rum flavor.',
+        imageSrc: '/Frontend/cakes/pina_colada_cake.jpg.jpg',
+        imageAlt: 'Tropical cake with pineapple and coconut.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'orange-cake',
+        title: 'Orange',
+        description: 'A juicy splash.',
+        imageSrc: '/Frontend/cakes/Orange cake.jpeg',
+        imageAlt: 'Orange cake.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'butter-cake',
+        title: 'Butter',
+        description: 'Soft cushy and tasty.',
+        imageSrc: '/Frontend/cakes/Butter Cake.jpeg',
+        imageAlt: 'Butter cake.',
+        category: 'cakes',
+        price: null
+    },
+    {
+        id: 'cinnamon-cake',
+        title: 'Cinnamon',
+        description: 'Cinnamon full of swirl equal double trouble.',
+        imageSrc: '/Frontend/cakes/Cinnamon Swirl Bundt Cake.jpeg',
+        imageAlt: 'Cinnamon Swirl Bundt Cake.',
+        category: 'cakes',
+        price: null
+    },
+];
+
+export const cookiesData = [
+    {
+        id: 'brown-butter-chocolate-chip',
+        title: 'Brown Butter Chocolate Chip',
+        description: 'Nutty brown butter base with pools of melted dark chocolate.',
+        imageSrc: '/Frontend/cookies/Choco chips cookie.jpeg',
+        imageAlt: 'Brown Butter Chocolate Chip Cookies',
+        category: 'cookies',
+        price: 3.50
+    },
+    {
+        id: 'butter-cookies',
+        title: 'Butter Cookies',
+        description: 'Simple, delicate, and melt-in-your-mouth Danish classic.',
+        imageSrc: '/Frontend/cookies/Butter Cookies.jpeg',
+        imageAlt: 'Butter Cookies',
+        category: 'cookies',
+        price: 2.00
+    },
+    {
+        id: 'caramel-cookies',
+        title: 'Caramel Cookies',
+        description: 'Soft cookies loaded with chewy caramel chunks.',
+        imageSrc: '/Frontend/cookies/Caramel cookies.jpeg',
+        imageAlt: 'Caramel Cookies',
+        category: 'cookies',
+        price: 3.75
+    },
+    {
+        id: 'gingerbread-men',
+        title: 'Classic Gingerbread Men',
+        description: 'Warm spice and molasses, decorated with royal icing.',
+        imageSrc: '/Frontend/cookies/Classic Gingerbread Men Cookies.jpeg',
+        imageAlt: 'Classic Gingerbread Men Cookies',
+        category: 'cookies',
+        price: 4.00
+    },
+    {
+        id: 'double-chocolate-cookies',
+        title: 'Double Chocolate Cookies',
+        description: 'Rich cocoa cookie base with both milk and dark chocolate chips.',
+        imageSrc: '/Frontend/cookies/Double Chocolate Cookies.jpeg',
+        imageAlt: 'Double Chocolate Cookies',
+        category: 'cookies',
+        price: 3.25
+    },
+    {
+        id: 'german-spritz-cookies',
+        title: 'German Spritz Cookies',
+        description: 'Delicate, pressed shortbread, often dipped in chocolate.',
+        imageSrc: '/Frontend/cookies/German Spritz Cookies.jpeg',
+        imageAlt: 'German Spritz Cookies',
+        category: 'cookies',
+        price: 2.50
+    },
+    {
+        id: 'ginger-cookies',
+        title: 'Ginger Cookies',
+        description: 'Chewy, soft cookies with a strong, spicy ginger kick.',
+        imageSrc: '/Frontend/cookies/Ginger Cookies.jpeg',
+        imageAlt: 'Ginger Cookies',
+        category: 'cookies',
+        price: 3.00
+    },
+    {
+        id: 'valentine-sugar-cookies',
+        title: 'Valentine Sugar Cookies',
+        description: 'Our classic sugar cookie, cut into hearts and decorated.',
+        imageSrc: '/Frontend/cookies/Valentine Sugar Cookies.jpeg',
+        imageAlt: 'Valentine Sugar Cookies',
+        category: 'cookies',
+        price: 4.50
+    },
+    {
+        id: 'chocolate-pinwheel-cookies',
+        title: 'Chocolate Pinwheel Cookies',
+        description: 'With swirls of chocolate and vanilla in every bite.',
+        imageSrc: '/Frontend/cookies/Chocolate and Vanilla Pinwheel Cookies.jpeg',
+        imageAlt: 'Pinwheel Cookies',
+        category: 'cookies',
+        price: 4.50
+    },
+];
+
+export const drinksMenuData = [
+    {
+        id: 'water',
+        title: 'Water',
+        description: 'Still or sparkling, served chilled.',
+        imageSrc: '/Frontend/drinks/water.jpeg',
+        imageAlt: 'Water Bottle',
+        category: 'drinks',
+        price: 1.00
+    },
+    {
+        id: 'classic-mojito',
+        title: 'Classic Mojito',
+        description: 'Fresh mint, lime, sugar, and sparkling soda.',
+        imageSrc: '/Frontend/drinks/Classic Mojito Mocktail.jpeg',
+        imageAlt: 'Classic Mojito',
+        category: 'drinks',
+        price: 5.50
+    },
+    {
+        id: 'mango-juice',
+        title: 'Mango Juice',
+        description: 'Thick, sweet, and tropical mango puree.',
+        imageSrc: '/Frontend/drinks/Mango juice.jpeg',
+        imageAlt: 'Mango Juice',
+        category: 'drinks',
+        price: 4.00
+    },
+    {
+        id: 'passion-fruit-smoothie',
+        title: 'Passion Fruit Smoothie',
+        description: 'Creamy blend of tart passion fruit and yogurt.',
+        imageSrc: '/Frontend/drinks/Passion Fruit Smoothie.jpeg',
+        imageAlt: 'Passion Fruit Smoothie',
+        category: 'drinks',
+        price: 6.00
+    },
+    {
+        id: 'pineapple-juice',
+        title: 'Pineapple Juice',
+        description: 'Freshly squeezed, tangy, and sweet.',
+        imageSrc: '/Frontend/drinks/Pineapple juice.jpeg',
+        imageAlt: 'Pineapple Juice',
+        category: 'drinks',
+        price: 3.50
+    },
+    {
+        id: 'soft-drinks',
+        title: 'Soft Drinks',
+        description: 'Assorted bottled sodas (Coke, Sprite, Fanta).',
+        imageSrc: '/Frontend/drinks/Soft Drinks.jpeg',
+        imageAlt: 'Soft Drinks',
+        category: 'drinks',
+        price: 2.50
+    },
+    {
+        id: 'tropical-orange',
+        title: 'Tropical Orange',
+        description: 'A blend of orange, mango, and a hint of ginger.',
+        imageSrc: '/Frontend/drinks/Tropical Orange.jpeg',
+        imageAlt: 'Tropical Orange Juice',
+        category: 'drinks',
+        price: 4.50
+    },
+    {
+        id: 'watermelon-juice',
+        title: 'Watermelon Juice',
+        description: 'Cooling, hydrating, and naturally sweet.',
+        imageSrc: '/Frontend/drinks/watermelon juice.jpeg',
+        imageAlt: 'Watermelon Juice',
+        category: 'drinks',
+        price: 3.75
+    },
+];
+
+export const iceCreamMenuData = [
+    {
+        id: 'chocolate-ice-cream',
+        title: 'Chocolate Ice Cream',
+        description: 'Rich, dark, and decadent fudge ice cream, a timeless indulgence.',
+        imageSrc: '/Frontend/ice cream/chocolate ice cream.jpeg',
+        imageAlt: 'A slice of rich chocolate fudge ice cream.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'pineapple-ice-cream',
+        title: 'Pineapple Ice Cream',
+        description: 'A Fruity Dessert That Screams Summer.',
+        imageSrc: '/Frontend/ice cream/Pineapple Ice Cream.jpeg',
+        imageAlt: 'A scoop of Summer.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'coconut-ice-cream',
+        title: 'Coconut Ice Cream',
+        description: 'Subtle coconut flavor with a striking creamy hue.',
+        imageSrc: '/Frontend/ice cream/coconut ice cream.jpeg',
+        imageAlt: 'Coconut just like Mombasa.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'lemon-custard-ice-cream',
+        title: 'Lemon Custard Ice Cream',
+        description: 'Bright, zesty, and refreshing—a tart and sweet citrus delight.',
+        imageSrc: '/Frontend/ice cream/Lemon Custard Ice Cream.jpeg',
+        imageAlt: 'A bright lemon Ice Cream with glaze.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'mango-ice-cream',
+        title: 'Mango Ice Cream',
+        description: 'Moist dessert studded with sweet mango flakes.',
+        imageSrc: '/Frontend/ice cream/mango ice cream.jpeg',
+        imageAlt: 'A mango flavored icecream.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'orange-ice-cream',
+        title: 'Orange Ice Cream',
+        description: 'Orange like the color.',
+        imageSrc: '/Frontend/ice cream/orange ice cream.jpeg',
+        imageAlt: 'orange like the color.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'passion-ice-cream',
+        title: 'Passion Ice Cream',
+        description: 'It\'s the seedy season.',
+        imageSrc: '/Frontend/ice cream/passion ice cream.jpeg',
+        imageAlt: 'passion.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'strawberry-ice-cream',
+        title: 'Strawberry Ice Cream',
+        description: 'Match your tongue\'s energy.',
+        imageSrc: '/Frontend/ice cream/strawberry ice cream.jpeg',
+        imageAlt: 'Rizzard of berries.',
+        category: 'ice-cream',
+        price: null
+    },
+    {
+        id: 'vanilla-ice-cream',
+        title: 'Vanilla Ice Cream',
+        description: 'One of a kind flavor.',
+        imageSrc: '/Frontend/ice cream/vanilla (2).jpeg',
+        imageAlt: 'Try with vanilla yorghut.',
+        category: 'ice-cream',
+        price: null
+    },
+];
+
+export const allMenuData = [...cakeMenuData, ...cookiesData, ...drinksMenuData, ...iceCreamMenuData];